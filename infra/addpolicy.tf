@@ -28,7 +28,8 @@ resource "aws_iam_policy" "fargate-additional-policy" {
     {
       "Action": [
         "ecs:DescribeTasks",
-        "ecs:ListTasks"
+        "ecs:ListTasks",
+        "ecs:StopTask"
       ],
       "Resource": "*",
       "Condition" : { "StringEquals" : { "ecs:cluster" : "${aws_ecs_cluster.ballista_cluster.arn}" }},
@@ -64,21 +65,22 @@ resource "aws_iam_policy" "fargate-additional-policy" {
 EOF
 }
 
-# resource "aws_iam_policy" "lambda-additional-policy" {
-#   name        = "${module.env.module_name}_lambda_access_${var.region_name}_${module.env.stage}"
-#   description = "additional policy for lambda access"
-#   policy = <<EOF
-# {
-#   "Version": "2012-10-17",
-#   "Statement": [
-#     {
-#       "Action": [
-#         "lambda:InvokeFunction"
-#       ],
-#       "Resource": "${module.hbee.lambda_arn}",
-#       "Effect": "Allow"
-#     }
-#   ]
-# }
-# EOF
-# }
\ No newline at end of file
+resource "aws_iam_policy" "lambda-additional-policy" {
+  name        = "${module.env.module_name}_lambda_access_${var.region_name}_${module.env.stage}"
+  description = "additional policy for lambda access"
+  policy = <<EOF
+{
+  "Version": "2012-10-17",
+  "Statement": [
+    {
+      "Action": [
+        "lambda:InvokeFunction",
+        "lambda:GetFunctionConfiguration"
+      ],
+      "Resource": "${module.lambda_executor.lambda_arn}",
+      "Effect": "Allow"
+    }
+  ]
+}
+EOF
+}
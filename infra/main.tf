@@ -36,6 +36,11 @@ variable "git_revision" {
   default     = "unknown"
 }
 
+variable "s3_sync" {
+  description = "Comma separated list of s3://bucket/prefix locations synced by the executors, where S3 tables can be declared"
+  default     = ""
+}
+
 provider "aws" {
   profile = var.profile
   region  = var.region_name
@@ -1,5 +1,10 @@
 ##### BALLISTA #####
 
+locals {
+  # outputs of the Lambda executors on the EFS volume, served by the standalone executor
+  shuffle_dir = "/mnt/data/shuffle"
+}
+
 module "ballista_standalone" {
   source = "./fargate"
 
@@ -15,6 +20,10 @@ module "ballista_standalone" {
   push_image                  = var.push_ballista
   subnets                     = module.vpc.public_subnets
 
+  additional_policies = [
+    aws_iam_policy.s3-additional-policy.arn
+  ]
+
   attach_efs     = true
   file_system_id = aws_efs_file_system.efs_test_data.id
 
@@ -25,6 +34,12 @@ module "ballista_standalone" {
     name  = "AWS_REGION"
     value = var.region_name
     }, {
+    name  = "BALLISTA_S3_SYNC"
+    value = var.s3_sync
+    }, {
+    name  = "BALLISTA_STANDALONE_SHUFFLE_DIR"
+    value = local.shuffle_dir
+    }, {
     name  = "RUST_LOG"
     value = "info"
   }]
@@ -46,7 +61,7 @@ module "ballista_executor" {
   subnets                     = module.vpc.public_subnets
 
   additional_policies = [
-    # aws_iam_policy.s3-additional-policy.arn,
+    aws_iam_policy.s3-additional-policy.arn,
     aws_iam_policy.fargate-additional-policy.arn
     # aws_iam_policy.lambda-additional-policy.arn
   ]
@@ -67,6 +82,9 @@ module "ballista_executor" {
     name  = "BALLISTA_EXECUTOR_SCHEDULER_TASK_DEF_ARN"
     value = module.ballista_standalone.task_definition_arn
     }, {
+    name  = "BALLISTA_S3_SYNC"
+    value = var.s3_sync
+    }, {
     name  = "RUST_LOG"
     value = "info"
   }]
@@ -92,8 +110,8 @@ module "trigger" {
 
   additional_policies = [
     # aws_iam_policy.s3-additional-policy.arn,
-    aws_iam_policy.fargate-additional-policy.arn
-    # aws_iam_policy.lambda-additional-policy.arn
+    aws_iam_policy.fargate-additional-policy.arn,
+    aws_iam_policy.lambda-additional-policy.arn
   ]
 
   environment = {
@@ -105,6 +123,10 @@ module "trigger" {
     BALLISTA_TRIGGER_EXECUTOR_TASK_SG_ID     = module.ballista_executor.task_security_group_id
     BALLISTA_TRIGGER_EXECUTOR_TASK_DEF_ARN   = module.ballista_executor.task_definition_arn
     BALLISTA_TRIGGER_SUBNETS                 = join(",", module.vpc.public_subnets)
+    BALLISTA_TRIGGER_EXECUTOR_LAMBDA_NAME    = module.lambda_executor.lambda_name
+    BALLISTA_TRIGGER_RESULT_CACHE_DIR        = "/mnt/data/result_cache"
+    BALLISTA_S3_SYNC                         = var.s3_sync
+    BALLISTA_TRIGGER_SHUFFLE_DIR             = local.shuffle_dir
   }
 
   # lambda attached to EFS will fail to create if the mount points are not ready
@@ -113,6 +135,38 @@ module "trigger" {
   ]
 }
 
+module "lambda_executor" {
+  source = "./lambda"
+
+  function_base_name = "executor"
+  region_name        = var.region_name
+  filename           = "../rust/target/docker/lambda_executor.zip"
+  memory_size        = 3008
+  # the executor stops a few seconds before the timeout
+  timeout = 300
+
+  in_vpc  = true
+  vpc_id  = module.vpc.vpc_id
+  subnets = module.vpc.public_subnets
+
+  attach_efs     = true
+  file_system_id = aws_efs_file_system.efs_test_data.id
+
+  additional_policies = [
+    aws_iam_policy.s3-additional-policy.arn,
+  ]
+
+  environment = {
+    RUST_LOG         = "info"
+    GIT_REVISION     = var.git_revision
+    BALLISTA_S3_SYNC = var.s3_sync
+  }
+
+  depends_on = [
+    aws_efs_mount_target.alpha,
+  ]
+}
+
 data "archive_file" "copy_function_zip" {
   type        = "zip"
   source_file = "copy-data.py"
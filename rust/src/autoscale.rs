@@ -0,0 +1,322 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ballista_core::serde::protobuf::{task_status, TaskStatus};
+use ballista_scheduler::state::ConfigBackendClient;
+use log::{info, warn};
+use prost::Message;
+use serde::Serialize;
+
+use crate::fargate::FargateCreationClient;
+
+/// Tasks of the unfinished jobs, read from the scheduler state
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ClusterLoad {
+    /// tasks waiting for an executor
+    pub pending_tasks: usize,
+    pub running_tasks: usize,
+}
+
+impl ClusterLoad {
+    pub fn is_idle(&self) -> bool {
+        self.pending_tasks == 0 && self.running_tasks == 0
+    }
+}
+
+#[async_trait]
+pub trait LoadSource: Send + Sync {
+    async fn load(&self) -> Result<ClusterLoad>;
+}
+
+/// Reads the load from the state of the scheduler running in the same process
+pub struct SchedulerLoad {
+    config_backend: Arc<dyn ConfigBackendClient>,
+    namespace: String,
+}
+
+impl SchedulerLoad {
+    pub fn new(config_backend: Arc<dyn ConfigBackendClient>, namespace: String) -> Self {
+        Self {
+            config_backend,
+            namespace,
+        }
+    }
+}
+
+#[async_trait]
+impl LoadSource for SchedulerLoad {
+    async fn load(&self) -> Result<ClusterLoad> {
+        // see `ballista_scheduler::state` for the keys
+        let tasks = self
+            .config_backend
+            .get_from_prefix(&format!("/ballista/{}/tasks", self.namespace))
+            .await?;
+        let mut load = ClusterLoad::default();
+        for (_, value) in tasks {
+            match TaskStatus::decode(value.as_slice())?.status {
+                None => load.pending_tasks += 1,
+                Some(task_status::Status::Running(_)) => load.running_tasks += 1,
+                Some(_) => {}
+            }
+        }
+        Ok(load)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AutoscaleOptions {
+    /// bounds of the number of executor tasks, the standalone executor is not counted
+    pub min_executors: usize,
+    pub max_executors: usize,
+    /// concurrent tasks of each executor
+    pub tasks_per_executor: usize,
+    /// minimum time between two scale ups
+    pub scale_up_cooldown: Duration,
+    /// time without any pending or running task before retiring the executors
+    pub idle_timeout: Duration,
+}
+
+/// Decides the number of executors from the successive loads of the cluster
+pub struct Autoscaler {
+    options: AutoscaleOptions,
+    last_scale_up: Option<Instant>,
+    idle_since: Option<Instant>,
+}
+
+impl Autoscaler {
+    pub fn new(options: AutoscaleOptions) -> Self {
+        Self {
+            options,
+            last_scale_up: None,
+            idle_since: None,
+        }
+    }
+
+    /// Number of executors wanted for the load observed at `now`.
+    /// Executors are only retired once the whole cluster is idle, as the
+    /// scheduler does not tell which executors hold shuffle outputs that are still needed.
+    pub fn target(&mut self, load: &ClusterLoad, current: usize, now: Instant) -> usize {
+        let options = &self.options;
+        if load.is_idle() {
+            let idle_since = *self.idle_since.get_or_insert(now);
+            if now.duration_since(idle_since) >= options.idle_timeout {
+                return options.min_executors;
+            }
+            return self.bounded(current);
+        }
+        self.idle_since = None;
+
+        let demand = load.pending_tasks + load.running_tasks;
+        let tasks_per_executor = options.tasks_per_executor.max(1);
+        let wanted = (demand + tasks_per_executor - 1) / tasks_per_executor;
+        let wanted = wanted.max(options.min_executors).min(options.max_executors);
+        let cooling_down = self
+            .last_scale_up
+            .map_or(false, |last| now.duration_since(last) < options.scale_up_cooldown);
+        if wanted > current && !cooling_down {
+            self.last_scale_up = Some(now);
+            return wanted;
+        }
+        self.bounded(current)
+    }
+
+    fn bounded(&self, executors: usize) -> usize {
+        executors
+            .max(self.options.min_executors)
+            .min(self.options.max_executors)
+    }
+}
+
+/// Where the executor tasks are started
+#[derive(Debug, Clone)]
+pub struct ExecutorTaskSpec {
+    pub task_def_arn: String,
+    pub task_sg_id: String,
+    pub subnets: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScalingStep {
+    pub load: ClusterLoad,
+    pub executors_before: usize,
+    pub executors_after: usize,
+}
+
+/// Applies the decisions of the autoscaler to the executor tasks
+pub struct AutoscaleController {
+    autoscaler: Autoscaler,
+    client: FargateCreationClient,
+    executor_task: ExecutorTaskSpec,
+}
+
+impl AutoscaleController {
+    pub fn new(
+        options: AutoscaleOptions,
+        client: FargateCreationClient,
+        executor_task: ExecutorTaskSpec,
+    ) -> Self {
+        Self {
+            autoscaler: Autoscaler::new(options),
+            client,
+            executor_task,
+        }
+    }
+
+    /// Start or stop executor tasks for the given load
+    pub async fn step(&mut self, load: ClusterLoad, now: Instant) -> Result<ScalingStep> {
+        let task_def_arn = self.executor_task.task_def_arn.clone();
+        let task_arns = self.client.get_existing_tasks(task_def_arn.clone()).await?;
+        let current = task_arns.len();
+        let target = self.autoscaler.target(&load, current, now);
+        if target > current {
+            info!("scaling up from {} to {} executors", current, target);
+            self.client
+                .get_or_provision(
+                    task_def_arn,
+                    self.executor_task.task_sg_id.clone(),
+                    self.executor_task.subnets.clone(),
+                    target,
+                )
+                .await?;
+        } else if target < current {
+            info!("scaling down from {} to {} idle executors", current, target);
+            for task_arn in &task_arns[target..] {
+                self.client
+                    .stop_task(task_arn.clone(), "Idle Ballista executor")
+                    .await?;
+            }
+        }
+        Ok(ScalingStep {
+            load,
+            executors_before: current,
+            executors_after: target,
+        })
+    }
+
+    /// Scale the executors every `interval` until the process exits
+    pub async fn run(mut self, source: impl LoadSource, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let step = match source.load().await {
+                Ok(load) => self.step(load, Instant::now()).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = step {
+                warn!("autoscaling failed: {:?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_ecs::{FakeEcs, FakeEcsDelays};
+
+    fn autoscaler() -> Autoscaler {
+        Autoscaler::new(AutoscaleOptions {
+            min_executors: 1,
+            max_executors: 4,
+            tasks_per_executor: 2,
+            scale_up_cooldown: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(60),
+        })
+    }
+
+    fn load(pending_tasks: usize, running_tasks: usize) -> ClusterLoad {
+        ClusterLoad {
+            pending_tasks,
+            running_tasks,
+        }
+    }
+
+    #[test]
+    fn target_never_exceeds_max() {
+        let start = Instant::now();
+        let mut autoscaler = autoscaler();
+        assert_eq!(autoscaler.target(&load(100, 0), 1, start), 4);
+        let later = start + Duration::from_secs(60);
+        assert_eq!(autoscaler.target(&load(100, 8), 6, later), 4);
+        assert_eq!(autoscaler.target(&load(0, 0), 6, later), 4);
+    }
+
+    #[test]
+    fn no_scale_up_during_cooldown() {
+        let start = Instant::now();
+        let mut autoscaler = autoscaler();
+        assert_eq!(autoscaler.target(&load(4, 0), 1, start), 2);
+        let cooling = start + Duration::from_secs(10);
+        assert_eq!(autoscaler.target(&load(8, 0), 2, cooling), 2);
+        let cooled = start + Duration::from_secs(30);
+        assert_eq!(autoscaler.target(&load(8, 0), 2, cooled), 4);
+    }
+
+    #[test]
+    fn scale_down_to_min_after_idle_timeout() {
+        let start = Instant::now();
+        let mut autoscaler = autoscaler();
+        assert_eq!(autoscaler.target(&load(0, 0), 3, start), 3);
+        let idle = start + Duration::from_secs(59);
+        assert_eq!(autoscaler.target(&load(0, 0), 3, idle), 3);
+        // a task resets the idle period
+        assert_eq!(autoscaler.target(&load(0, 1), 3, idle), 3);
+        let idle = start + Duration::from_secs(60);
+        assert_eq!(autoscaler.target(&load(0, 0), 3, idle), 3);
+        let timeout = idle + Duration::from_secs(60);
+        assert_eq!(autoscaler.target(&load(0, 0), 3, timeout), 1);
+    }
+
+    #[test]
+    fn scale_up_to_min() {
+        let start = Instant::now();
+        let mut autoscaler = Autoscaler::new(AutoscaleOptions {
+            min_executors: 2,
+            ..autoscaler().options
+        });
+        assert_eq!(autoscaler.target(&load(0, 0), 0, start), 2);
+        assert_eq!(autoscaler.target(&load(1, 0), 0, start), 2);
+        // even during the cooldown
+        assert_eq!(autoscaler.target(&load(8, 0), 4, start), 4);
+        assert_eq!(autoscaler.target(&load(1, 0), 1, start), 2);
+    }
+
+    #[tokio::test]
+    async fn controller_scales_the_fake_tasks() {
+        let delays = FakeEcsDelays {
+            api_call: Duration::from_millis(1),
+            provisioning: Duration::from_millis(10),
+            pending: Duration::from_millis(10),
+        };
+        let client = FargateCreationClient::with_api(
+            Arc::new(FakeEcs::new("localhost".to_owned(), delays)),
+            "cluster".to_owned(),
+        );
+        let executor_task = ExecutorTaskSpec {
+            task_def_arn: "executor".to_owned(),
+            task_sg_id: "sg".to_owned(),
+            subnets: vec![],
+        };
+        let mut controller = AutoscaleController::new(autoscaler().options, client, executor_task);
+        let start = Instant::now();
+        let step = controller.step(load(0, 0), start).await.unwrap();
+        assert_eq!((step.executors_before, step.executors_after), (0, 1));
+
+        let step = controller.step(load(100, 0), start).await.unwrap();
+        assert_eq!((step.executors_before, step.executors_after), (1, 4));
+        let busy = start + Duration::from_secs(60);
+        let step = controller.step(load(100, 8), busy).await.unwrap();
+        assert_eq!((step.executors_before, step.executors_after), (4, 4));
+
+        let idle = busy + Duration::from_secs(1);
+        let step = controller.step(load(0, 0), idle).await.unwrap();
+        assert_eq!((step.executors_before, step.executors_after), (4, 4));
+        let timeout = idle + Duration::from_secs(60);
+        let step = controller.step(load(0, 0), timeout).await.unwrap();
+        assert_eq!((step.executors_before, step.executors_after), (4, 1));
+        let step = controller.step(load(0, 0), timeout).await.unwrap();
+        assert_eq!((step.executors_before, step.executors_after), (1, 1));
+    }
+}
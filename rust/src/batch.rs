@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::{ensure, Result};
+use ballista::context::BallistaContext;
+use ballista::prelude::BallistaConfig;
+use futures::StreamExt;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::query::{run_statements, QueryTimings};
+use crate::tpch::get_query;
+
+/// A query of the batch, with the statements run in order
+#[derive(Debug, Clone)]
+pub struct BatchQuery {
+    pub name: String,
+    pub stream: usize,
+    pub statements: Vec<String>,
+}
+
+/// Queries of a batch, as declared in the trigger events
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchSpec {
+    /// TPC-H query numbers
+    #[serde(default)]
+    pub tpch_queries: Vec<u8>,
+    /// SQL queries, each one a single statement
+    #[serde(default)]
+    pub sql: Vec<String>,
+    /// number of times the queries are run, each stream starting at a different
+    /// query. The streams are rotations of the list of queries, not the TPC-H
+    /// throughput test permutations.
+    #[serde(default = "default_one")]
+    pub streams: usize,
+    /// maximum number of streams running at the same time, the queries of a stream
+    /// run one after the other
+    #[serde(default = "default_one")]
+    pub concurrency: usize,
+}
+
+fn default_one() -> usize {
+    1
+}
+
+impl BatchSpec {
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            !self.tpch_queries.is_empty() || !self.sql.is_empty(),
+            "batch should contain at least one query"
+        );
+        for &tpch_query in &self.tpch_queries {
+            ensure!(
+                get_query(tpch_query).is_ok(),
+                "batch tpch_queries should be between 1 and 22, got {}",
+                tpch_query
+            );
+        }
+        ensure!(self.streams >= 1, "batch streams should be at least 1");
+        ensure!(self.concurrency >= 1, "batch concurrency should be at least 1");
+        Ok(())
+    }
+
+    /// The queries of all the streams, stream `i` starting with the `i`-th query
+    pub fn queries(&self) -> Result<Vec<BatchQuery>> {
+        let mut queries = vec![];
+        for &tpch_query in &self.tpch_queries {
+            queries.push((
+                format!("q{}", tpch_query),
+                get_query(tpch_query)?.to_owned(),
+            ));
+        }
+        for (index, sql) in self.sql.iter().enumerate() {
+            queries.push((format!("sql{}", index), sql.clone()));
+        }
+        let mut batch = vec![];
+        for stream in 0..self.streams {
+            for offset in 0..queries.len() {
+                let (name, sql) = &queries[(stream + offset) % queries.len()];
+                batch.push(BatchQuery {
+                    name: name.clone(),
+                    stream,
+                    statements: vec![sql.clone()],
+                });
+            }
+        }
+        Ok(batch)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchQueryResult {
+    pub name: String,
+    pub stream: usize,
+    /// start of the query in ms since the start of the batch
+    pub start_ms: u64,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execution: Option<QueryTimings>,
+    pub row_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchSummary {
+    pub concurrency: usize,
+    pub query_count: usize,
+    pub failed_count: usize,
+    pub duration_ms: u64,
+    /// completed queries per hour
+    pub queries_per_hour: f64,
+    /// in the order of completion
+    pub queries: Vec<BatchQueryResult>,
+}
+
+/// Run the queries against an existing cluster, the queries of each stream one
+/// after the other and at most `concurrency` streams at the same time. A failed
+/// query does not stop the batch.
+pub async fn run_batch(
+    ctx: &BallistaContext,
+    host: &str,
+    port: u16,
+    config: &BallistaConfig,
+    queries: Vec<BatchQuery>,
+    concurrency: usize,
+) -> BatchSummary {
+    let start = Instant::now();
+    let query_count = queries.len();
+    let results = run_streams(queries, concurrency, |query| async move {
+        let start_ms = start.elapsed().as_millis() as u64;
+        let output = run_statements(ctx, host, port, config, &query.statements).await;
+        let duration_ms = start.elapsed().as_millis() as u64 - start_ms;
+        let mut result = BatchQueryResult {
+            name: query.name,
+            stream: query.stream,
+            start_ms,
+            duration_ms,
+            execution: None,
+            row_count: 0,
+            error: None,
+        };
+        match output {
+            Ok(output) => {
+                info!("{} completed in {}ms", result.name, duration_ms);
+                result.row_count = output.batches.iter().map(|b| b.num_rows()).sum();
+                result.execution = Some(output.timings);
+            }
+            Err(err) => {
+                warn!("{} failed: {:?}", result.name, err);
+                result.error = Some(format!("{:#}", err));
+            }
+        }
+        result
+    })
+    .await;
+
+    let duration_ms = start.elapsed().as_millis() as u64;
+    let failed_count = results.iter().filter(|r| r.error.is_some()).count();
+    let completed = (query_count - failed_count) as f64;
+    BatchSummary {
+        concurrency,
+        query_count,
+        failed_count,
+        duration_ms,
+        queries_per_hour: completed * 3_600_000. / duration_ms.max(1) as f64,
+        queries: results,
+    }
+}
+
+/// Run the streams concurrently, at most `concurrency` of them, and the queries of
+/// each stream in order. Returns the results in the order of completion.
+async fn run_streams<F, Fut>(
+    queries: Vec<BatchQuery>,
+    concurrency: usize,
+    run: F,
+) -> Vec<BatchQueryResult>
+where
+    F: Fn(BatchQuery) -> Fut,
+    Fut: Future<Output = BatchQueryResult>,
+{
+    let mut streams: BTreeMap<usize, Vec<BatchQuery>> = BTreeMap::new();
+    for query in queries {
+        streams.entry(query.stream).or_default().push(query);
+    }
+    let completed = Mutex::new(vec![]);
+    futures::stream::iter(streams.into_iter().map(|(_, stream)| stream))
+        .for_each_concurrent(concurrency.max(1), |stream| {
+            let (run, completed) = (&run, &completed);
+            async move {
+                for query in stream {
+                    let result = run(query).await;
+                    completed.lock().unwrap().push(result);
+                }
+            }
+        })
+        .await;
+    completed.into_inner().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(json: &str) -> BatchSpec {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn streams_are_rotations_of_the_queries() {
+        let batch = spec(r#"{"tpch_queries": [1, 6], "sql": ["select 1"], "streams": 3}"#);
+        let queries = batch.queries().unwrap();
+        let names: Vec<_> = queries
+            .iter()
+            .map(|query| format!("{}:{}", query.stream, query.name))
+            .collect();
+        assert_eq!(names[..3], ["0:q1", "0:q6", "0:sql0"]);
+        assert_eq!(names[3..6], ["1:q6", "1:sql0", "1:q1"]);
+        assert_eq!(names[6..], ["2:sql0", "2:q1", "2:q6"]);
+        assert_eq!(queries[2].statements, vec!["select 1"]);
+        assert_eq!(queries[0].statements, vec![get_query(1).unwrap()]);
+    }
+
+    #[test]
+    fn validate_batch() {
+        let valid = |json: &str| spec(json).validate().is_ok();
+        assert!(valid(r#"{"tpch_queries": [1, 22]}"#));
+        assert!(valid(r#"{"sql": ["select 1"], "concurrency": 4}"#));
+        assert!(!valid("{}"));
+        assert!(!valid(r#"{"tpch_queries": [23]}"#));
+        assert!(!valid(r#"{"tpch_queries": [1], "streams": 0}"#));
+        assert!(!valid(r#"{"tpch_queries": [1], "concurrency": 0}"#));
+        assert!(serde_json::from_str::<BatchSpec>(r#"{"queries": [1]}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn queries_of_a_stream_run_in_order() {
+        let batch = spec(r#"{"tpch_queries": [1, 6, 14], "streams": 3, "concurrency": 2}"#);
+        // running queries, by stream
+        let running: Mutex<Vec<usize>> = Mutex::new(vec![]);
+        let max_running = Mutex::new(0);
+        let started = Mutex::new(vec![]);
+        let results = run_streams(batch.queries().unwrap(), batch.concurrency, |query| {
+            let (running, max_running, started) = (&running, &max_running, &started);
+            async move {
+                {
+                    let mut running = running.lock().unwrap();
+                    assert!(!running.contains(&query.stream), "{:?}", query);
+                    running.push(query.stream);
+                    let mut max_running = max_running.lock().unwrap();
+                    *max_running = running.len().max(*max_running);
+                    started
+                        .lock()
+                        .unwrap()
+                        .push(format!("{}:{}", query.stream, query.name));
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                running
+                    .lock()
+                    .unwrap()
+                    .retain(|stream| *stream != query.stream);
+                BatchQueryResult {
+                    name: query.name,
+                    stream: query.stream,
+                    start_ms: 0,
+                    duration_ms: 0,
+                    execution: None,
+                    row_count: 0,
+                    error: None,
+                }
+            }
+        })
+        .await;
+        assert_eq!(results.len(), 9);
+        assert_eq!(*max_running.lock().unwrap(), 2);
+        let started = started.into_inner().unwrap();
+        for (stream, names) in vec![(0, ["q1", "q6", "q14"]), (1, ["q6", "q14", "q1"])] {
+            let prefix = format!("{}:", stream);
+            let order: Vec<_> = started
+                .iter()
+                .filter_map(|name| name.strip_prefix(&prefix))
+                .collect();
+            assert_eq!(order, names);
+        }
+    }
+}
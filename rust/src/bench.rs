@@ -0,0 +1,207 @@
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use ballista::context::BallistaContext;
+use ballista::prelude::BallistaConfig;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::catalog::Catalog;
+use crate::cost::{LaunchType, PriceTable, TaskResources};
+use crate::query::{run_statements, QueryTimings};
+use crate::tpch::{get_query, register_tpch_tables, TpchFormat, TpchSchemaOptions};
+
+/// The 22 TPC-H queries
+pub const ALL_QUERIES: std::ops::RangeInclusive<u8> = 1..=22;
+
+#[derive(Debug, Clone)]
+pub struct BenchmarkOptions {
+    pub queries: Vec<u8>,
+    /// runs of each query that are not measured
+    pub warmup: usize,
+    /// measured runs of each query
+    pub iterations: usize,
+    pub format: TpchFormat,
+    pub schema: TpchSchemaOptions,
+    /// catalog file to register instead of the TPC-H tables of the given format
+    pub catalog: Option<String>,
+    /// size of the cluster, including the standalone executor
+    pub executor_count: usize,
+    /// size of each task of the cluster, to estimate the cost of the runs
+    pub task_resources: TaskResources,
+    pub launch_type: LaunchType,
+    pub prices: PriceTable,
+}
+
+/// One measured run, with the fields of the trigger response plotted by the notebook
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkRun {
+    pub executor_count: usize,
+    pub tpch_query: u8,
+    pub iteration: usize,
+    /// always 0, the cluster already exists
+    pub provisioning_duration_ms: u64,
+    pub execution_duration_ms: u64,
+    pub execution: QueryTimings,
+    pub row_count: usize,
+    /// share of the cost of the cluster during the run
+    pub cost_usd: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryStatistics {
+    pub tpch_query: u8,
+    pub iterations: usize,
+    /// null if no run was measured before the query failed
+    pub min_ms: Option<u64>,
+    pub median_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    /// of all the measured runs
+    pub cost_usd: f64,
+    /// set if the query failed, the other queries are still run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkSummary {
+    pub executor_count: usize,
+    pub format: TpchFormat,
+    pub warmup: usize,
+    pub launch_type: LaunchType,
+    pub task_resources: TaskResources,
+    /// of all the measured runs
+    pub cost_usd: f64,
+    pub queries: Vec<QueryStatistics>,
+    /// all measured runs, `pd.DataFrame(summary["runs"])` in the notebook
+    pub runs: Vec<BenchmarkRun>,
+}
+
+/// Run the query set against an existing cluster
+pub async fn run_benchmark(
+    host: &str,
+    port: u16,
+    options: &BenchmarkOptions,
+) -> Result<BenchmarkSummary> {
+    if options.iterations == 0 {
+        bail!("At least one iteration is required");
+    }
+    let config = BallistaConfig::new()?;
+    let mut ctx = BallistaContext::remote(host, port, &config);
+    match &options.catalog {
+        Some(catalog) => Catalog::from_file(catalog)?.register(&mut ctx).await?,
+        None => register_tpch_tables(&mut ctx, options.format, &options.schema.types())?,
+    }
+
+    let mut summary = BenchmarkSummary {
+        executor_count: options.executor_count,
+        format: options.format,
+        warmup: options.warmup,
+        launch_type: options.launch_type,
+        task_resources: options.task_resources,
+        cost_usd: 0.,
+        queries: vec![],
+        runs: vec![],
+    };
+    for &tpch_query in &options.queries {
+        let statements = vec![get_query(tpch_query)?.to_owned()];
+        let mut durations = vec![];
+        let mut cost_usd = 0.;
+        let mut error = None;
+        for iteration in 0..options.warmup + options.iterations {
+            let start = Instant::now();
+            let output = match run_statements(&ctx, host, port, &config, &statements).await {
+                Ok(output) => output,
+                Err(err) => {
+                    warn!("query {} failed: {:?}", tpch_query, err);
+                    error = Some(format!("{:#}", err));
+                    break;
+                }
+            };
+            let execution_duration_ms = start.elapsed().as_millis() as u64;
+            if iteration < options.warmup {
+                continue;
+            }
+            info!(
+                "query {} iteration {}: {}ms",
+                tpch_query,
+                iteration - options.warmup,
+                execution_duration_ms
+            );
+            durations.push(execution_duration_ms);
+            let run_cost_usd = options.executor_count as f64
+                * options.prices.cost(
+                    options.launch_type,
+                    &options.task_resources,
+                    execution_duration_ms,
+                );
+            cost_usd += run_cost_usd;
+            summary.runs.push(BenchmarkRun {
+                executor_count: options.executor_count,
+                tpch_query,
+                iteration: iteration - options.warmup,
+                provisioning_duration_ms: 0,
+                execution_duration_ms,
+                execution: output.timings,
+                row_count: output.batches.iter().map(|batch| batch.num_rows()).sum(),
+                cost_usd: run_cost_usd,
+            });
+        }
+        durations.sort_unstable();
+        summary.queries.push(QueryStatistics {
+            tpch_query,
+            iterations: durations.len(),
+            min_ms: percentile(&durations, 0.),
+            median_ms: percentile(&durations, 50.),
+            p95_ms: percentile(&durations, 95.),
+            cost_usd,
+            error,
+        });
+        summary.cost_usd += cost_usd;
+    }
+    Ok(summary)
+}
+
+/// Nearest-rank percentile of sorted values, None if there are none
+fn percentile(sorted: &[u64], percent: f64) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent * sorted.len() as f64 / 100.).ceil() as usize;
+    Some(sorted[rank.max(1) - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn percentiles(sorted: &[u64]) -> [Option<u64>; 3] {
+        [
+            percentile(sorted, 0.),
+            percentile(sorted, 50.),
+            percentile(sorted, 95.),
+        ]
+    }
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        assert_eq!(percentiles(&[7]), [Some(7); 3]);
+        assert_eq!(
+            percentiles(&[10, 20, 30, 40, 50]),
+            [Some(10), Some(30), Some(50)]
+        );
+        assert_eq!(
+            percentiles(&[10, 20, 30, 40]),
+            [Some(10), Some(20), Some(40)]
+        );
+        let sorted: Vec<u64> = (1..=20).collect();
+        assert_eq!(percentiles(&sorted), [Some(1), Some(10), Some(19)]);
+        let sorted: Vec<u64> = (1..=21).collect();
+        assert_eq!(percentiles(&sorted), [Some(1), Some(11), Some(20)]);
+    }
+
+    #[test]
+    fn no_percentile_without_runs() {
+        assert_eq!(percentiles(&[]), [None; 3]);
+    }
+}
@@ -0,0 +1,126 @@
+//! Simulate the executor autoscaling on a fake ECS backend with a synthetic load.
+//! Each task runs for one tick, and each executor (plus the standalone one) runs
+//! `tasks_per_executor` tasks at a time. Prints one JSON line per tick.
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use ballista_aws_tools::autoscale::{
+    AutoscaleController, AutoscaleOptions, ClusterLoad, ExecutorTaskSpec,
+};
+use ballista_aws_tools::fake_ecs::{FakeEcs, FakeEcsDelays};
+use ballista_aws_tools::fargate::FargateCreationClient;
+
+/// `tick:tasks`, a job of `tasks` tasks submitted at the given tick
+#[derive(Debug, Clone, Copy)]
+struct Arrival {
+    tick: usize,
+    tasks: usize,
+}
+
+impl FromStr for Arrival {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (tick, tasks) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Arrival should be tick:tasks, got {}", s))?;
+        Ok(Self {
+            tick: tick.parse()?,
+            tasks: tasks.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "autoscale_sim")]
+struct Opt {
+    /// Number of simulated ticks
+    #[structopt(long, default_value = "120")]
+    ticks: usize,
+    /// Simulated duration of a tick, which is the autoscaling interval
+    #[structopt(long, default_value = "5")]
+    interval_sec: u64,
+    /// Comma separated jobs as tick:tasks
+    #[structopt(long, use_delimiter = true, default_value = "0:32,60:96")]
+    arrivals: Vec<Arrival>,
+    #[structopt(long, default_value = "0")]
+    min_executors: usize,
+    #[structopt(long, default_value = "8")]
+    max_executors: usize,
+    #[structopt(long, default_value = "2")]
+    tasks_per_executor: usize,
+    #[structopt(long, default_value = "30")]
+    cooldown_sec: u64,
+    #[structopt(long, default_value = "60")]
+    idle_sec: u64,
+}
+
+#[derive(Serialize)]
+struct Tick {
+    tick: usize,
+    time_sec: u64,
+    #[serde(flatten)]
+    load: ClusterLoad,
+    executors: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+    let delays = FakeEcsDelays {
+        api_call: Duration::from_millis(0),
+        provisioning: Duration::from_millis(0),
+        pending: Duration::from_millis(0),
+    };
+    let client = FargateCreationClient::with_api(
+        Arc::new(FakeEcs::new("localhost".to_owned(), delays)),
+        "simulation".to_owned(),
+    );
+    let options = AutoscaleOptions {
+        min_executors: opt.min_executors,
+        max_executors: opt.max_executors,
+        tasks_per_executor: opt.tasks_per_executor,
+        scale_up_cooldown: Duration::from_secs(opt.cooldown_sec),
+        idle_timeout: Duration::from_secs(opt.idle_sec),
+    };
+    let executor_task = ExecutorTaskSpec {
+        task_def_arn: "executor".to_owned(),
+        task_sg_id: "NA".to_owned(),
+        subnets: vec![],
+    };
+    let mut controller = AutoscaleController::new(options, client, executor_task);
+
+    // the time of the autoscaler is simulated, only the ECS calls take real time
+    let start = Instant::now();
+    let mut load = ClusterLoad::default();
+    for tick in 0..opt.ticks {
+        load.pending_tasks += opt
+            .arrivals
+            .iter()
+            .filter(|arrival| arrival.tick == tick)
+            .map(|arrival| arrival.tasks)
+            .sum::<usize>();
+        let now = start + Duration::from_secs(opt.interval_sec * tick as u64);
+        let step = controller.step(load, now).await?;
+        println!(
+            "{}",
+            serde_json::to_string(&Tick {
+                tick,
+                time_sec: opt.interval_sec * tick as u64,
+                load,
+                executors: step.executors_after,
+            })?
+        );
+        // the running tasks complete and the free slots take pending tasks
+        let capacity = (step.executors_after + 1) * opt.tasks_per_executor;
+        load.running_tasks = load.pending_tasks.min(capacity);
+        load.pending_tasks -= load.running_tasks;
+    }
+    Ok(())
+}
@@ -0,0 +1,231 @@
+//! Run SQL on a running Ballista cluster, from a file, the command line or an interactive prompt.
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ballista::context::BallistaContext;
+use ballista::prelude::BallistaConfig;
+use datafusion::arrow::csv;
+use datafusion::arrow::json::writer::record_batches_to_json_rows;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty;
+use structopt::StructOpt;
+
+use ballista_aws_tools::catalog::Catalog;
+use ballista_aws_tools::fargate::FargateCreationClient;
+use ballista_aws_tools::query::{ends_statement, run_statements, split_statements};
+use ballista_aws_tools::tpch::{register_tpch_tables, TpchFormat, TpchTypes};
+
+#[derive(Debug, Clone, Copy)]
+enum OutputFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            _ => bail!("Unknown output format: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "ballista-aws-cli")]
+struct Opt {
+    /// Host of the scheduler, discovered on ECS if not specified
+    #[structopt(long)]
+    host: Option<String>,
+    /// Port of the scheduler
+    #[structopt(long, default_value = "50050")]
+    port: u16,
+    /// ECS cluster where the scheduler task is discovered
+    #[structopt(long)]
+    cluster_name: Option<String>,
+    /// Task definition of the scheduler (standalone) task to discover
+    #[structopt(long)]
+    task_def_arn: Option<String>,
+    /// Maximum time to wait for the discovered scheduler task to get its IP
+    #[structopt(long, default_value = "30")]
+    discovery_timeout_sec: u64,
+    /// Catalog file with the tables to register
+    #[structopt(long)]
+    catalog: Option<String>,
+    /// Register the TPC-H tables in the given format: tbl or parquet
+    #[structopt(long)]
+    tpch: Option<TpchFormat>,
+    /// SQL statements to run, separated by `;`
+    #[structopt(short, long, conflicts_with = "file")]
+    execute: Option<String>,
+    /// File containing the SQL statements to run
+    #[structopt(short, long)]
+    file: Option<String>,
+    /// Output format of the results: table, csv or json
+    #[structopt(long, default_value = "table")]
+    output: OutputFormat,
+}
+
+/// Find the private IP of the running scheduler task
+async fn discover_scheduler(opt: &Opt) -> Result<String> {
+    let (cluster_name, task_def_arn) = match (&opt.cluster_name, &opt.task_def_arn) {
+        (Some(cluster_name), Some(task_def_arn)) => (cluster_name, task_def_arn),
+        _ => bail!("Either --host or --cluster-name and --task-def-arn are required"),
+    };
+    let client = FargateCreationClient::try_new(cluster_name.clone())
+        .context("AWS_REGION is required to discover the scheduler")?;
+    let task_arns = client.get_existing_tasks(task_def_arn.clone()).await?;
+    let task_arn = task_arns
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No running scheduler task in cluster {}", cluster_name))?;
+    // the task may be stopping or stuck in the provisioning
+    let timeout = Duration::from_secs(opt.discovery_timeout_sec);
+    let provisioning = client.wait_for_provisioning(vec![task_arn.clone()]);
+    let mut ips = match tokio::time::timeout(timeout, provisioning).await {
+        Ok(ips) => ips?,
+        Err(_) => bail!(
+            "Scheduler task {} did not get an IP within {}s",
+            task_arn,
+            opt.discovery_timeout_sec
+        ),
+    };
+    ips.pop()
+        .ok_or_else(|| anyhow!("Scheduler task has no private IP"))
+}
+
+fn print_results(batches: &[RecordBatch], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Table => pretty::print_batches(batches)?,
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::new(io::stdout());
+            for batch in batches {
+                writer.write(batch)?;
+            }
+        }
+        OutputFormat::Json => {
+            for row in record_batches_to_json_rows(batches) {
+                println!("{}", serde_json::to_string(&row)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Session {
+    ctx: BallistaContext,
+    config: BallistaConfig,
+    host: String,
+    port: u16,
+    output: OutputFormat,
+}
+
+impl Session {
+    async fn run(&self, statements: &[String]) -> Result<()> {
+        for statement in statements {
+            let start = Instant::now();
+            let output = run_statements(
+                &self.ctx,
+                &self.host,
+                self.port,
+                &self.config,
+                std::slice::from_ref(statement),
+            )
+            .await?;
+            let row_count: usize = output.batches.iter().map(|batch| batch.num_rows()).sum();
+            print_results(&output.batches, self.output)?;
+            eprintln!("{} rows in {}ms", row_count, start.elapsed().as_millis());
+        }
+        Ok(())
+    }
+
+    /// Read statements terminated by `;` from stdin until EOF or `\q`
+    async fn repl(&self) -> Result<()> {
+        let stdin = io::stdin();
+        let mut buffer = String::new();
+        prompt(&buffer)?;
+        for line in stdin.lock().lines() {
+            let line = line?;
+            if buffer.is_empty() && matches!(line.trim(), "\\q" | "quit" | "exit") {
+                break;
+            }
+            buffer.push_str(&line);
+            buffer.push('\n');
+            if ends_statement(&buffer) {
+                if let Err(err) = self.run(&split_statements(&buffer)).await {
+                    eprintln!("Error: {:#}", err);
+                }
+                buffer.clear();
+            }
+            prompt(&buffer)?;
+        }
+        Ok(())
+    }
+}
+
+fn prompt(buffer: &str) -> Result<()> {
+    if buffer.is_empty() {
+        print!("ballista> ");
+    } else {
+        print!("       -> ");
+    }
+    io::stdout().flush()?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+    let host = match &opt.host {
+        Some(host) => host.clone(),
+        None => discover_scheduler(&opt).await?,
+    };
+    eprintln!("connected to scheduler {}:{}", host, opt.port);
+
+    let config = BallistaConfig::new()?;
+    let mut ctx = BallistaContext::remote(&host, opt.port, &config);
+    if let Some(format) = opt.tpch {
+        register_tpch_tables(&mut ctx, format, &TpchTypes::default())?;
+    }
+    if let Some(catalog) = &opt.catalog {
+        Catalog::from_file(catalog)?.register(&mut ctx).await?;
+    }
+    let session = Session {
+        ctx,
+        config,
+        host,
+        port: opt.port,
+        output: opt.output,
+    };
+
+    match (&opt.execute, &opt.file) {
+        (Some(sql), _) => session.run(&split_statements(sql)).await,
+        (None, Some(file)) => {
+            let script = fs::read_to_string(file)
+                .with_context(|| format!("Could not read SQL file {}", file))?;
+            session.run(&split_statements(&script)).await
+        }
+        (None, None) => session.repl().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn execute_conflicts_with_file() {
+        let parse = |args: &[&str]| Opt::from_iter_safe(args);
+        assert!(parse(&["ballista-aws-cli", "-e", "SELECT 1"]).is_ok());
+        assert!(parse(&["ballista-aws-cli", "-f", "query.sql"]).is_ok());
+        assert!(parse(&["ballista-aws-cli", "-e", "SELECT 1", "-f", "query.sql"]).is_err());
+    }
+}
@@ -1,13 +1,16 @@
 //! Ballista executor binary.
 use std::process::exit;
-use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{bail, Result};
-use log::info;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
 
-use ballista_aws_tools::fargate;
-use ballista_aws_tools::{start_executor, wait_executors};
+use ballista_aws_tools::discovery::{
+    wait_for_scheduler, DiscoveryMethod, DnsDiscovery, EcsDiscovery, FileDiscovery,
+    SchedulerAddress, SchedulerDiscovery, StaticDiscovery,
+};
+use ballista_aws_tools::fargate::FargateCreationClient;
+use ballista_aws_tools::{start_executor, wait_executors, SharedShuffle};
 
 #[macro_use]
 extern crate configure_me;
@@ -16,53 +19,93 @@ include_config!("executor");
 
 const TASK_RECONNECT_SEC: u64 = 10;
 
+/// time to wait for a scheduler started at the same time as the executor
+const DISCOVERY_TIMEOUT_SEC: u64 = 60;
+
+/// The configured discovery. By default, the scheduler host if there is one,
+/// otherwise the scheduler task on ECS.
+fn scheduler_discovery(opt: &Config) -> Result<Box<dyn SchedulerDiscovery>> {
+    let method = match (&opt.discovery, &opt.scheduler_host) {
+        (Some(discovery), _) => discovery.parse()?,
+        (None, Some(_)) => DiscoveryMethod::Static,
+        (None, None) => DiscoveryMethod::Ecs,
+    };
+    let required = |param: &Option<String>, name: &str| {
+        param
+            .clone()
+            .ok_or_else(|| anyhow!("{} is required for the {:?} discovery", name, method))
+    };
+    let discovery: Box<dyn SchedulerDiscovery> = match &method {
+        DiscoveryMethod::Static => Box::new(StaticDiscovery::new(
+            required(&opt.scheduler_host, "scheduler_host")?,
+            opt.scheduler_port,
+        )),
+        DiscoveryMethod::Ecs => Box::new(EcsDiscovery::new(
+            FargateCreationClient::try_new(required(&opt.cluster_name, "cluster_name")?)?,
+            required(&opt.scheduler_task_def_arn, "scheduler_task_def_arn")?,
+            opt.scheduler_port,
+        )),
+        DiscoveryMethod::Dns(name) => Box::new(DnsDiscovery::try_new(
+            name.clone(),
+            Some(opt.scheduler_port),
+        )?),
+        DiscoveryMethod::Srv(name) => Box::new(DnsDiscovery::try_new(name.clone(), None)?),
+        DiscoveryMethod::File(path) => Box::new(FileDiscovery::new(path)),
+    };
+    Ok(discovery)
+}
+
+/// Shut down once the scheduler is not found anymore, or was replaced by another one
+async fn watch_scheduler(discovery: Box<dyn SchedulerDiscovery>, scheduler: SchedulerAddress) {
+    let mut interval = tokio::time::interval(Duration::from_secs(TASK_RECONNECT_SEC));
+    loop {
+        interval.tick().await;
+        match discovery.locate().await {
+            Ok(Some(address)) if address == scheduler => {}
+            Ok(_) => {
+                info!("Shutting down after scheduler lost");
+                exit(0);
+            }
+            Err(err) => warn!("could not check that the scheduler is running: {:#}", err),
+        }
+    }
+}
+
 pub async fn executor() -> Result<()> {
     let (opt, _remaining_args) =
         config::Config::including_optional_config_files(&["/etc/ballista/executor.toml"])
             .unwrap_or_exit();
 
-    // if no host is specified in conf, assume we are runnin in Fargate
-    let scheduler_host = match &opt.scheduler_host {
-        Some(host) => host.clone(),
-        None => {
-            let client = fargate::FargateCreationClient::try_new(opt.cluster_name)?;
-            let task_def_arn_ref = Arc::new(opt.scheduler_task_def_arn);
-            let task_arn = client
-                .get_existing_tasks(String::clone(&task_def_arn_ref))
-                .await?;
-            if task_arn.len() == 0 {
-                bail!("Scheduler task not found");
-            }
-            let host = client.wait_for_provisioning(task_arn).await?;
-            // poll fargate to check that the scheduler is still there, otherwise shutdown
-            tokio::spawn(async move {
-                let mut interval = tokio::time::interval(Duration::from_secs(TASK_RECONNECT_SEC));
-                loop {
-                    interval.tick().await;
-                    // TODO ping scheduler instead of using Fargate API
-                    let scheduler_tasks = client
-                        .get_existing_tasks(String::clone(&task_def_arn_ref))
-                        .await
-                        .expect("Could not get existing tasks in keepalive ticker");
-                    if scheduler_tasks.len() == 0 {
-                        info!("Shutting down after scheduler lost");
-                        exit(0);
-                    }
-                }
-            });
-            String::from(&host[0])
-        }
-    };
+    let discovery = scheduler_discovery(&opt)?;
+    let scheduler = wait_for_scheduler(
+        discovery.as_ref(),
+        Duration::from_secs(DISCOVERY_TIMEOUT_SEC),
+    )
+    .await?;
+    info!("scheduler found at {}:{}", scheduler.host, scheduler.port);
+    tokio::spawn(watch_scheduler(discovery, scheduler.clone()));
+    let scheduler_host = scheduler.host;
+    let scheduler_port = scheduler.port;
 
     let bind_host = opt.bind_host;
     let bind_port = opt.bind_port;
-    let scheduler_port = opt.scheduler_port;
     let concurrent_tasks = opt.concurrent_tasks as usize;
 
     // should wait for the scheduler to be ready (up with 0 executor) before starting.
     wait_executors(&scheduler_host, scheduler_port, 0).await?;
 
-    start_executor(bind_host, bind_port, scheduler_host, scheduler_port, None, concurrent_tasks).await
+    let shared_shuffle = opt.shuffle_dir.map(|dir| SharedShuffle { dir });
+
+    start_executor(
+        bind_host,
+        bind_port,
+        scheduler_host,
+        scheduler_port,
+        None,
+        concurrent_tasks,
+        shared_shuffle,
+    )
+    .await
 }
 
 #[tokio::main]
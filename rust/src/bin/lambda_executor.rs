@@ -0,0 +1,61 @@
+//! Ballista executor running inside a Lambda invocation.
+//! It registers with the scheduler of the event and stops before the Lambda deadline.
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use lambda_runtime::{handler_fn, Context, Error};
+use log::info;
+use serde::Serialize;
+use serde_json::Value;
+
+use ballista_aws_tools::lambda::LambdaExecutorEvent;
+use ballista_aws_tools::start_executor;
+
+const BIND_PORT: u16 = 50051;
+
+/// Time left to the invocation to return before the Lambda deadline
+const DEADLINE_MARGIN_MS: u64 = 5000;
+
+#[derive(Serialize)]
+struct LambdaExecutorResponse {
+    pub running_duration_ms: u64,
+}
+
+async fn run_executor(event: Value, context: Context) -> Result<LambdaExecutorResponse, Error> {
+    let start = Instant::now();
+    let event: LambdaExecutorEvent =
+        serde_json::from_value(event).map_err(|e| anyhow!("Invalid event: {}", e))?;
+    let now_ms = chrono::Utc::now().timestamp_millis() as u64;
+    let remaining_ms = context.deadline.saturating_sub(now_ms + DEADLINE_MARGIN_MS);
+    info!(
+        "running executor for scheduler {}:{} during {}ms",
+        event.scheduler_host, event.scheduler_port, remaining_ms
+    );
+
+    // the scheduler registers the executor with the IP the poll requests come from
+    let executor = start_executor(
+        "0.0.0.0".to_owned(),
+        BIND_PORT,
+        event.scheduler_host,
+        event.scheduler_port,
+        None,
+        event.concurrent_tasks,
+        event.shuffle,
+    );
+    tokio::select! {
+        res = executor => res?,
+        _ = tokio::time::sleep(Duration::from_millis(remaining_ms)) => {
+            info!("stopping executor before the Lambda deadline");
+        }
+    }
+    Ok(LambdaExecutorResponse {
+        running_duration_ms: start.elapsed().as_millis() as u64,
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    env_logger::init();
+    lambda_runtime::run(handler_fn(run_executor)).await?;
+    Ok(())
+}
@@ -2,17 +2,37 @@
 
 use std::convert::Infallible;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 use std::{net::SocketAddr, sync::Arc};
 
-use ballista_aws_tools::fargate::get_fargate_task_external_host;
-use ballista_aws_tools::{shutdown_ticker, start_executor};
+use ballista_aws_tools::autoscale::{
+    AutoscaleController, AutoscaleOptions, ExecutorTaskSpec, SchedulerLoad,
+};
+use ballista_aws_tools::cancel::cancel_job;
+use ballista_aws_tools::catalog::Catalog;
+use ballista_aws_tools::discovery::{keep_registered, SchedulerAddress};
+use ballista_aws_tools::fargate::{get_fargate_task_external_host, FargateCreationClient};
+use ballista_aws_tools::flight_sql::FlightSqlService;
+use ballista_aws_tools::rest::{self, RestApi};
+use ballista_aws_tools::telemetry::{extract_http_headers, init_tracing};
+use ballista_aws_tools::tpch::{register_tpch_tables, TpchFormat, TpchTypes, TABLES};
+use ballista_aws_tools::{shutdown_ticker, start_executor, SharedShuffle};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use arrow_flight::flight_service_server::FlightServiceServer;
+use ballista::context::BallistaContext;
+use ballista::prelude::BallistaConfig;
 use futures::future::{self, Either, TryFutureExt};
-use hyper::{server::conn::AddrStream, service::make_service_fn, Server};
-use log::info;
+use hyper::{
+    server::conn::AddrStream, service::make_service_fn, Body, Method, Response, Server,
+    StatusCode,
+};
+use log::{info, warn};
 use tonic::transport::Server as TonicServer;
 use tower::Service;
+use tracing::{info_span, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use warp::Filter;
 
 use ballista_core::serde::protobuf::scheduler_grpc_server::SchedulerGrpcServer;
 use ballista_core::BALLISTA_VERSION;
@@ -25,18 +45,67 @@ extern crate configure_me;
 
 include_config!("standalone");
 
+/// prefix of the paths of the Flight (SQL) gRPC methods
+const FLIGHT_SERVICE_PATH: &str = "/arrow.flight.protocol.FlightService/";
+
+/// root of the paths of the REST query API, served by warp
+const REST_PATH: &str = "/query";
+
+/// interval between two autoscaling decisions
+const AUTOSCALE_INTERVAL_SEC: u64 = 5;
+
+/// `/query` and the paths below it, but not the ones that only share the prefix
+fn is_rest_path(path: &str) -> bool {
+    path.strip_prefix(REST_PATH)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The job id of `POST /jobs/{job_id}/cancel` requests, which extend the
+/// scheduler API with job cancellation
+fn cancel_job_id(req: &hyper::Request<hyper::Body>) -> Option<String> {
+    if req.method() != Method::POST {
+        return None;
+    }
+    match req.uri().path().split('/').collect::<Vec<_>>().as_slice() {
+        ["", "jobs", job_id, "cancel"] if !job_id.is_empty() => Some((*job_id).to_owned()),
+        _ => None,
+    }
+}
+
+async fn cancel_response(
+    config_backend: Arc<dyn ConfigBackendClient>,
+    namespace: String,
+    job_id: String,
+) -> Response<Body> {
+    let (status, body) = match cancel_job(config_backend.as_ref(), &namespace, &job_id).await {
+        Ok(Some(outcome)) => (
+            StatusCode::OK,
+            serde_json::to_string(&outcome).unwrap_or_default(),
+        ),
+        Ok(None) => (StatusCode::NOT_FOUND, format!("Unknown job {}", job_id)),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", err)),
+    };
+    Response::builder()
+        .status(status)
+        .body(Body::from(body))
+        .expect("Could not build cancel response")
+}
+
 async fn start_scheduler_server(
     config_backend: Arc<dyn ConfigBackendClient>,
     namespace: String,
     addr: SocketAddr,
     task_expiration_sec: i64,
+    flight_sql: Option<Arc<FlightSqlService>>,
+    rest_api: Option<Arc<RestApi>>,
 ) -> Result<()> {
     info!(
         "Ballista v{} Scheduler listening on {:?}",
         BALLISTA_VERSION, addr
     );
 
-    let last_query_time = shutdown_ticker(task_expiration_sec);
+    let last_query_time =
+        shutdown_ticker(task_expiration_sec, config_backend.clone(), namespace.clone());
 
     Ok(Server::bind(&addr)
         .serve(make_service_fn(move |request: &AddrStream| {
@@ -49,24 +118,51 @@ async fn start_scheduler_server(
 
             let mut tonic = TonicServer::builder()
                 .add_service(scheduler_grpc_server)
+                .add_optional_service(flight_sql.clone().map(FlightServiceServer::from_arc))
                 .into_service();
-            let mut warp = warp::service(get_routes(scheduler_server));
+            let rest_routes = match &rest_api {
+                Some(rest_api) => rest::routes(rest_api.clone()),
+                None => rest::unavailable_routes(),
+            };
+            let mut warp = warp::service(get_routes(scheduler_server).or(rest_routes));
 
             let last_query_time = Arc::clone(&last_query_time);
+            let config_backend = config_backend.clone();
+            let namespace = namespace.clone();
 
             future::ok::<_, Infallible>(tower::service_fn(
                 move |req: hyper::Request<hyper::Body>| {
+                    let span = info_span!("scheduler_request", path = %req.uri().path());
+                    span.set_parent(extract_http_headers(req.headers()));
                     let lifetime_header = req.headers().get("x-lifetime");
-                    if lifetime_header.is_some() && lifetime_header.unwrap().eq("extend") {
+                    // Flight SQL and REST clients are not aware of the lifetime header
+                    let is_flight_sql = req.uri().path().starts_with(FLIGHT_SERVICE_PATH);
+                    let is_rest = is_rest_path(req.uri().path());
+                    if is_flight_sql
+                        || is_rest
+                        || lifetime_header.is_some() && lifetime_header.unwrap().eq("extend")
+                    {
                         last_query_time.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
                     }
+                    if let Some(job_id) = cancel_job_id(&req) {
+                        let config_backend = config_backend.clone();
+                        let namespace = namespace.clone();
+                        return Either::Left(Either::Left(async move {
+                            let res = cancel_response(config_backend, namespace, job_id).await;
+                            Ok::<_, Error>(res.map(EitherBody::Left))
+                        }))
+                        .instrument(span);
+                    }
                     let accept_header = req.headers().get(hyper::header::ACCEPT);
-                    if accept_header.is_some() && accept_header.unwrap().eq("application/json") {
-                        return Either::Left(
+                    if is_rest
+                        || accept_header.is_some() && accept_header.unwrap().eq("application/json")
+                    {
+                        return Either::Left(Either::Right(
                             warp.call(req)
                                 .map_ok(|res| res.map(EitherBody::Left))
                                 .map_err(Error::from),
-                        );
+                        ))
+                        .instrument(span);
                     }
                     Either::Right(
                         tonic
@@ -74,6 +170,7 @@ async fn start_scheduler_server(
                             .map_ok(|res| res.map(EitherBody::Right))
                             .map_err(Error::from),
                     )
+                    .instrument(span)
                 },
             ))
         }))
@@ -90,26 +187,121 @@ async fn scheduler(opt: &Config) -> Result<()> {
     let addr = format!("{}:{}", bind_host, port);
     let addr = addr.parse()?;
 
-    let client = Arc::new(
+    let client: Arc<dyn ConfigBackendClient> = Arc::new(
         StandaloneClient::try_new_temporary()
             .context("Could not create standalone config backend")?,
     );
-    start_scheduler_server(client, namespace, addr, task_expiration_sec).await?;
+    if let Some(path) = &opt.registration_file {
+        let address = SchedulerAddress {
+            host: external_host(opt).await?,
+            port,
+        };
+        tokio::spawn(keep_registered(path.clone(), address));
+    }
+    if let Some(controller) = autoscale_controller(opt)? {
+        let source = SchedulerLoad::new(Arc::clone(&client), namespace.clone());
+        tokio::spawn(controller.run(source, Duration::from_secs(AUTOSCALE_INTERVAL_SEC)));
+    }
+    // the scheduler is still useful to the regular clients without the catalog
+    let (flight_sql, rest_api) = match query_context(opt).await {
+        Ok((ctx, config, tables)) => {
+            let ctx = Arc::new(ctx);
+            let host = "localhost".to_owned();
+            let flight_sql =
+                FlightSqlService::new(ctx.clone(), config.clone(), host.clone(), port, tables);
+            let rest_api = RestApi::new(ctx, config, host, port);
+            (Some(Arc::new(flight_sql)), Some(Arc::new(rest_api)))
+        }
+        Err(err) => {
+            warn!(
+                "Flight SQL and REST endpoints disabled, could not register the tables: {:?}",
+                err
+            );
+            (None, None)
+        }
+    };
+    start_scheduler_server(
+        client,
+        namespace,
+        addr,
+        task_expiration_sec,
+        flight_sql,
+        rest_api,
+    )
+    .await?;
     Ok(())
 }
 
+/// Context of the queries received by the scheduler through Flight SQL or HTTP,
+/// with the tables of the catalog, or the TPC-H tables if none is configured
+async fn query_context(opt: &Config) -> Result<(BallistaContext, BallistaConfig, Vec<String>)> {
+    let config = BallistaConfig::new()?;
+    let mut ctx = BallistaContext::remote("localhost", opt.scheduler_bind_port, &config);
+    let tables = match &opt.flight_sql_catalog {
+        Some(path) => {
+            let catalog = Catalog::from_file(path)?;
+            catalog.register(&mut ctx).await?;
+            catalog.tables.iter().map(|table| table.name.clone()).collect()
+        }
+        None => {
+            register_tpch_tables(&mut ctx, TpchFormat::Tbl, &TpchTypes::default())?;
+            TABLES.iter().map(|table| table.to_string()).collect()
+        }
+    };
+    Ok((ctx, config, tables))
+}
+
+/// The controller of the executor tasks, if autoscaling is enabled
+fn autoscale_controller(opt: &Config) -> Result<Option<AutoscaleController>> {
+    if opt.autoscale_max_executors == 0 {
+        return Ok(None);
+    }
+    let required = |param: &Option<String>, name: &str| {
+        param
+            .clone()
+            .ok_or_else(|| anyhow!("{} is required for autoscaling", name))
+    };
+    let client = FargateCreationClient::try_new(required(&opt.cluster_name, "cluster_name")?)?;
+    let executor_task = ExecutorTaskSpec {
+        task_def_arn: required(&opt.executor_task_def_arn, "executor_task_def_arn")?,
+        task_sg_id: required(&opt.executor_task_sg_id, "executor_task_sg_id")?,
+        subnets: required(&opt.subnets, "subnets")?
+            .split(',')
+            .map(|s| s.to_owned())
+            .collect(),
+    };
+    let options = AutoscaleOptions {
+        min_executors: opt.autoscale_min_executors as usize,
+        max_executors: opt.autoscale_max_executors as usize,
+        tasks_per_executor: opt.autoscale_tasks_per_executor as usize,
+        scale_up_cooldown: Duration::from_secs(opt.autoscale_cooldown_sec),
+        idle_timeout: Duration::from_secs(opt.autoscale_idle_sec),
+    };
+    info!("autoscaling executors with {:?}", options);
+    Ok(Some(AutoscaleController::new(options, client, executor_task)))
+}
+
+/// Host of the task as seen by the executors
+async fn external_host(opt: &Config) -> Result<String> {
+    // if no host is specified in conf, assume we are runnin in Fargate
+    match &opt.executor_external_host {
+        Some(host) => Ok(host.clone()),
+        None => get_fargate_task_external_host().await,
+    }
+}
+
 pub async fn executor(opt: &Config) -> Result<()> {
     let bind_host = opt.bind_host.clone();
-    // if no host is specified in conf, assume we are runnin in Fargate
-    let external_host = match &opt.executor_external_host {
-        Some(host) => host.clone(),
-        None => get_fargate_task_external_host().await?,
-    };
+    let external_host = external_host(opt).await?;
     let bind_port = opt.executor_bind_port;
     let scheduler_host = "localhost".to_owned();
     let scheduler_port = opt.scheduler_bind_port;
     let concurrent_tasks = opt.concurrent_tasks as usize;
 
+    // this executor serves the outputs of all the executors sharing the directory
+    // to the clients that do not mount it
+    let shared_shuffle = opt.shuffle_dir.clone().map(|dir| SharedShuffle { dir });
+
     start_executor(
         bind_host,
         bind_port,
@@ -117,6 +309,7 @@ pub async fn executor(opt: &Config) -> Result<()> {
         scheduler_port,
         Some(external_host),
         concurrent_tasks,
+        shared_shuffle,
     )
     .await
 }
@@ -129,6 +322,7 @@ async fn main() -> Result<()> {
             .unwrap_or_exit();
 
     env_logger::init();
+    init_tracing("ballista-standalone", &opt.trace_exporter)?;
     tokio::select! {
         res = scheduler(&opt) => {
             info!("scheduler stopped: {:?}", res);
@@ -139,3 +333,18 @@ async fn main() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rest_paths() {
+        assert!(is_rest_path("/query"));
+        assert!(is_rest_path("/query/"));
+        assert!(is_rest_path("/query/1234/result"));
+        assert!(!is_rest_path("/queryx"));
+        assert!(!is_rest_path("/jobs/1234/cancel"));
+        assert!(!is_rest_path("/"));
+    }
+}
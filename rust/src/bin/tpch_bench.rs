@@ -0,0 +1,111 @@
+//! Benchmark the TPC-H queries against an existing Ballista cluster.
+use std::fs::File;
+
+use anyhow::Result;
+use structopt::StructOpt;
+
+use ballista_aws_tools::bench::{run_benchmark, BenchmarkOptions, ALL_QUERIES};
+use ballista_aws_tools::cost::{LaunchType, PriceTable, TaskResources};
+use ballista_aws_tools::tpch::{TpchFormat, TpchSchemaOptions};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "tpch_bench")]
+struct Opt {
+    /// Host of the scheduler
+    #[structopt(long, default_value = "localhost")]
+    host: String,
+    /// Port of the scheduler
+    #[structopt(long, default_value = "50050")]
+    port: u16,
+    /// Comma separated query numbers, all 22 queries if not specified
+    #[structopt(long, use_delimiter = true)]
+    queries: Vec<u8>,
+    /// Unmeasured runs of each query
+    #[structopt(long, default_value = "1")]
+    warmup: usize,
+    /// Measured runs of each query
+    #[structopt(long, default_value = "3")]
+    iterations: usize,
+    /// Storage format of the TPC-H tables: tbl or parquet
+    #[structopt(long, default_value = "tbl")]
+    format: TpchFormat,
+    /// Scale factor of the dataset, Int32 keys are used up to SF 300, Int64 keys if not set
+    #[structopt(long)]
+    scale_factor: Option<f64>,
+    /// Catalog file registered instead of the TPC-H tables
+    #[structopt(long)]
+    catalog: Option<String>,
+    /// Number of executors of the cluster, including the standalone one
+    #[structopt(long, default_value = "1")]
+    executor_count: usize,
+    /// vCPU of each task of the cluster, to estimate the cost of the runs
+    #[structopt(long, default_value = "2")]
+    task_vcpu: f64,
+    /// Memory (GB) of each task of the cluster
+    #[structopt(long, default_value = "4")]
+    task_memory_gb: f64,
+    /// Tasks of the cluster running on Fargate Spot
+    #[structopt(long)]
+    spot: bool,
+    /// TOML or JSON price table, the us-east-1 prices if not specified
+    #[structopt(long)]
+    price_table: Option<String>,
+    /// File where the JSON summary is written, printed if not specified
+    #[structopt(long)]
+    output: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+    let queries = if opt.queries.is_empty() {
+        ALL_QUERIES.collect()
+    } else {
+        opt.queries
+    };
+    let options = BenchmarkOptions {
+        queries,
+        warmup: opt.warmup,
+        iterations: opt.iterations,
+        format: opt.format,
+        schema: TpchSchemaOptions {
+            scale_factor: opt.scale_factor,
+        },
+        catalog: opt.catalog,
+        executor_count: opt.executor_count,
+        task_resources: TaskResources {
+            vcpu: opt.task_vcpu,
+            memory_gb: opt.task_memory_gb,
+        },
+        launch_type: if opt.spot {
+            LaunchType::Spot
+        } else {
+            LaunchType::OnDemand
+        },
+        prices: PriceTable::load(opt.price_table.as_deref())?,
+    };
+    let summary = run_benchmark(&opt.host, opt.port, &options).await?;
+    for query in &summary.queries {
+        // a query that failed may have measured runs before
+        if let (Some(min), Some(median), Some(p95)) = (query.min_ms, query.median_ms, query.p95_ms)
+        {
+            eprintln!(
+                "q{}: min {}ms, median {}ms, p95 {}ms, ${:.4}",
+                query.tpch_query, min, median, p95, query.cost_usd
+            );
+        }
+        if let Some(error) = &query.error {
+            eprintln!("q{}: failed: {}", query.tpch_query, error);
+        }
+    }
+    eprintln!(
+        "estimated cost of the measured runs: ${:.4}",
+        summary.cost_usd
+    );
+    match &opt.output {
+        Some(path) => serde_json::to_writer_pretty(File::create(path)?, &summary)?,
+        None => println!("{}", serde_json::to_string_pretty(&summary)?),
+    }
+    Ok(())
+}
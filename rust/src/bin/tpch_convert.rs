@@ -0,0 +1,46 @@
+//! Convert the TPC-H `.tbl` dataset to partitioned Parquet files.
+use anyhow::Result;
+use structopt::StructOpt;
+
+use ballista_aws_tools::tpch::{
+    convert_tbl_to_parquet, parse_compression, ParquetConversionOptions, TpchSchemaOptions,
+};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "tpch_convert")]
+struct Opt {
+    /// Directory containing the `.tbl` files or directories
+    #[structopt(long)]
+    input: String,
+    /// Directory where the Parquet tables are written
+    #[structopt(long)]
+    output: String,
+    /// Maximum number of rows per row group
+    #[structopt(long, default_value = "1048576")]
+    row_group_size: usize,
+    /// Compression codec: uncompressed, snappy, gzip, lzo, brotli, lz4 or zstd
+    #[structopt(long, default_value = "snappy")]
+    compression: String,
+    /// Number of Parquet files per table
+    #[structopt(long, default_value = "8")]
+    partitions: usize,
+    /// Scale factor of the dataset, Int32 keys are used up to SF 300, Int64 keys if not set
+    #[structopt(long)]
+    scale_factor: Option<f64>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+    let options = ParquetConversionOptions {
+        row_group_size: opt.row_group_size,
+        compression: parse_compression(&opt.compression)?,
+        partitions: opt.partitions,
+        types: TpchSchemaOptions {
+            scale_factor: opt.scale_factor,
+        }
+        .types(),
+    };
+    convert_tbl_to_parquet(&opt.input, &opt.output, &options).await
+}
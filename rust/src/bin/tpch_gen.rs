@@ -0,0 +1,45 @@
+//! Generate the TPC-H dataset as `.tbl` files split in partitions (memsql layout).
+use anyhow::{ensure, Result};
+use structopt::StructOpt;
+
+use ballista_aws_tools::tpchgen::generate_tables;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "tpch_gen")]
+struct Opt {
+    /// TPC-H scale factor, 1 is about 1GB of data
+    #[structopt(long, default_value = "1")]
+    scale_factor: f64,
+    /// Seed of the random generator, the same seed always generates the same data
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+    /// Number of files per table, except for nation and region
+    #[structopt(long, default_value = "8", parse(try_from_str = at_least_one))]
+    files: usize,
+    /// Directory where the `{table}/{partition}.tbl` files are written
+    #[structopt(long)]
+    output: String,
+    /// Number of files generated concurrently
+    #[structopt(long, default_value = "4", parse(try_from_str = at_least_one))]
+    parallelism: usize,
+}
+
+fn at_least_one(s: &str) -> Result<usize> {
+    let value = s.parse()?;
+    ensure!(value > 0, "should be at least 1");
+    Ok(value)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+    generate_tables(
+        opt.scale_factor,
+        opt.seed,
+        opt.files,
+        &opt.output,
+        opt.parallelism,
+    )
+    .await
+}
@@ -1,107 +1,1166 @@
-use std::time::Instant;
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, ensure, Result};
 use ballista::prelude::BallistaConfig;
-use log::{debug, info};
+use log::{info, warn};
 
-use ballista_aws_tools::tpch::{register_memsql_tpch_tables, get_query};
-use ballista_aws_tools::{fargate, wait_executors};
+use ballista_aws_tools::batch::{run_batch, BatchSpec, BatchSummary};
+use ballista_aws_tools::cancel::CancelOutcome;
+use ballista_aws_tools::fake_ecs::{FakeEcs, FakeEcsDelays};
+use ballista_aws_tools::fargate::{FargateCreationClient, ProvisionedTask, TaskTimings};
+use ballista_aws_tools::catalog::{register_tables, Catalog, TableDefinition};
+use ballista_aws_tools::cost::{CostEstimate, LaunchType, PriceTable};
+use ballista_aws_tools::lambda::{LambdaExecutorClient, LambdaExecutorEvent};
+use ballista_aws_tools::query::{
+    bind_params, fetch_results, run_statements, submit_statements, JobClient, JobState,
+    QueryTimings, StatementsOutput,
+};
+use ballista_aws_tools::result_cache::{
+    data_version, CacheKey, FileVersion, ResultCache, TableSource,
+};
+use ballista_aws_tools::results::{export_results, QueryResult, ResultFormat, ResultOptions};
+use ballista_aws_tools::telemetry::{flush_traces, init_tracing};
+use ballista_aws_tools::tpch::{
+    get_query, register_tpch_tables, tpch_table_path, TpchFormat, TpchSchemaOptions, TABLES,
+};
+use ballista_aws_tools::verify::{validate_tpch_query, ValidationOptions, ValidationReport};
+use ballista_aws_tools::{wait_executors, ExecutorRegistrationTiming, SharedShuffle};
 
 use ballista::context::BallistaContext;
-use datafusion::arrow::util::pretty;
+use ballista_core::serde::protobuf::job_status;
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
 use lambda_runtime::{handler_fn, Context, Error};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tokio::time::timeout;
+use tracing::instrument;
+use uuid::Uuid;
 
 #[macro_use]
 extern crate configure_me;
 
 include_config!("trigger");
 
-async fn query_ballista(host: &str, port: u16, tpch_query: u8) -> Result<()> {
-    let mut ctx = BallistaContext::remote(host, port, &BallistaConfig::new()?);
-    register_memsql_tpch_tables(&mut ctx)?;
-    let sql = get_query(tpch_query)?;
-    // run benchmark
-    debug!("Running benchmark with query: {}", sql);
-    
-    let df = ctx.sql(&sql)?;
-    debug!("plan: {:?}", &df.to_logical_plan());
-    let batches = df.collect().await?;
-    pretty::print_batches(&batches)?;
+/// Lambda responses are limited to 6MB
+const LAMBDA_MAX_RESPONSE_BYTES: usize = 6 * 1024 * 1024;
 
-    Ok(())
+/// Create a context with the tables of the query registered
+async fn query_context(
+    host: &str,
+    port: u16,
+    catalog: Option<&str>,
+    query: &TriggerQuery,
+) -> Result<(BallistaConfig, BallistaContext)> {
+    let config = BallistaConfig::new()?;
+    let mut ctx = BallistaContext::remote(host, port, &config);
+    match catalog {
+        _ if !query.tables.is_empty() => register_tables(&mut ctx, &query.tables).await?,
+        Some(catalog) => Catalog::from_file(catalog)?.register(&mut ctx).await?,
+        None => register_tpch_tables(&mut ctx, query.format, &query.schema.types())?,
+    }
+    Ok((config, ctx))
 }
 
-pub async fn start_trigger(executor_count: usize, tpch_query: u8) -> Result<(u64, u64)> {
-    // parse options
-    let (opt, _remaining_args) =
-        config::Config::including_optional_config_files(&["/etc/ballista/standalone.toml"])
-            .unwrap_or_exit();
+/// The tables registered by `query_context`
+fn table_sources(catalog: Option<&str>, query: &TriggerQuery) -> Result<Vec<TableSource>> {
+    let tables = match catalog {
+        _ if !query.tables.is_empty() => query.tables.clone(),
+        Some(catalog) => Catalog::from_file(catalog)?.tables,
+        None => {
+            let options = format!("{:?} {:?}", query.format, query.schema.types());
+            return Ok(TABLES
+                .iter()
+                .map(|table| TableSource {
+                    name: table.to_string(),
+                    path: tpch_table_path(table, query.format),
+                    options: options.clone(),
+                })
+                .collect());
+        }
+    };
+    tables
+        .into_iter()
+        .map(|table| {
+            let options = serde_json::to_string(&table)?;
+            Ok(TableSource {
+                name: table.name,
+                path: table.path,
+                options,
+            })
+        })
+        .collect()
+}
+
+/// The statements of the query, with their parameters bound
+fn query_statements(query: &TriggerQuery) -> Result<Vec<String>> {
+    match &query.sql {
+        Some(sql) => sql
+            .statements()
+            .iter()
+            .map(|statement| bind_params(statement, &query.params))
+            .collect(),
+        None => Ok(vec![get_query(query.tpch_query)?.to_owned()]),
+    }
+}
+
+/// Register the tables of the query and build its statements
+async fn prepare_query(
+    host: &str,
+    port: u16,
+    catalog: Option<&str>,
+    query: &TriggerQuery,
+) -> Result<(BallistaConfig, BallistaContext, Vec<String>)> {
+    let (config, ctx) = query_context(host, port, catalog, query).await?;
+    Ok((config, ctx, query_statements(query)?))
+}
+
+#[instrument(skip(query))]
+async fn query_ballista(
+    host: &str,
+    port: u16,
+    catalog: Option<&str>,
+    query: &TriggerQuery,
+) -> Result<StatementsOutput> {
+    let (config, ctx, statements) = prepare_query(host, port, catalog, query).await?;
+    let output = run_statements(&ctx, host, port, &config, &statements).await?;
+    let row_count: usize = output.batches.iter().map(|batch| batch.num_rows()).sum();
+    info!("query returned {} rows", row_count);
+    Ok(output)
+}
+
+/// Validate the results of the query if requested and export them
+async fn query_results(
+    query: &TriggerQuery,
+    schema: SchemaRef,
+    batches: &[RecordBatch],
+) -> Result<(QueryResult, Option<ValidationReport>)> {
+    let validation = match &query.validate {
+        Some(options) => Some(
+            validate_tpch_query(
+                query.tpch_query,
+                query.format,
+                &query.schema.types(),
+                batches,
+                options,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    let result = export_results(&Uuid::new_v4().to_string(), schema, batches, &query.result)?;
+    Ok((result, validation))
+}
+
+/// Where the results of the query are cached, with the current version of its tables
+struct CacheSlot {
+    cache: ResultCache,
+    key: CacheKey,
+    data_version: Vec<FileVersion>,
+}
+
+/// The slot of the query in the result cache, None if the query cannot be cached
+async fn cache_slot(opt: &Config, query: &TriggerQuery) -> Option<CacheSlot> {
+    let slot = async {
+        let dir = opt
+            .result_cache_dir
+            .as_deref()
+            .ok_or_else(|| anyhow!("result_cache_dir is not configured"))?;
+        let key = CacheKey::new(
+            &query_statements(query)?,
+            table_sources(opt.catalog.as_deref(), query)?,
+        );
+        ensure!(
+            !key.creates_tables(),
+            "tables created by the statements are not versioned"
+        );
+        let data_version = data_version(&key.tables).await?;
+        Ok::<_, anyhow::Error>(CacheSlot {
+            cache: ResultCache::new(dir),
+            key,
+            data_version,
+        })
+    };
+    match slot.await {
+        Ok(slot) => Some(slot),
+        Err(err) => {
+            warn!("the results of the query are not cached: {:#}", err);
+            None
+        }
+    }
+}
+
+/// Create the client that provisions the tasks, either on ECS or on a fake
+/// backend that resolves all tasks to a local cluster.
+fn provisioning_client(opt: &Config) -> Result<FargateCreationClient> {
+    match opt.backend.as_str() {
+        "ecs" => FargateCreationClient::try_new(opt.cluster_name.clone()),
+        "fake" => Ok(FargateCreationClient::with_api(
+            Arc::new(FakeEcs::new(
+                opt.fake_task_host.clone(),
+                FakeEcsDelays::default(),
+            )),
+            opt.cluster_name.clone(),
+        )),
+        backend => bail!("Unknown provisioning backend: {}", backend),
+    }
+}
+
+/// Timings of the cluster provisioning phases, in ms since the trigger started
+#[derive(Default, Serialize)]
+struct ProvisioningTimings {
+    pub scheduler_task: TaskTimings,
+    pub executor_tasks: Vec<TaskTimings>,
+    pub executor_registrations: Vec<ExecutorRegistrationTiming>,
+}
+
+/// A cluster with all its executors registered to the scheduler
+struct Cluster {
+    scheduler: ProvisionedTask,
+    runtime: ExecutorRuntime,
+    executors: Vec<TaskTimings>,
+    executor_registrations: Vec<ExecutorRegistrationTiming>,
+    provisioning_duration_ms: u64,
+}
+
+impl Cluster {
+    fn timings(self) -> ProvisioningTimings {
+        ProvisioningTimings {
+            scheduler_task: self.scheduler.timings,
+            executor_tasks: self.executors,
+            executor_registrations: self.executor_registrations,
+        }
+    }
+}
+
+/// Where clients can send queries to a running cluster
+#[derive(Serialize)]
+struct ClusterEndpoints {
+    /// arn of the scheduler task, the cluster lives as long as this task
+    pub session_id: String,
+    pub scheduler_host: String,
+    pub scheduler_port: u16,
+    /// hosts of the executors registered to the scheduler
+    pub executors: Vec<String>,
+    /// unix timestamp (s) at which the scheduler shuts down if it receives no request.
+    /// Every query extends it.
+    pub expires_at: i64,
+}
 
+impl ClusterEndpoints {
+    fn new(
+        opt: &Config,
+        session_id: String,
+        scheduler_host: String,
+        registrations: &[ExecutorRegistrationTiming],
+    ) -> Self {
+        Self {
+            session_id,
+            scheduler_host,
+            scheduler_port: opt.scheduler_port,
+            executors: registrations.iter().map(|r| r.host.clone()).collect(),
+            expires_at: chrono::Utc::now().timestamp() + opt.task_expiration_sec,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TriggerResponse {
+    pub provisioning_duration_ms: u64,
+    pub execution_duration_ms: u64,
+    pub provisioning: ProvisioningTimings,
+    pub execution: QueryTimings,
+    pub result: QueryResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validation: Option<ValidationReport>,
+    /// not set for cached results or if the size of the tasks could not be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<CostEstimate>,
+    /// age of the cached results returned without provisioning the cluster
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached_age_sec: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ProvisionResponse {
+    #[serde(flatten)]
+    pub endpoints: ClusterEndpoints,
+    pub provisioning_duration_ms: u64,
+    pub provisioning: ProvisioningTimings,
+}
+
+#[derive(Serialize)]
+struct DescribeResponse {
+    /// false if no scheduler task (or not the one of the requested session) is running
+    pub active: bool,
+    #[serde(flatten)]
+    pub endpoints: Option<ClusterEndpoints>,
+}
+
+#[derive(Serialize)]
+struct SubmitResponse {
+    pub job_id: String,
+    #[serde(flatten)]
+    pub endpoints: ClusterEndpoints,
+    pub provisioning_duration_ms: u64,
+    pub provisioning: ProvisioningTimings,
+}
+
+#[derive(Serialize)]
+struct JobResultResponse {
+    pub job_id: String,
+    pub result_transfer_ms: u64,
+    pub result: QueryResult,
+}
+
+#[derive(Serialize)]
+struct BatchResponse {
+    pub provisioning_duration_ms: u64,
+    pub provisioning: ProvisioningTimings,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<CostEstimate>,
+    #[serde(flatten)]
+    pub summary: BatchSummary,
+}
+
+#[derive(Serialize)]
+struct InvalidateResponse {
+    pub removed_entries: usize,
+}
+
+#[derive(Serialize)]
+struct TeardownResponse {
+    pub stopped_tasks: Vec<String>,
+}
+
+/// Start (or reuse) the scheduler and the executors and wait for the executors to register
+#[instrument(skip(opt))]
+async fn provision_cluster(
+    opt: &Config,
+    executor_count: usize,
+    runtime: ExecutorRuntime,
+) -> Result<Cluster> {
     let start = Instant::now();
 
     // start standalone and extra executor
     let subnets: Vec<String> = opt.subnets.split(",").map(|s| s.to_owned()).collect();
-    let client = fargate::FargateCreationClient::try_new(opt.cluster_name)?;
+    let client = provisioning_client(opt)?;
     let sched_future = client.get_or_provision(
-        opt.standalone_task_def_arn,
-        opt.standalone_task_sg_id,
+        opt.standalone_task_def_arn.clone(),
+        opt.standalone_task_sg_id.clone(),
         subnets.clone(),
         1,
     );
-    let exec_future = client.get_or_provision(
-        opt.executor_task_def_arn,
-        opt.executor_task_sg_id,
-        subnets,
-        executor_count - 1,
+    let (scheduler, executors) = match runtime {
+        ExecutorRuntime::Fargate => {
+            let exec_future = client.get_or_provision(
+                opt.executor_task_def_arn.clone(),
+                opt.executor_task_sg_id.clone(),
+                subnets,
+                executor_count - 1,
+            );
+            let (scheduler_res, executor_res) = tokio::join!(sched_future, exec_future);
+            let scheduler = scheduler_res?.remove(0);
+            let executors = executor_res?;
+            info!(
+                "scheduler: {}, executors: {:?}",
+                scheduler.private_ip,
+                executors.iter().map(|e| &e.private_ip).collect::<Vec<_>>()
+            );
+            (scheduler, executors.into_iter().map(|e| e.timings).collect())
+        }
+        ExecutorRuntime::Lambda => {
+            // the Lambda executors need the scheduler address and give up if it is not up
+            let scheduler = sched_future.await?.remove(0);
+            wait_executors(&scheduler.private_ip, opt.scheduler_port, 0).await?;
+            info!("scheduler: {}, invoking Lambda executors", scheduler.private_ip);
+            let function_name = opt
+                .executor_lambda_name
+                .clone()
+                .ok_or_else(|| anyhow!("executor_lambda_name is not configured"))?;
+            let shuffle_dir = opt
+                .shuffle_dir
+                .clone()
+                .ok_or_else(|| anyhow!("shuffle_dir is not configured"))?;
+            let event = LambdaExecutorEvent {
+                scheduler_host: scheduler.private_ip.clone(),
+                scheduler_port: opt.scheduler_port,
+                concurrent_tasks: 1,
+                shuffle: Some(SharedShuffle { dir: shuffle_dir }),
+            };
+            let executors = LambdaExecutorClient::try_new(function_name)?
+                .invoke_executors(&event, executor_count - 1)
+                .await?;
+            (scheduler, executors)
+        }
+    };
+
+    let registration_start = start.elapsed().as_millis() as u64;
+    let executor_registrations =
+        wait_executors(&scheduler.private_ip, opt.scheduler_port, executor_count)
+            .await?
+            .into_iter()
+            .map(|registration| ExecutorRegistrationTiming {
+                registered_ms: registration.registered_ms + registration_start,
+                ..registration
+            })
+            .collect();
+
+    Ok(Cluster {
+        scheduler,
+        runtime,
+        executors,
+        executor_registrations,
+        provisioning_duration_ms: start.elapsed().as_millis() as u64,
+    })
+}
+
+/// Estimate the cost of the cluster for a run that ended `run_end_ms` after the start
+/// of its provisioning, from the size of the tasks read from their definitions
+async fn cluster_cost(opt: &Config, cluster: &Cluster, run_end_ms: u64) -> Result<CostEstimate> {
+    let prices = PriceTable::load(opt.price_table.as_deref())?;
+    let client = provisioning_client(opt)?;
+    let expiration_ms = opt.task_expiration_sec as u64 * 1000;
+    let mut estimate = CostEstimate::default();
+    let standalone = client
+        .task_resources(opt.standalone_task_def_arn.clone())
+        .await?;
+    estimate.add_fargate_task(
+        &prices,
+        &cluster.scheduler.timings,
+        standalone,
+        run_end_ms,
+        expiration_ms,
     );
-    let (scheduler_ip_res, executor_ip_res) = tokio::join!(sched_future, exec_future);
-    let scheduler_ip = &scheduler_ip_res?[0];
-    let executor_ips = executor_ip_res?;
+    match cluster.runtime {
+        ExecutorRuntime::Fargate => {
+            let executor = client
+                .task_resources(opt.executor_task_def_arn.clone())
+                .await?;
+            for timings in &cluster.executors {
+                estimate.add_fargate_task(&prices, timings, executor, run_end_ms, expiration_ms);
+            }
+        }
+        ExecutorRuntime::Lambda => {
+            let function_name = opt
+                .executor_lambda_name
+                .clone()
+                .ok_or_else(|| anyhow!("executor_lambda_name is not configured"))?;
+            let function = LambdaExecutorClient::try_new(function_name)?
+                .function_size()
+                .await?;
+            for timings in &cluster.executors {
+                estimate.add_task(
+                    &prices,
+                    timings.task_arn.clone(),
+                    LaunchType::Lambda,
+                    function.resources,
+                    function.timeout_ms,
+                );
+            }
+        }
+    }
+    Ok(estimate)
+}
 
-    info!("scheduler: {}, executors: {:?}", scheduler_ip, executor_ips);
+/// The cost of the run, a failed estimation does not fail the run
+async fn try_cluster_cost(
+    opt: &Config,
+    cluster: &Cluster,
+    run_end_ms: u64,
+) -> Option<CostEstimate> {
+    match cluster_cost(opt, cluster, run_end_ms).await {
+        Ok(estimate) => Some(estimate),
+        Err(err) => {
+            warn!("could not estimate the cost of the run: {:?}", err);
+            None
+        }
+    }
+}
 
-    wait_executors(&scheduler_ip, opt.scheduler_port, executor_count).await?;
+#[instrument(skip(opt, query), fields(executor_count = query.executor_count))]
+async fn start_trigger(opt: &Config, query: &TriggerQuery) -> Result<TriggerResponse> {
+    if query.sql.is_some() && query.validate.is_some() {
+        bail!("Only TPC-H queries can be validated");
+    }
+    let slot = if query.cache {
+        cache_slot(opt, query).await
+    } else {
+        None
+    };
+    if let Some(slot) = &slot {
+        let start = Instant::now();
+        let ttl = Duration::from_secs(query.cache_ttl_sec.unwrap_or(opt.result_cache_ttl_sec));
+        match slot.cache.get(&slot.key, &slot.data_version, ttl) {
+            Ok(Some(cached)) => {
+                info!("returning the results cached {}s ago", cached.age_sec);
+                let (result, validation) =
+                    query_results(query, cached.schema, &cached.batches).await?;
+                return Ok(TriggerResponse {
+                    provisioning_duration_ms: 0,
+                    execution_duration_ms: start.elapsed().as_millis() as u64,
+                    provisioning: ProvisioningTimings::default(),
+                    execution: QueryTimings::default(),
+                    result,
+                    validation,
+                    cost: None,
+                    cached_age_sec: Some(cached.age_sec),
+                });
+            }
+            Ok(None) => {}
+            Err(err) => warn!("could not read the result cache: {:#}", err),
+        }
+    }
 
-    let provisioning_duration = start.elapsed().as_millis() as u64;
+    let cluster =
+        provision_cluster(opt, query.executor_count as usize, query.executor_runtime).await?;
+    let scheduler_ip = cluster.scheduler.private_ip.clone();
 
     let start = Instant::now();
-    query_ballista(&scheduler_ip, opt.scheduler_port, tpch_query).await?;
-    let execution_duration = start.elapsed().as_millis() as u64;
+    let output =
+        query_ballista(&scheduler_ip, opt.scheduler_port, opt.catalog.as_deref(), query).await?;
+    let execution_duration_ms = start.elapsed().as_millis() as u64;
+    if let Some(slot) = slot {
+        let stored = slot.cache.put(
+            &slot.key,
+            slot.data_version,
+            output.schema.clone(),
+            &output.batches,
+        );
+        if let Err(err) = stored {
+            warn!("could not cache the results: {:#}", err);
+        }
+    }
+    let (result, validation) = query_results(query, output.schema, &output.batches).await?;
+    let cost = try_cluster_cost(
+        opt,
+        &cluster,
+        cluster.provisioning_duration_ms + execution_duration_ms,
+    )
+    .await;
 
-    Ok((provisioning_duration, execution_duration))
+    Ok(TriggerResponse {
+        provisioning_duration_ms: cluster.provisioning_duration_ms,
+        execution_duration_ms,
+        provisioning: cluster.timings(),
+        execution: output.timings,
+        result,
+        validation,
+        cost,
+        cached_age_sec: None,
+    })
+}
+
+/// Provision the cluster without running any query
+#[instrument(skip(opt, query), fields(executor_count = query.executor_count))]
+async fn provision(opt: &Config, query: &TriggerQuery) -> Result<ProvisionResponse> {
+    let cluster =
+        provision_cluster(opt, query.executor_count as usize, query.executor_runtime).await?;
+    let endpoints = ClusterEndpoints::new(
+        opt,
+        cluster.scheduler.timings.task_arn.clone(),
+        cluster.scheduler.private_ip.clone(),
+        &cluster.executor_registrations,
+    );
+    Ok(ProvisionResponse {
+        endpoints,
+        provisioning_duration_ms: cluster.provisioning_duration_ms,
+        provisioning: cluster.timings(),
+    })
+}
+
+/// Find the arn and the IP of the running scheduler task, if it belongs to the given session
+async fn find_scheduler(
+    opt: &Config,
+    session_id: Option<&str>,
+) -> Result<Option<(String, String)>> {
+    let client = provisioning_client(opt)?;
+    let scheduler_task = client
+        .get_existing_tasks(opt.standalone_task_def_arn.clone())
+        .await?
+        .into_iter()
+        .find(|task_arn| session_id.map_or(true, |id| id == task_arn));
+    let scheduler_task = match scheduler_task {
+        Some(task_arn) => task_arn,
+        None => return Ok(None),
+    };
+    let scheduler_ip = scheduler_timeout(
+        opt,
+        client.wait_for_provisioning(vec![scheduler_task.clone()]),
+    )
+    .await?
+    .remove(0);
+    Ok(Some((scheduler_task, scheduler_ip)))
+}
+
+/// Bound a request to a running scheduler, that might be stopping or unreachable
+async fn scheduler_timeout<T>(opt: &Config, request: impl Future<Output = Result<T>>) -> Result<T> {
+    match timeout(Duration::from_secs(opt.scheduler_timeout_sec), request).await {
+        Ok(result) => result,
+        Err(_) => bail!(
+            "The scheduler is unavailable, it did not answer within {}s",
+            opt.scheduler_timeout_sec
+        ),
+    }
+}
+
+/// The IP of the scheduler that runs the jobs of the session
+async fn session_scheduler(opt: &Config, session_id: Option<&str>) -> Result<String> {
+    match find_scheduler(opt, session_id).await? {
+        Some((_, scheduler_ip)) => Ok(scheduler_ip),
+        None => bail!("The scheduler of the session is not running anymore, its jobs are lost"),
+    }
+}
+
+/// Describe the running cluster. As any request to the scheduler, it extends its lifetime.
+#[instrument(skip(opt))]
+async fn describe(opt: &Config, session_id: Option<&str>) -> Result<DescribeResponse> {
+    let (scheduler_task, scheduler_ip) = match find_scheduler(opt, session_id).await? {
+        Some(scheduler) => scheduler,
+        None => {
+            return Ok(DescribeResponse {
+                active: false,
+                endpoints: None,
+            })
+        }
+    };
+    let registrations =
+        scheduler_timeout(opt, wait_executors(&scheduler_ip, opt.scheduler_port, 0)).await?;
+    Ok(DescribeResponse {
+        active: true,
+        endpoints: Some(ClusterEndpoints::new(
+            opt,
+            scheduler_task,
+            scheduler_ip,
+            &registrations,
+        )),
+    })
+}
+
+/// Provision the cluster and submit the query without waiting for its completion
+#[instrument(skip(opt, query), fields(executor_count = query.executor_count))]
+async fn submit(opt: &Config, query: &TriggerQuery) -> Result<SubmitResponse> {
+    if query.validate.is_some() {
+        bail!("Submitted jobs cannot be validated");
+    }
+    let cluster =
+        provision_cluster(opt, query.executor_count as usize, query.executor_runtime).await?;
+    let scheduler_ip = cluster.scheduler.private_ip.clone();
+    let (config, ctx, statements) =
+        prepare_query(&scheduler_ip, opt.scheduler_port, opt.catalog.as_deref(), query).await?;
+    let job_id =
+        submit_statements(&ctx, &scheduler_ip, opt.scheduler_port, &config, &statements).await?;
+    info!("submitted job {}", job_id);
+    let endpoints = ClusterEndpoints::new(
+        opt,
+        cluster.scheduler.timings.task_arn.clone(),
+        scheduler_ip,
+        &cluster.executor_registrations,
+    );
+    Ok(SubmitResponse {
+        job_id,
+        endpoints,
+        provisioning_duration_ms: cluster.provisioning_duration_ms,
+        provisioning: cluster.timings(),
+    })
+}
+
+/// Provision the cluster and run the queries of the batch concurrently
+#[instrument(skip(opt, query), fields(executor_count = query.executor_count))]
+async fn run_batch_action(opt: &Config, query: &TriggerQuery) -> Result<BatchResponse> {
+    let batch = query
+        .batch
+        .as_ref()
+        .ok_or_else(|| anyhow!("batch is required for the batch action"))?;
+    let cluster =
+        provision_cluster(opt, query.executor_count as usize, query.executor_runtime).await?;
+    let scheduler_ip = cluster.scheduler.private_ip.clone();
+    let (config, ctx) =
+        query_context(&scheduler_ip, opt.scheduler_port, opt.catalog.as_deref(), query).await?;
+    let summary = run_batch(
+        &ctx,
+        &scheduler_ip,
+        opt.scheduler_port,
+        &config,
+        batch.queries()?,
+        batch.concurrency,
+    )
+    .await;
+    let cost = try_cluster_cost(
+        opt,
+        &cluster,
+        cluster.provisioning_duration_ms + summary.duration_ms,
+    )
+    .await;
+    Ok(BatchResponse {
+        provisioning_duration_ms: cluster.provisioning_duration_ms,
+        provisioning: cluster.timings(),
+        cost,
+        summary,
+    })
+}
+
+async fn job_client(opt: &Config, query: &TriggerQuery) -> Result<JobClient> {
+    let scheduler_ip = session_scheduler(opt, query.session_id.as_deref()).await?;
+    JobClient::try_new(&scheduler_ip, opt.scheduler_port, &BallistaConfig::new()?).await
+}
+
+#[instrument(skip(opt, query))]
+async fn job_status(opt: &Config, query: &TriggerQuery) -> Result<JobState> {
+    let job_id = query.job_id()?;
+    let status = job_client(opt, query).await?.status(job_id).await?;
+    Ok(JobState::new(job_id, &status))
+}
+
+/// Fetch the results of a completed job
+#[instrument(skip(opt, query))]
+async fn job_result(opt: &Config, query: &TriggerQuery) -> Result<JobResultResponse> {
+    let job_id = query.job_id()?;
+    let mut client = job_client(opt, query).await?;
+    let locations = match client.status(job_id).await? {
+        job_status::Status::Completed(completed) => completed.partition_location,
+        status => bail!(
+            "Job {} is {}, results are only available once completed",
+            job_id,
+            JobState::new(job_id, &status).status
+        ),
+    };
+    let start = Instant::now();
+    let batches = fetch_results(locations, client.host()).await?;
+    let result_transfer_ms = start.elapsed().as_millis() as u64;
+    let schema = batches
+        .first()
+        .map(|batch| batch.schema())
+        .unwrap_or_else(|| Arc::new(Schema::empty()));
+    let result = export_results(job_id, schema, &batches, &query.result)?;
+    Ok(JobResultResponse {
+        job_id: job_id.to_owned(),
+        result_transfer_ms,
+        result,
+    })
+}
+
+/// Cancel the job, finished jobs are left untouched
+#[instrument(skip(opt, query))]
+async fn cancel_job(opt: &Config, query: &TriggerQuery) -> Result<CancelOutcome> {
+    let job_id = query.job_id()?;
+    job_client(opt, query).await?.cancel(job_id).await
+}
+
+/// Remove the cached results of the query of the event, or all of them if it has none
+#[instrument(skip(opt, query))]
+async fn invalidate_cache(opt: &Config, query: &TriggerQuery) -> Result<InvalidateResponse> {
+    let dir = opt
+        .result_cache_dir
+        .as_deref()
+        .ok_or_else(|| anyhow!("result_cache_dir is not configured"))?;
+    let cache = ResultCache::new(dir);
+    let removed_entries = if query.sql.is_none() && query.tpch_query == 0 {
+        cache.clear()?
+    } else {
+        let key = CacheKey::new(
+            &query_statements(query)?,
+            table_sources(opt.catalog.as_deref(), query)?,
+        );
+        cache.invalidate(&key)? as usize
+    };
+    info!("removed {} result cache entries", removed_entries);
+    Ok(InvalidateResponse { removed_entries })
+}
+
+/// Stop the executors and the scheduler
+#[instrument(skip(opt))]
+async fn teardown(opt: &Config) -> Result<TeardownResponse> {
+    let client = provisioning_client(opt)?;
+    let reason = "Ballista cluster teardown";
+    let mut stopped_tasks = client
+        .stop_tasks(opt.executor_task_def_arn.clone(), reason)
+        .await?;
+    stopped_tasks.append(
+        &mut client
+            .stop_tasks(opt.standalone_task_def_arn.clone(), reason)
+            .await?,
+    );
+    Ok(TeardownResponse { stopped_tasks })
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    let (opt, _remaining_args) =
+        config::Config::including_optional_config_files(&["/etc/ballista/trigger.toml"])
+            .unwrap_or_exit();
     env_logger::init();
-    let func = handler_fn(my_handler);
+    init_tracing("ballista-trigger", &opt.trace_exporter)?;
+    // the config is loaded once and shared by all the invocations
+    let opt = Arc::new(opt);
+    // outside of Lambda, run a single event read from stdin
+    if env::var("AWS_LAMBDA_RUNTIME_API").is_err() {
+        let event: Value = serde_json::from_reader(std::io::stdin())?;
+        let response = handle_event(&opt, event).await?;
+        println!("{}", serde_json::to_string_pretty(&response)?);
+        return Ok(());
+    }
+    let func = handler_fn(move |event: Value, _: Context| {
+        let opt = Arc::clone(&opt);
+        async move { handle_event(&opt, event).await }
+    });
     lambda_runtime::run(func).await?;
     Ok(())
 }
 
+/// A single SQL statement or a list of statements run in order
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SqlStatements {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl SqlStatements {
+    fn statements(&self) -> Vec<String> {
+        match self {
+            SqlStatements::One(sql) => vec![sql.clone()],
+            SqlStatements::Many(sql) => sql.clone(),
+        }
+    }
+}
+
+/// What the trigger should do with the cluster
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TriggerAction {
+    /// provision the cluster and run the query
+    Query,
+    /// provision the cluster and return its endpoints
+    Provision,
+    /// return the endpoints of the running cluster
+    Describe,
+    /// stop all the tasks of the cluster
+    Teardown,
+    /// provision the cluster and submit the query, returning its job id immediately
+    Submit,
+    /// status of the job `job_id`
+    Status,
+    /// results of the completed job `job_id`
+    Result,
+    /// cancel the job `job_id`
+    Cancel,
+    /// provision the cluster and run the queries of `batch` concurrently
+    Batch,
+    /// remove the cached results of the query, or all of them if the event has no query
+    Invalidate,
+}
+
+impl TriggerAction {
+    fn provisions(&self) -> bool {
+        matches!(
+            self,
+            TriggerAction::Query
+                | TriggerAction::Provision
+                | TriggerAction::Submit
+                | TriggerAction::Batch
+        )
+    }
+
+    fn runs_query(&self) -> bool {
+        matches!(self, TriggerAction::Query | TriggerAction::Submit)
+    }
+
+    fn returns_results(&self) -> bool {
+        matches!(self, TriggerAction::Query | TriggerAction::Result)
+    }
+
+    fn targets_job(&self) -> bool {
+        matches!(
+            self,
+            TriggerAction::Status | TriggerAction::Result | TriggerAction::Cancel
+        )
+    }
+}
+
+impl Default for TriggerAction {
+    fn default() -> Self {
+        TriggerAction::Query
+    }
+}
+
+/// Where the executors other than the one of the standalone task run
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExecutorRuntime {
+    Fargate,
+    /// invocations of the `executor_lambda_name` function, only stopped by their timeout
+    Lambda,
+}
+
+impl Default for ExecutorRuntime {
+    fn default() -> Self {
+        ExecutorRuntime::Fargate
+    }
+}
+
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct TriggerQuery {
+    #[serde(default)]
+    pub action: TriggerAction,
+    /// session returned by `provision`, `describe` reports it inactive if its scheduler is gone
+    pub session_id: Option<String>,
+    /// job returned by `submit`
+    pub job_id: Option<String>,
     #[serde(default)]
     pub executor_count: u16,
     #[serde(default)]
+    pub executor_runtime: ExecutorRuntime,
+    /// TPC-H query number, used if no `sql` is provided
+    #[serde(default)]
     pub tpch_query: u8,
+    /// storage format of the TPC-H tables
+    #[serde(default)]
+    pub format: TpchFormat,
+    /// column types of the TPC-H `.tbl` tables
+    #[serde(default)]
+    pub schema: TpchSchemaOptions,
+    pub sql: Option<SqlStatements>,
+    /// tables to register, if empty the configured catalog or the TPC-H tables are registered
+    #[serde(default)]
+    pub tables: Vec<TableDefinition>,
+    /// values of the `$name` placeholders in the SQL
+    #[serde(default)]
+    pub params: HashMap<String, Value>,
+    #[serde(default)]
+    pub result: ResultOptions,
+    /// compare the results of the TPC-H query with a reference answer
+    pub validate: Option<ValidationOptions>,
+    /// queries of the `batch` action
+    pub batch: Option<BatchSpec>,
+    /// return the cached results of the same query if its tables did not change,
+    /// otherwise cache the results of the run
+    #[serde(default)]
+    pub cache: bool,
+    /// maximum age of the cached results, `result_cache_ttl_sec` if not set
+    pub cache_ttl_sec: Option<u64>,
 }
 
-impl Default for TriggerQuery{
-    fn default() -> Self {
-        Self { executor_count: 2, tpch_query: 1 }
+impl TriggerQuery {
+    fn job_id(&self) -> Result<&str> {
+        match &self.job_id {
+            Some(job_id) => Ok(job_id),
+            None => bail!("job_id is required for the {:?} action", self.action),
+        }
+    }
+
+    /// Check the event before doing anything with the cluster
+    fn validate(&self, opt: &Config) -> Result<()> {
+        if self.action.provisions() {
+            ensure!(
+                self.executor_count >= 1,
+                "executor_count should be at least 1"
+            );
+            ensure!(
+                self.executor_count <= opt.max_executor_count,
+                "executor_count should be at most {}",
+                opt.max_executor_count
+            );
+            if self.executor_runtime == ExecutorRuntime::Lambda {
+                ensure!(
+                    opt.executor_lambda_name.is_some(),
+                    "Lambda executors require executor_lambda_name to be configured"
+                );
+                // the outputs of a Lambda executor are lost when its invocation ends
+                ensure!(
+                    opt.shuffle_dir.is_some(),
+                    "Lambda executors require shuffle_dir to be configured"
+                );
+            }
+        }
+        if self.action.runs_query() {
+            match &self.sql {
+                Some(sql) => ensure!(
+                    sql.statements().iter().any(|s| !s.trim().is_empty()),
+                    "sql should contain at least one statement"
+                ),
+                None => ensure!(
+                    get_query(self.tpch_query).is_ok(),
+                    "tpch_query should be between 1 and 22 when no sql is provided, got {}",
+                    self.tpch_query
+                ),
+            }
+        }
+        if self.action.returns_results() && self.result.format == ResultFormat::Json {
+            ensure!(
+                self.result.max_bytes <= LAMBDA_MAX_RESPONSE_BYTES,
+                "result.max_bytes should be at most {} for inline results",
+                LAMBDA_MAX_RESPONSE_BYTES
+            );
+        }
+        if self.action.targets_job() {
+            self.job_id()?;
+        }
+        if self.cache {
+            ensure!(
+                self.action == TriggerAction::Query,
+                "cache is only supported by the query action"
+            );
+        }
+        if self.cache || self.action == TriggerAction::Invalidate {
+            ensure!(
+                opt.result_cache_dir.is_some(),
+                "The result cache requires result_cache_dir to be configured"
+            );
+        }
+        if self.action == TriggerAction::Batch {
+            ensure!(
+                self.sql.is_none() && self.tpch_query == 0 && self.params.is_empty(),
+                "sql, tpch_query and params are not used by the batch action, see batch"
+            );
+            match &self.batch {
+                Some(batch) => batch.validate()?,
+                None => bail!("batch is required for the batch action"),
+            }
+        }
+        Ok(())
     }
 }
 
-#[derive(Serialize)]
-struct TriggerResponse {
-    pub provisioning_duration_ms: u64,
-    pub execution_duration_ms: u64,
+async fn run_action(opt: &Config, query: &TriggerQuery) -> Result<Value> {
+    let response = match query.action {
+        TriggerAction::Query => serde_json::to_value(start_trigger(opt, query).await?)?,
+        TriggerAction::Provision => serde_json::to_value(provision(opt, query).await?)?,
+        TriggerAction::Describe => {
+            serde_json::to_value(describe(opt, query.session_id.as_deref()).await?)?
+        }
+        TriggerAction::Teardown => serde_json::to_value(teardown(opt).await?)?,
+        TriggerAction::Submit => serde_json::to_value(submit(opt, query).await?)?,
+        TriggerAction::Status => serde_json::to_value(job_status(opt, query).await?)?,
+        TriggerAction::Result => serde_json::to_value(job_result(opt, query).await?)?,
+        TriggerAction::Cancel => serde_json::to_value(cancel_job(opt, query).await?)?,
+        TriggerAction::Batch => serde_json::to_value(run_batch_action(opt, query).await?)?,
+        TriggerAction::Invalidate => serde_json::to_value(invalidate_cache(opt, query).await?)?,
+    };
+    Ok(response)
 }
 
-async fn my_handler(event: Value, _: Context) -> Result<Value, Error> {
-    let query: TriggerQuery = serde_json::from_value(event)?;
-    let (provisioning_duration_ms, execution_duration_ms) = start_trigger(query.executor_count as usize, query.tpch_query).await?;
-    Ok(serde_json::to_value(TriggerResponse{provisioning_duration_ms, execution_duration_ms})?)
+async fn handle_event(opt: &Config, event: Value) -> Result<Value, Error> {
+    let query: TriggerQuery =
+        serde_json::from_value(event).map_err(|e| anyhow!("Invalid event: {}", e))?;
+    query
+        .validate(opt)
+        .map_err(|e| anyhow!("Invalid event: {:#}", e))?;
+    let result = run_action(opt, &query).await;
+    flush_traces();
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> Config {
+        Config {
+            namespace: "ballista".to_owned(),
+            scheduler_port: 50050,
+            cluster_name: "cluster".to_owned(),
+            standalone_task_sg_id: "sg".to_owned(),
+            standalone_task_def_arn: "standalone".to_owned(),
+            executor_task_sg_id: "sg".to_owned(),
+            executor_task_def_arn: "executor".to_owned(),
+            subnets: "subnet".to_owned(),
+            max_executor_count: 64,
+            task_expiration_sec: 600,
+            trace_exporter: "none".to_owned(),
+            backend: "fake".to_owned(),
+            fake_task_host: "localhost".to_owned(),
+            catalog: None,
+            executor_lambda_name: Some("executor".to_owned()),
+            shuffle_dir: None,
+            price_table: None,
+            result_cache_dir: Some("/tmp/results".to_owned()),
+            result_cache_ttl_sec: 3600,
+            scheduler_timeout_sec: 30,
+        }
+    }
+
+    fn validate(event: Value) -> Result<()> {
+        serde_json::from_value::<TriggerQuery>(event)?.validate(&config())
+    }
+
+    fn error(event: Value) -> String {
+        validate(event).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn executor_count_is_bounded() {
+        assert!(validate(json!({"executor_count": 1, "tpch_query": 1})).is_ok());
+        assert!(validate(json!({"executor_count": 64, "tpch_query": 1})).is_ok());
+        assert!(error(json!({"tpch_query": 1})).contains("at least 1"));
+        assert!(validate(json!({"executor_count": -1, "tpch_query": 1})).is_err());
+        let event = json!({"executor_count": 65, "tpch_query": 1});
+        assert!(error(event).contains("at most 64"));
+        // actions that do not provision ignore it
+        assert!(validate(json!({"action": "status", "job_id": "job"})).is_ok());
+    }
+
+    #[test]
+    fn query_should_be_known() {
+        let event = json!({"executor_count": 1, "tpch_query": 23});
+        assert!(error(event).contains("between 1 and 22"));
+        assert!(validate(json!({"executor_count": 1})).is_err());
+        let event = json!({"executor_count": 1, "sql": ["", " "]});
+        assert!(error(event).contains("at least one statement"));
+        assert!(validate(json!({"executor_count": 1, "sql": "SELECT 1"})).is_ok());
+    }
+
+    #[test]
+    fn lambda_executors_require_shuffle_dir() {
+        let event = json!({"executor_count": 2, "executor_runtime": "lambda", "tpch_query": 1});
+        assert!(error(event.clone()).contains("shuffle_dir"));
+        let query: TriggerQuery = serde_json::from_value(event).unwrap();
+        let opt = Config {
+            shuffle_dir: Some("/mnt/shuffle".to_owned()),
+            ..config()
+        };
+        assert!(query.validate(&opt).is_ok());
+        let opt = Config {
+            executor_lambda_name: None,
+            ..opt
+        };
+        assert!(query.validate(&opt).is_err());
+    }
+
+    #[test]
+    fn cache_only_with_query() {
+        let event = json!({"executor_count": 1, "tpch_query": 1, "cache": true});
+        assert!(validate(event.clone()).is_ok());
+        let query: TriggerQuery = serde_json::from_value(event).unwrap();
+        let opt = Config {
+            result_cache_dir: None,
+            ..config()
+        };
+        assert!(query.validate(&opt).is_err());
+        let event =
+            json!({"action": "submit", "executor_count": 1, "tpch_query": 1, "cache": true});
+        assert!(error(event).contains("only supported by the query action"));
+    }
+
+    #[test]
+    fn batch_does_not_take_sql() {
+        let batch = json!({"tpch_queries": [1, 6], "streams": 2});
+        let event = json!({"action": "batch", "executor_count": 1, "batch": batch.clone()});
+        assert!(validate(event).is_ok());
+        let event = json!({"action": "batch", "executor_count": 1, "batch": batch.clone(),
+            "sql": "SELECT 1"});
+        assert!(error(event).contains("not used by the batch action"));
+        let event =
+            json!({"action": "batch", "executor_count": 1, "tpch_query": 1, "batch": batch});
+        assert!(validate(event).is_err());
+        let event = json!({"action": "batch", "executor_count": 1});
+        assert!(error(event).contains("batch is required"));
+    }
 }
@@ -0,0 +1,475 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ballista_core::serde::protobuf::{
+    job_status, scheduler_grpc_client::SchedulerGrpcClient, task_status, FailedJob, FailedTask,
+    GetJobStatusParams, JobStatus, TaskStatus,
+};
+use ballista_scheduler::state::ConfigBackendClient;
+use log::{debug, info, warn};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use tonic::transport::Channel;
+
+use crate::execution::RunningTasks;
+
+/// error of the cancelled jobs and tasks
+pub const CANCELLED_ERROR: &str = "Job cancelled";
+
+/// interval between two sweeps of the executor work dir
+const CLEANUP_INTERVAL_SEC: u64 = 10;
+
+/// time the outputs of a completed job are kept for the clients to fetch them
+const COMPLETED_RETENTION_SEC: u64 = 3600;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelOutcome {
+    pub job_id: String,
+    /// false if the job was already completed or failed
+    pub cancelled: bool,
+    /// number of queued or running tasks marked as failed
+    pub cancelled_tasks: usize,
+}
+
+// keys of the scheduler state, see `ballista_scheduler::state`
+fn job_key(namespace: &str, job_id: &str) -> String {
+    format!("/ballista/{}/jobs/{}", namespace, job_id)
+}
+
+fn jobs_prefix(namespace: &str) -> String {
+    format!("/ballista/{}/jobs", namespace)
+}
+
+fn job_tasks_prefix(namespace: &str, job_id: &str) -> String {
+    format!("/ballista/{}/tasks/{}", namespace, job_id)
+}
+
+/// Cancel a job, which the Ballista scheduler does not support natively.
+/// The job and its unfinished tasks are marked as failed in the scheduler state
+/// so that no new task is assigned to executors. The tasks already running on
+/// executors are aborted on their next poll, see `abort_failed_jobs`, and their
+/// shuffle outputs removed by `cleanup_jobs`.
+/// Returns None if the job does not exist.
+pub async fn cancel_job(
+    config_backend: &dyn ConfigBackendClient,
+    namespace: &str,
+    job_id: &str,
+) -> Result<Option<CancelOutcome>> {
+    let _lock = config_backend.lock().await?;
+    let key = job_key(namespace, job_id);
+    let job = JobStatus::decode(config_backend.get(&key).await?.as_slice())?;
+    match job.status {
+        None => return Ok(None),
+        Some(job_status::Status::Completed(_)) | Some(job_status::Status::Failed(_)) => {
+            return Ok(Some(CancelOutcome {
+                job_id: job_id.to_owned(),
+                cancelled: false,
+                cancelled_tasks: 0,
+            }))
+        }
+        Some(_) => {}
+    }
+
+    let mut cancelled_tasks = 0;
+    let tasks = config_backend
+        .get_from_prefix(&job_tasks_prefix(namespace, job_id))
+        .await?;
+    for (task_key, value) in tasks {
+        let mut task = TaskStatus::decode(value.as_slice())?;
+        match task.status {
+            Some(task_status::Status::Completed(_)) | Some(task_status::Status::Failed(_)) => {
+                continue
+            }
+            _ => {}
+        }
+        task.status = Some(task_status::Status::Failed(FailedTask {
+            error: CANCELLED_ERROR.to_owned(),
+        }));
+        config_backend.put(task_key, encode(&task)?, None).await?;
+        cancelled_tasks += 1;
+    }
+
+    let job = JobStatus {
+        status: Some(job_status::Status::Failed(FailedJob {
+            error: CANCELLED_ERROR.to_owned(),
+        })),
+    };
+    config_backend.put(key, encode(&job)?, None).await?;
+    info!("cancelled job {} and {} tasks", job_id, cancelled_tasks);
+    Ok(Some(CancelOutcome {
+        job_id: job_id.to_owned(),
+        cancelled: true,
+        cancelled_tasks,
+    }))
+}
+
+/// True if a job of the scheduler state is queued or running
+pub async fn has_active_jobs(
+    config_backend: &dyn ConfigBackendClient,
+    namespace: &str,
+) -> Result<bool> {
+    let jobs = config_backend
+        .get_from_prefix(&jobs_prefix(namespace))
+        .await?;
+    for (_, value) in jobs {
+        match JobStatus::decode(value.as_slice())?.status {
+            Some(job_status::Status::Queued(_)) | Some(job_status::Status::Running(_)) => {
+                return Ok(true)
+            }
+            _ => {}
+        }
+    }
+    Ok(false)
+}
+
+fn encode<T: Message>(message: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut buf)?;
+    Ok(buf)
+}
+
+/// Status of the jobs, as known by the scheduler
+#[async_trait]
+pub trait JobStatuses: Send {
+    async fn job_status(&mut self, job_id: &str) -> Result<Option<job_status::Status>>;
+}
+
+#[async_trait]
+impl JobStatuses for SchedulerGrpcClient<Channel> {
+    async fn job_status(&mut self, job_id: &str) -> Result<Option<job_status::Status>> {
+        Ok(self
+            .get_job_status(GetJobStatusParams {
+                job_id: job_id.to_owned(),
+            })
+            .await?
+            .into_inner()
+            .status
+            .and_then(|status| status.status))
+    }
+}
+
+/// Abort the running tasks of the failed, including cancelled, jobs. Called by
+/// the poll loop of the executors after each poll, returns the number of tasks aborted.
+pub async fn abort_failed_jobs(
+    scheduler: &mut impl JobStatuses,
+    running: &RunningTasks,
+) -> Result<usize> {
+    let mut aborted = 0;
+    for job_id in running.job_ids() {
+        if let Some(job_status::Status::Failed(_)) = scheduler.job_status(&job_id).await? {
+            let job_aborted = running.abort_job(&job_id);
+            info!(
+                "aborted {} running tasks of failed job {}",
+                job_aborted, job_id
+            );
+            aborted += job_aborted;
+        }
+    }
+    Ok(aborted)
+}
+
+/// Periodically clean up the shuffle outputs of the jobs, where each job has a
+/// `{work_dir}/{job_id}` directory:
+/// - the outputs of the failed (including cancelled) jobs are removed, and their
+///   running tasks aborted if the poll loop did not already
+/// - the outputs of the completed jobs are removed after `completed_retention`,
+///   which leaves time to the clients to fetch the results
+///
+/// With a shared shuffle, the work dirs of all the executors under `shared_dir`
+/// are swept, including the ones of the executors that stopped.
+pub async fn cleanup_jobs(
+    mut scheduler: impl JobStatuses,
+    work_dir: String,
+    shared_dir: Option<String>,
+    running: RunningTasks,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SEC));
+    loop {
+        interval.tick().await;
+        let work_dirs = match &shared_dir {
+            Some(shared_dir) => sub_dirs(Path::new(shared_dir)),
+            None => Ok(vec![PathBuf::from(&work_dir)]),
+        };
+        let res = match work_dirs {
+            Ok(work_dirs) => {
+                let retention = Duration::from_secs(COMPLETED_RETENTION_SEC);
+                cleanup_once(&mut scheduler, &work_dirs, &running, retention).await
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            warn!("could not clean up the work dir: {:?}", err);
+        }
+    }
+}
+
+fn sub_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    Ok(dirs)
+}
+
+/// Remove the outputs of a job, that another executor sweeping the same shared
+/// directory might have removed already
+fn remove_outputs(path: &Path) -> Result<()> {
+    match std::fs::remove_dir_all(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+async fn cleanup_once(
+    scheduler: &mut impl JobStatuses,
+    work_dirs: &[PathBuf],
+    running: &RunningTasks,
+    completed_retention: Duration,
+) -> Result<()> {
+    let mut job_dirs: BTreeMap<String, Vec<PathBuf>> = running
+        .job_ids()
+        .into_iter()
+        .map(|job_id| (job_id, vec![]))
+        .collect();
+    for work_dir in work_dirs {
+        for path in sub_dirs(work_dir)? {
+            if let Some(job_id) = path.file_name().and_then(|name| name.to_str()) {
+                job_dirs
+                    .entry(job_id.to_owned())
+                    .or_default()
+                    .push(path.clone());
+            }
+        }
+    }
+    for (job_id, dirs) in job_dirs {
+        match scheduler.job_status(&job_id).await? {
+            Some(job_status::Status::Failed(failed)) => {
+                debug!("job {} failed: {}", job_id, failed.error);
+                let aborted = running.abort_job(&job_id);
+                if aborted > 0 {
+                    info!("aborted {} running tasks of failed job {}", aborted, job_id);
+                }
+                for dir in &dirs {
+                    remove_outputs(dir)?;
+                }
+                if !dirs.is_empty() {
+                    info!("removed shuffle outputs of failed job {}", job_id);
+                }
+            }
+            Some(job_status::Status::Completed(_)) => {
+                for dir in &dirs {
+                    // the job dir is not modified once its last stage is written
+                    let age = std::fs::metadata(dir)?
+                        .modified()?
+                        .elapsed()
+                        .unwrap_or_default();
+                    if age >= completed_retention {
+                        remove_outputs(dir)?;
+                        info!("removed shuffle outputs {} of completed job", dir.display());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ballista_core::serde::protobuf::{CompletedTask, RunningTask};
+    use ballista_scheduler::state::StandaloneClient;
+    use std::collections::HashMap;
+
+    #[async_trait]
+    impl JobStatuses for HashMap<String, job_status::Status> {
+        async fn job_status(&mut self, job_id: &str) -> Result<Option<job_status::Status>> {
+            Ok(self.get(job_id).cloned())
+        }
+    }
+
+    #[tokio::test]
+    async fn cleanup_removes_failed_and_completed_jobs() {
+        let shared_dir = tempfile::tempdir().unwrap();
+        let executors = [shared_dir.path().join("a"), shared_dir.path().join("b")];
+        for work_dir in &executors {
+            for job_id in &["failed", "running", "completed"] {
+                std::fs::create_dir_all(work_dir.join(job_id).join("1/0")).unwrap();
+            }
+            std::fs::write(work_dir.join("file"), b"").unwrap();
+        }
+        let mut statuses = HashMap::new();
+        statuses.insert(
+            "failed".to_owned(),
+            job_status::Status::Failed(FailedJob {
+                error: CANCELLED_ERROR.to_owned(),
+            }),
+        );
+        statuses.insert(
+            "running".to_owned(),
+            job_status::Status::Running(Default::default()),
+        );
+        statuses.insert(
+            "completed".to_owned(),
+            job_status::Status::Completed(Default::default()),
+        );
+        let running = RunningTasks::default();
+        running.spawn(
+            "failed".to_owned(),
+            "2/0".to_owned(),
+            futures::future::pending(),
+        );
+        running.spawn(
+            "running".to_owned(),
+            "2/0".to_owned(),
+            futures::future::pending(),
+        );
+
+        let work_dirs = sub_dirs(shared_dir.path()).unwrap();
+        let retention = Duration::from_secs(3600);
+        cleanup_once(&mut statuses, &work_dirs, &running, retention)
+            .await
+            .unwrap();
+        for work_dir in &executors {
+            assert!(!work_dir.join("failed").exists());
+            assert!(work_dir.join("running").exists());
+            assert!(work_dir.join("completed").exists());
+            assert!(work_dir.join("file").exists());
+        }
+        assert_eq!(
+            running.job_ids().into_iter().collect::<Vec<_>>(),
+            vec!["running"]
+        );
+
+        // the completed job outlived the retention
+        cleanup_once(&mut statuses, &work_dirs, &running, Duration::from_secs(0))
+            .await
+            .unwrap();
+        for work_dir in &executors {
+            assert!(work_dir.join("running").exists());
+            assert!(!work_dir.join("completed").exists());
+        }
+    }
+
+    fn task_key(job_id: &str, stage_id: u32) -> String {
+        format!("{}/{}/0", job_tasks_prefix("ns", job_id), stage_id)
+    }
+
+    async fn read_task_status(
+        backend: &StandaloneClient,
+        key: &str,
+    ) -> Option<task_status::Status> {
+        let value = backend.get(key).await.unwrap();
+        TaskStatus::decode(value.as_slice()).unwrap().status
+    }
+
+    #[tokio::test]
+    async fn cancel_marks_the_job_and_its_unfinished_tasks_failed() {
+        let backend = StandaloneClient::try_new_temporary().unwrap();
+        let job = JobStatus {
+            status: Some(job_status::Status::Running(Default::default())),
+        };
+        let job_key = job_key("ns", "job");
+        backend
+            .put(job_key.clone(), encode(&job).unwrap(), None)
+            .await
+            .unwrap();
+        let statuses = vec![
+            None,
+            Some(task_status::Status::Running(RunningTask::default())),
+            Some(task_status::Status::Completed(CompletedTask::default())),
+        ];
+        for (stage_id, status) in statuses.into_iter().enumerate() {
+            let task = TaskStatus {
+                partition_id: None,
+                status,
+            };
+            let key = task_key("job", stage_id as u32);
+            backend
+                .put(key, encode(&task).unwrap(), None)
+                .await
+                .unwrap();
+        }
+        // another job of the namespace
+        let other_task = TaskStatus {
+            partition_id: None,
+            status: None,
+        };
+        backend
+            .put(task_key("other", 0), encode(&other_task).unwrap(), None)
+            .await
+            .unwrap();
+
+        let outcome = cancel_job(&backend, "ns", "job").await.unwrap().unwrap();
+        assert!(outcome.cancelled);
+        assert_eq!(outcome.cancelled_tasks, 2);
+        let job = JobStatus::decode(backend.get(&job_key).await.unwrap().as_slice()).unwrap();
+        match job.status {
+            Some(job_status::Status::Failed(failed)) => assert_eq!(failed.error, CANCELLED_ERROR),
+            status => panic!("unexpected job status {:?}", status),
+        }
+        for stage_id in 0..2 {
+            match read_task_status(&backend, &task_key("job", stage_id)).await {
+                Some(task_status::Status::Failed(failed)) => {
+                    assert_eq!(failed.error, CANCELLED_ERROR)
+                }
+                status => panic!("unexpected task status {:?}", status),
+            }
+        }
+        assert!(matches!(
+            read_task_status(&backend, &task_key("job", 2)).await,
+            Some(task_status::Status::Completed(_))
+        ));
+        assert_eq!(
+            read_task_status(&backend, &task_key("other", 0)).await,
+            None
+        );
+
+        let outcome = cancel_job(&backend, "ns", "job").await.unwrap().unwrap();
+        assert!(!outcome.cancelled);
+        assert_eq!(outcome.cancelled_tasks, 0);
+        assert!(cancel_job(&backend, "ns", "unknown")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_jobs_are_aborted() {
+        let mut statuses = HashMap::new();
+        statuses.insert(
+            "cancelled".to_owned(),
+            job_status::Status::Failed(FailedJob {
+                error: CANCELLED_ERROR.to_owned(),
+            }),
+        );
+        statuses.insert(
+            "running".to_owned(),
+            job_status::Status::Running(Default::default()),
+        );
+        let running = RunningTasks::default();
+        for job_id in &["cancelled", "running"] {
+            for task in &["1/0", "1/1"] {
+                running.spawn(
+                    job_id.to_string(),
+                    task.to_string(),
+                    futures::future::pending(),
+                );
+            }
+        }
+        let aborted = abort_failed_jobs(&mut statuses, &running).await.unwrap();
+        assert_eq!(aborted, 2);
+        assert_eq!(
+            running.job_ids().into_iter().collect::<Vec<_>>(),
+            vec!["running"]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use ballista::context::BallistaContext;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use datafusion::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::s3::{is_s3_uri, new_client, sync_metadata, S3Cache, S3Location};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TableFormat {
+    Csv,
+    Parquet,
+}
+
+impl Default for TableFormat {
+    fn default() -> Self {
+        TableFormat::Csv
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ColumnDefinition {
+    pub name: String,
+    /// type name such as `int64`, `utf8` or `date32`
+    #[serde(rename = "type")]
+    pub data_type: String,
+    #[serde(default = "default_nullable")]
+    pub nullable: bool,
+}
+
+fn default_nullable() -> bool {
+    true
+}
+
+/// A dataset to register as a table before running queries.
+/// If no schema is provided, it is inferred from the files.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TableDefinition {
+    pub name: String,
+    /// file or directory, must be readable by the client and the executors.
+    /// `s3://bucket/prefix` URIs are read from the cache synced by the executors.
+    pub path: String,
+    #[serde(default)]
+    pub format: TableFormat,
+    pub schema: Option<Vec<ColumnDefinition>>,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    #[serde(default)]
+    pub has_header: bool,
+    pub file_extension: Option<String>,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+impl TableDefinition {
+    /// The declared schema of the table, if any
+    pub fn arrow_schema(&self) -> Result<Option<Schema>> {
+        let columns = match &self.schema {
+            Some(columns) => columns,
+            None => return Ok(None),
+        };
+        let fields = columns
+            .iter()
+            .map(|column| {
+                let data_type = parse_data_type(&column.data_type).with_context(|| {
+                    format!("Invalid type for column {}.{}", self.name, column.name)
+                })?;
+                Ok(Field::new(&column.name, data_type, column.nullable))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(Schema::new(fields)))
+    }
+}
+
+/// A list of tables, usually loaded from a TOML or JSON file:
+/// ```toml
+/// [[tables]]
+/// name = "nation"
+/// path = "/mnt/data/nation.tbl"
+/// delimiter = "|"
+/// schema = [
+///   { name = "n_nationkey", type = "int32", nullable = false },
+///   { name = "n_name", type = "utf8" },
+/// ]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Catalog {
+    #[serde(default)]
+    pub tables: Vec<TableDefinition>,
+}
+
+impl Catalog {
+    /// Load the catalog from a `.toml` or `.json` file
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).with_context(|| format!("Could not read catalog {}", path))?;
+        let catalog = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&content)?,
+            Some("json") => serde_json::from_str(&content)?,
+            _ => bail!("Catalog {} should be a .toml or .json file", path),
+        };
+        Ok(catalog)
+    }
+
+    pub async fn register(&self, ctx: &mut BallistaContext) -> Result<()> {
+        register_tables(ctx, &self.tables).await
+    }
+}
+
+/// The location of an S3 table, None for the local tables
+fn s3_location(table: &TableDefinition, cache: &S3Cache) -> Result<Option<S3Location>> {
+    if !is_s3_uri(&table.path) {
+        return Ok(None);
+    }
+    let location: S3Location = table.path.parse()?;
+    ensure!(
+        cache.is_synced(&location),
+        "S3 table {} is not under the locations synced by the executors (BALLISTA_S3_SYNC)",
+        table.name
+    );
+    Ok(Some(location))
+}
+
+/// The path from which the executors read the table. S3 tables are read from
+/// the local cache where the executors sync them, see `s3::S3Cache`.
+fn local_path(table: &TableDefinition, schema: &Option<Schema>, cache: &S3Cache) -> Result<String> {
+    let location = match s3_location(table, cache)? {
+        Some(location) => location,
+        None => return Ok(table.path.clone()),
+    };
+    ensure!(
+        table.format != TableFormat::Csv || schema.is_some(),
+        "S3 table {} should declare its schema as the clients do not download the files",
+        table.name
+    );
+    Ok(cache.path(&location).to_string_lossy().into_owned())
+}
+
+pub fn register_table(
+    ctx: &mut BallistaContext,
+    table: &TableDefinition,
+    cache: &S3Cache,
+) -> Result<()> {
+    let schema = table.arrow_schema()?;
+    let path = local_path(table, &schema, cache)?;
+    match table.format {
+        TableFormat::Csv => {
+            ensure!(
+                table.delimiter.is_ascii(),
+                "Delimiter of table {} should be an ASCII character",
+                table.name
+            );
+            let mut options = CsvReadOptions::new()
+                .delimiter(table.delimiter as u8)
+                .has_header(table.has_header);
+            if let Some(schema) = &schema {
+                options = options.schema(schema);
+            }
+            if let Some(extension) = &table.file_extension {
+                options = options.file_extension(extension);
+            }
+            ctx.register_csv(&table.name, &path, options)?;
+        }
+        TableFormat::Parquet => {
+            ensure!(
+                schema.is_none(),
+                "The schema of Parquet table {} is read from the files and should not be declared",
+                table.name
+            );
+            ctx.register_parquet(&table.name, &path)?
+        }
+    }
+    Ok(())
+}
+
+/// Register the tables. The clients also need the files of the S3 tables in the
+/// cache, their footers are fetched for the Parquet schemas, see `s3::sync_metadata`.
+pub async fn register_tables(ctx: &mut BallistaContext, tables: &[TableDefinition]) -> Result<()> {
+    let cache = S3Cache::from_env()?;
+    let mut client = None;
+    for table in tables {
+        let location = match s3_location(table, &cache)? {
+            Some(location) => location,
+            None => continue,
+        };
+        if client.is_none() {
+            client = Some(new_client()?);
+        }
+        let parquet = table.format == TableFormat::Parquet;
+        sync_metadata(client.as_ref().unwrap(), &cache, &location, parquet)
+            .await
+            .with_context(|| format!("Could not fetch the metadata of S3 table {}", table.name))?;
+    }
+    for table in tables {
+        register_table(ctx, table, &cache)?;
+    }
+    Ok(())
+}
+
+/// Parse a type name, case insensitive, with a few SQL aliases
+pub fn parse_data_type(name: &str) -> Result<DataType> {
+    let name = name.trim().to_lowercase();
+    // the CSV reader of the pinned arrow version can't parse decimals
+    if name.starts_with("decimal") || name.starts_with("numeric") {
+        bail!(
+            "Unsupported data type: {}, the CSV reader can't read decimals, use float64",
+            name
+        );
+    }
+    let data_type = match name.as_str() {
+        "boolean" | "bool" => DataType::Boolean,
+        "int8" | "tinyint" => DataType::Int8,
+        "int16" | "smallint" => DataType::Int16,
+        "int32" | "int" | "integer" => DataType::Int32,
+        "int64" | "bigint" => DataType::Int64,
+        "uint8" => DataType::UInt8,
+        "uint16" => DataType::UInt16,
+        "uint32" => DataType::UInt32,
+        "uint64" => DataType::UInt64,
+        "float32" | "float" | "real" => DataType::Float32,
+        "float64" | "double" => DataType::Float64,
+        "utf8" | "string" | "varchar" | "text" => DataType::Utf8,
+        "date32" | "date" => DataType::Date32,
+        "date64" => DataType::Date64,
+        "timestamp" => DataType::Timestamp(TimeUnit::Microsecond, None),
+        _ => bail!("Unknown data type: {}", name),
+    };
+    Ok(data_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_type_names() {
+        assert_eq!(parse_data_type("BIGINT").unwrap(), DataType::Int64);
+        assert_eq!(parse_data_type(" utf8 ").unwrap(), DataType::Utf8);
+        let err = parse_data_type("decimal(15, 2)").unwrap_err();
+        assert!(err.to_string().contains("can't read decimals"));
+        assert!(parse_data_type("NUMERIC").is_err());
+        assert!(parse_data_type("json").is_err());
+    }
+
+    #[test]
+    fn parse_catalog() {
+        let catalog: Catalog = toml::from_str(
+            r#"
+            [[tables]]
+            name = "nation"
+            path = "/mnt/data/nation/"
+            delimiter = "|"
+            schema = [{ name = "n_nationkey", type = "int64", nullable = false }]
+            "#,
+        )
+        .unwrap();
+        let table = &catalog.tables[0];
+        assert_eq!(table.format, TableFormat::Csv);
+        assert_eq!(table.delimiter, '|');
+        let schema = table.arrow_schema().unwrap().unwrap();
+        assert_eq!(schema.field(0).data_type(), &DataType::Int64);
+        assert!(!schema.field(0).is_nullable());
+
+        let ndjson = r#"{"tables": [{"name": "t", "path": "/t", "format": "ndjson"}]}"#;
+        assert!(serde_json::from_str::<Catalog>(ndjson).is_err());
+    }
+
+    #[test]
+    fn s3_tables_must_be_synced() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = S3Cache {
+            dir: dir.path().to_owned(),
+            synced: vec!["s3://tpch/nation/".parse().unwrap()],
+        };
+        let table = |path: &str, format: &str, schema: &str| -> TableDefinition {
+            serde_json::from_str(&format!(
+                r#"{{"name": "t", "path": "{}", "format": "{}" {}}}"#,
+                path, format, schema
+            ))
+            .unwrap()
+        };
+        let path =
+            |table: &TableDefinition| local_path(table, &table.arrow_schema().unwrap(), &cache);
+        let schema = r#", "schema": [{"name": "n_nationkey", "type": "int64"}]"#;
+
+        let nation = table("s3://tpch/nation/", "csv", schema);
+        assert_eq!(
+            path(&nation).unwrap(),
+            dir.path().join("tpch/nation/").to_string_lossy()
+        );
+        assert_eq!(
+            path(&table("/data/nation/", "csv", "")).unwrap(),
+            "/data/nation/"
+        );
+        // not synced
+        assert!(path(&table("s3://tpch/region/", "csv", schema)).is_err());
+        // the schema can't be inferred from the files
+        assert!(path(&table("s3://tpch/nation/", "csv", "")).is_err());
+        // read from the footers
+        assert!(path(&table("s3://tpch/nation/", "parquet", "")).is_ok());
+    }
+}
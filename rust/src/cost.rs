@@ -0,0 +1,299 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::fargate::TaskTimings;
+
+/// Fargate bills the tasks per second, with a minimum of one minute
+const FARGATE_MIN_BILLED_MS: u64 = 60_000;
+
+/// Lambda allocates the equivalent of one vCPU per 1769MB of memory
+const LAMBDA_MB_PER_VCPU: f64 = 1769.;
+
+/// Prices in USD, the defaults are the Linux/x86 prices of us-east-1
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PriceTable {
+    pub fargate_vcpu_hour: f64,
+    pub fargate_gb_hour: f64,
+    pub fargate_spot_vcpu_hour: f64,
+    pub fargate_spot_gb_hour: f64,
+    pub lambda_gb_second: f64,
+    pub lambda_request: f64,
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        Self {
+            fargate_vcpu_hour: 0.04048,
+            fargate_gb_hour: 0.004445,
+            fargate_spot_vcpu_hour: 0.01334053,
+            fargate_spot_gb_hour: 0.00146489,
+            lambda_gb_second: 0.0000166667,
+            lambda_request: 0.0000002,
+        }
+    }
+}
+
+impl PriceTable {
+    /// Read a TOML or JSON price table, the missing prices keep their default
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read price table {}", path))?;
+        let prices = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&content)?,
+            Some("json") => serde_json::from_str(&content)?,
+            _ => bail!("Price table {} should be a .toml or .json file", path),
+        };
+        Ok(prices)
+    }
+
+    /// The price table of the given file, or the default one
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        match path {
+            Some(path) => Self::from_file(path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Cost of running a task of the given size for `billed_ms`.
+    /// For Lambda, it is the cost of an invocation that lasted `billed_ms`.
+    pub fn cost(&self, launch_type: LaunchType, resources: &TaskResources, billed_ms: u64) -> f64 {
+        let hours = billed_ms as f64 / 3_600_000.;
+        match launch_type {
+            LaunchType::OnDemand => {
+                (resources.vcpu * self.fargate_vcpu_hour
+                    + resources.memory_gb * self.fargate_gb_hour)
+                    * hours
+            }
+            LaunchType::Spot => {
+                (resources.vcpu * self.fargate_spot_vcpu_hour
+                    + resources.memory_gb * self.fargate_spot_gb_hour)
+                    * hours
+            }
+            LaunchType::Lambda => {
+                resources.memory_gb * billed_ms as f64 / 1000. * self.lambda_gb_second
+                    + self.lambda_request
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LaunchType {
+    /// Fargate on-demand
+    OnDemand,
+    /// Fargate Spot
+    Spot,
+    Lambda,
+}
+
+impl LaunchType {
+    /// Launch type of a Fargate task, from the capacity provider reported by ECS
+    pub fn from_capacity_provider(capacity_provider: Option<&str>) -> Self {
+        match capacity_provider {
+            Some("FARGATE_SPOT") => LaunchType::Spot,
+            _ => LaunchType::OnDemand,
+        }
+    }
+}
+
+/// Size of a task or of a Lambda function
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TaskResources {
+    pub vcpu: f64,
+    pub memory_gb: f64,
+}
+
+impl TaskResources {
+    /// From the CPU units and the memory (MiB) of an ECS task definition
+    pub fn from_task_definition(cpu: Option<&str>, memory: Option<&str>) -> Result<Self> {
+        let cpu: f64 = cpu
+            .ok_or_else(|| anyhow!("Task definition cpu should not be undefined"))?
+            .parse()
+            .context("Task definition cpu should be in CPU units")?;
+        let memory: f64 = memory
+            .ok_or_else(|| anyhow!("Task definition memory should not be undefined"))?
+            .parse()
+            .context("Task definition memory should be in MiB")?;
+        Ok(Self {
+            vcpu: cpu / 1024.,
+            memory_gb: memory / 1024.,
+        })
+    }
+
+    /// From the memory (MB) of a Lambda function
+    pub fn from_lambda_memory(memory_mb: i64) -> Self {
+        Self {
+            vcpu: memory_mb as f64 / LAMBDA_MB_PER_VCPU,
+            memory_gb: memory_mb as f64 / 1024.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskCost {
+    pub task_arn: String,
+    pub launch_type: LaunchType,
+    #[serde(flatten)]
+    pub resources: TaskResources,
+    pub billed_ms: u64,
+    pub cost_usd: f64,
+}
+
+/// Estimated cost of a run, from the size and the billed duration of its tasks
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CostEstimate {
+    pub total_usd: f64,
+    /// cost of the Fargate tasks from the end of the run until the scheduler expires,
+    /// not included in the total as a following run can reuse the cluster
+    pub until_expiration_usd: f64,
+    pub tasks: Vec<TaskCost>,
+}
+
+impl CostEstimate {
+    pub fn add_task(
+        &mut self,
+        prices: &PriceTable,
+        task_arn: String,
+        launch_type: LaunchType,
+        resources: TaskResources,
+        billed_ms: u64,
+    ) {
+        let cost_usd = prices.cost(launch_type, &resources, billed_ms);
+        self.total_usd += cost_usd;
+        self.tasks.push(TaskCost {
+            task_arn,
+            launch_type,
+            resources,
+            billed_ms,
+            cost_usd,
+        });
+    }
+
+    /// Add a Fargate task of a run that ended `run_end_ms` after the start of the
+    /// provisioning. A task started by the run is billed from its image pull, with
+    /// the minimum of one minute, a reused task for the whole run.
+    pub fn add_fargate_task(
+        &mut self,
+        prices: &PriceTable,
+        timings: &TaskTimings,
+        resources: TaskResources,
+        run_end_ms: u64,
+        expiration_ms: u64,
+    ) {
+        let launch_type = LaunchType::from_capacity_provider(timings.capacity_provider.as_deref());
+        let billed_ms = if timings.reused {
+            run_end_ms
+        } else {
+            let start_ms = timings.pending_ms.unwrap_or(0);
+            run_end_ms
+                .saturating_sub(start_ms)
+                .max(FARGATE_MIN_BILLED_MS)
+        };
+        // billed per second
+        let billed_ms = (billed_ms + 999) / 1000 * 1000;
+        self.add_task(
+            prices,
+            timings.task_arn.clone(),
+            launch_type,
+            resources,
+            billed_ms,
+        );
+        self.until_expiration_usd += prices.cost(launch_type, &resources, expiration_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TASK: TaskResources = TaskResources {
+        vcpu: 2.,
+        memory_gb: 4.,
+    };
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn timings(
+        reused: bool,
+        pending_ms: Option<u64>,
+        capacity_provider: Option<&str>,
+    ) -> TaskTimings {
+        TaskTimings {
+            task_arn: "task".to_owned(),
+            reused,
+            run_task_ms: Some(500),
+            pending_ms,
+            capacity_provider: capacity_provider.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cost_per_launch_type() {
+        let prices = PriceTable::default();
+        let hour = 3_600_000;
+        let on_demand = 2. * 0.04048 + 4. * 0.004445;
+        assert_close(prices.cost(LaunchType::OnDemand, &TASK, hour), on_demand);
+        assert_close(
+            prices.cost(LaunchType::OnDemand, &TASK, hour / 2),
+            on_demand / 2.,
+        );
+        let spot = 2. * 0.01334053 + 4. * 0.00146489;
+        assert_close(prices.cost(LaunchType::Spot, &TASK, hour), spot);
+        // 4GB for 1.5s and the request
+        let lambda = 4. * 1.5 * 0.0000166667 + 0.0000002;
+        assert_close(prices.cost(LaunchType::Lambda, &TASK, 1500), lambda);
+        assert_close(prices.cost(LaunchType::Lambda, &TASK, 0), 0.0000002);
+    }
+
+    #[test]
+    fn fargate_billing_minimums() {
+        let prices = PriceTable::default();
+        let billed_ms = |timings: TaskTimings, run_end_ms: u64| {
+            let mut estimate = CostEstimate::default();
+            estimate.add_fargate_task(&prices, &timings, TASK, run_end_ms, 0);
+            estimate.tasks[0].billed_ms
+        };
+        // new tasks are billed from their image pull, at least one minute
+        assert_eq!(billed_ms(timings(false, Some(2_000), None), 30_000), 60_000);
+        assert_eq!(billed_ms(timings(false, Some(2_000), None), 92_000), 90_000);
+        // per second
+        assert_eq!(billed_ms(timings(false, Some(2_000), None), 92_001), 91_000);
+        // from the start of the provisioning when the pull was not observed
+        assert_eq!(billed_ms(timings(false, None, None), 92_000), 92_000);
+        // reused tasks for the whole run, without minimum
+        assert_eq!(billed_ms(timings(true, None, None), 30_000), 30_000);
+        assert_eq!(billed_ms(timings(true, None, None), 30_400), 31_000);
+    }
+
+    #[test]
+    fn fargate_spot_tasks() {
+        let prices = PriceTable::default();
+        let mut estimate = CostEstimate::default();
+        let spot = timings(true, None, Some("FARGATE_SPOT"));
+        estimate.add_fargate_task(&prices, &spot, TASK, 3_600_000, 1_800_000);
+        let on_demand = timings(true, None, Some("FARGATE"));
+        estimate.add_fargate_task(&prices, &on_demand, TASK, 3_600_000, 1_800_000);
+        assert_eq!(estimate.tasks[0].launch_type, LaunchType::Spot);
+        assert_eq!(estimate.tasks[1].launch_type, LaunchType::OnDemand);
+        let spot_hour = prices.cost(LaunchType::Spot, &TASK, 3_600_000);
+        let on_demand_hour = prices.cost(LaunchType::OnDemand, &TASK, 3_600_000);
+        assert_close(estimate.total_usd, spot_hour + on_demand_hour);
+        assert_close(
+            estimate.until_expiration_usd,
+            (spot_hour + on_demand_hour) / 2.,
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::TokioAsyncResolver;
+use uuid::Uuid;
+
+use crate::fargate::FargateCreationClient;
+
+/// Interval between two refreshes of the registration file by the scheduler
+const REGISTRATION_INTERVAL_SEC: u64 = 10;
+
+/// Age after which a registration file is considered left by a stopped scheduler
+const REGISTRATION_TTL_SEC: i64 = 3 * REGISTRATION_INTERVAL_SEC as i64;
+
+/// How the executors find the scheduler, parsed from `static`, `ecs`,
+/// `dns:<name>`, `srv:<name>` or `file:<path>`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiscoveryMethod {
+    /// the configured scheduler host
+    Static,
+    /// the private IP of the running scheduler task
+    Ecs,
+    /// the A records of the name, with the configured scheduler port
+    Dns(String),
+    /// the SRV records of the name, as created by Cloud Map
+    Srv(String),
+    /// the registration file written by the scheduler on a shared storage
+    File(String),
+}
+
+impl FromStr for DiscoveryMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "static" => Ok(Self::Static),
+            None if s == "ecs" => Ok(Self::Ecs),
+            Some(("dns", name)) if !name.is_empty() => Ok(Self::Dns(name.to_owned())),
+            Some(("srv", name)) if !name.is_empty() => Ok(Self::Srv(name.to_owned())),
+            Some(("file", path)) if !path.is_empty() => Ok(Self::File(path.to_owned())),
+            _ => Err(anyhow!("Unknown discovery method: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchedulerAddress {
+    pub host: String,
+    pub port: u16,
+}
+
+#[async_trait]
+pub trait SchedulerDiscovery: Send + Sync {
+    /// The address of the running scheduler, None if no scheduler is running
+    async fn locate(&self) -> Result<Option<SchedulerAddress>>;
+}
+
+/// Wait until a scheduler is found, as it might be started at the same time as the executor
+pub async fn wait_for_scheduler(
+    discovery: &dyn SchedulerDiscovery,
+    timeout: Duration,
+) -> Result<SchedulerAddress> {
+    let start = Instant::now();
+    loop {
+        match discovery.locate().await {
+            Ok(Some(address)) => return Ok(address),
+            Ok(None) => {}
+            Err(err) => warn!("scheduler discovery failed: {:#}", err),
+        }
+        if start.elapsed() > timeout {
+            bail!("Scheduler not found after {}s", timeout.as_secs());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// A scheduler at a fixed address, which is never reported as stopped
+pub struct StaticDiscovery {
+    address: SchedulerAddress,
+}
+
+impl StaticDiscovery {
+    pub fn new(host: String, port: u16) -> Self {
+        Self {
+            address: SchedulerAddress { host, port },
+        }
+    }
+}
+
+#[async_trait]
+impl SchedulerDiscovery for StaticDiscovery {
+    async fn locate(&self) -> Result<Option<SchedulerAddress>> {
+        Ok(Some(self.address.clone()))
+    }
+}
+
+/// The scheduler running as a task of the given definition, requires the ECS read permissions
+pub struct EcsDiscovery {
+    client: FargateCreationClient,
+    task_def_arn: String,
+    port: u16,
+}
+
+impl EcsDiscovery {
+    pub fn new(client: FargateCreationClient, task_def_arn: String, port: u16) -> Self {
+        Self {
+            client,
+            task_def_arn,
+            port,
+        }
+    }
+}
+
+#[async_trait]
+impl SchedulerDiscovery for EcsDiscovery {
+    async fn locate(&self) -> Result<Option<SchedulerAddress>> {
+        let task_arns = self
+            .client
+            .get_existing_tasks(self.task_def_arn.clone())
+            .await?;
+        let task_arn = match task_arns.into_iter().next() {
+            Some(task_arn) => task_arn,
+            None => return Ok(None),
+        };
+        // a scheduler still being provisioned is looked up again by the caller
+        let host = self.client.task_private_ip(task_arn).await?;
+        Ok(host.map(|host| SchedulerAddress {
+            host,
+            port: self.port,
+        }))
+    }
+}
+
+/// The scheduler registered under a DNS name, for instance by Cloud Map
+pub struct DnsDiscovery {
+    resolver: TokioAsyncResolver,
+    name: String,
+    /// port of the A records, None to look up SRV records
+    port: Option<u16>,
+}
+
+impl DnsDiscovery {
+    /// Resolve with the nameservers of the system configuration
+    pub fn try_new(name: String, port: Option<u16>) -> Result<Self> {
+        Ok(Self {
+            resolver: TokioAsyncResolver::tokio_from_system_conf()?,
+            name,
+            port,
+        })
+    }
+}
+
+/// None if the name has no record anymore
+fn no_records<T>(result: Result<T, ResolveError>) -> Result<Option<T>> {
+    match result {
+        Ok(lookup) => Ok(Some(lookup)),
+        Err(err) => match err.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => Ok(None),
+            _ => Err(err.into()),
+        },
+    }
+}
+
+#[async_trait]
+impl SchedulerDiscovery for DnsDiscovery {
+    async fn locate(&self) -> Result<Option<SchedulerAddress>> {
+        let port = match self.port {
+            Some(port) => port,
+            None => {
+                let lookup = self.resolver.srv_lookup(self.name.as_str()).await;
+                return Ok(no_records(lookup)?.and_then(|lookup| {
+                    lookup
+                        .iter()
+                        .min_by_key(|srv| srv.priority())
+                        .map(|srv| SchedulerAddress {
+                            host: srv.target().to_utf8().trim_end_matches('.').to_owned(),
+                            port: srv.port(),
+                        })
+                }));
+            }
+        };
+        let lookup = self.resolver.lookup_ip(self.name.as_str()).await;
+        Ok(no_records(lookup)?.and_then(|lookup| {
+            lookup.iter().next().map(|ip| SchedulerAddress {
+                host: ip.to_string(),
+                port,
+            })
+        }))
+    }
+}
+
+/// Written by the scheduler and refreshed while it runs
+#[derive(Debug, Serialize, Deserialize)]
+struct SchedulerRegistration {
+    #[serde(flatten)]
+    address: SchedulerAddress,
+    /// unix timestamp (s)
+    updated_at: i64,
+}
+
+/// The scheduler that registered itself in a file on a storage shared with the
+/// executors. A file that is not refreshed anymore is left by a stopped scheduler.
+pub struct FileDiscovery {
+    path: PathBuf,
+}
+
+impl FileDiscovery {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+        }
+    }
+}
+
+#[async_trait]
+impl SchedulerDiscovery for FileDiscovery {
+    async fn locate(&self) -> Result<Option<SchedulerAddress>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let registration: SchedulerRegistration = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid registration file {}", self.path.display()))?;
+        if chrono::Utc::now().timestamp() - registration.updated_at > REGISTRATION_TTL_SEC {
+            return Ok(None);
+        }
+        Ok(Some(registration.address))
+    }
+}
+
+/// Write the registration file, replacing it atomically
+pub fn write_registration(path: &str, address: &SchedulerAddress) -> Result<()> {
+    let path = Path::new(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let registration = SchedulerRegistration {
+        address: address.clone(),
+        updated_at: chrono::Utc::now().timestamp(),
+    };
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".{}", Uuid::new_v4()));
+    fs::write(&tmp_path, serde_json::to_vec(&registration)?)?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Could not write registration file {}", path.display()))
+}
+
+/// Refresh the registration file of the scheduler until the process exits
+pub async fn keep_registered(path: String, address: SchedulerAddress) {
+    info!("registering the scheduler at {:?} in {}", address, path);
+    let mut ticker = tokio::time::interval(Duration::from_secs(REGISTRATION_INTERVAL_SEC));
+    loop {
+        ticker.tick().await;
+        if let Err(err) = write_registration(&path, &address) {
+            warn!("could not register the scheduler: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_ecs::{FakeEcs, FakeEcsDelays};
+    use crate::fargate::EcsApi;
+    use rusoto_ecs::RunTaskRequest;
+    use std::sync::Arc;
+
+    #[test]
+    fn parse_discovery_methods() {
+        let parse = |s: &str| s.parse::<DiscoveryMethod>().ok();
+        assert_eq!(parse("static"), Some(DiscoveryMethod::Static));
+        assert_eq!(parse("ecs"), Some(DiscoveryMethod::Ecs));
+        assert_eq!(
+            parse("dns:scheduler.ballista.local"),
+            Some(DiscoveryMethod::Dns("scheduler.ballista.local".to_owned()))
+        );
+        assert_eq!(
+            parse("srv:_scheduler._tcp.ballista.local"),
+            Some(DiscoveryMethod::Srv(
+                "_scheduler._tcp.ballista.local".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse("file:/mnt/data/scheduler.json"),
+            Some(DiscoveryMethod::File("/mnt/data/scheduler.json".to_owned()))
+        );
+        for invalid in &["", "dns", "dns:", "file:", "ecs:x", "consul:x"] {
+            assert_eq!(parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn file_registration_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registry").join("scheduler.json");
+        let path = path.to_str().unwrap();
+        let discovery = FileDiscovery::new(path);
+        assert_eq!(discovery.locate().await.unwrap(), None);
+
+        let address = SchedulerAddress {
+            host: "10.0.0.1".to_owned(),
+            port: 50050,
+        };
+        write_registration(path, &address).unwrap();
+        assert_eq!(discovery.locate().await.unwrap(), Some(address.clone()));
+        // only the registration file is left
+        assert_eq!(
+            fs::read_dir(dir.path().join("registry")).unwrap().count(),
+            1
+        );
+
+        // not refreshed by a stopped scheduler
+        let stale = SchedulerRegistration {
+            address,
+            updated_at: chrono::Utc::now().timestamp() - REGISTRATION_TTL_SEC - 1,
+        };
+        fs::write(path, serde_json::to_vec(&stale).unwrap()).unwrap();
+        assert_eq!(discovery.locate().await.unwrap(), None);
+
+        fs::write(path, "not json").unwrap();
+        assert!(discovery.locate().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ecs_discovery_does_not_wait_for_the_provisioning() {
+        let delays = FakeEcsDelays {
+            api_call: Duration::from_millis(1),
+            provisioning: Duration::from_secs(60),
+            pending: Duration::from_secs(60),
+        };
+        let ecs = Arc::new(FakeEcs::new("localhost".to_owned(), delays));
+        let client = FargateCreationClient::with_api(ecs.clone(), "cluster".to_owned());
+        let discovery = EcsDiscovery::new(client, "standalone".to_owned(), 50050);
+        assert_eq!(discovery.locate().await.unwrap(), None);
+
+        // started, but without IP until the end of its provisioning
+        let request = RunTaskRequest {
+            task_definition: "standalone".to_owned(),
+            ..Default::default()
+        };
+        ecs.run_task(request).await.unwrap();
+        let locate = tokio::time::timeout(Duration::from_secs(5), discovery.locate());
+        assert_eq!(locate.await.unwrap().unwrap(), None);
+    }
+}
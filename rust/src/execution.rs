@@ -0,0 +1,252 @@
+//! The poll loop of the executors, as `ballista_executor::execution_loop`, but
+//! keeping the handles of the running tasks so that the tasks of a failed or
+//! cancelled job can be aborted.
+
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use ballista_core::serde::physical_plan::from_proto::parse_protobuf_hash_partitioning;
+use ballista_core::serde::protobuf::{
+    scheduler_grpc_client::SchedulerGrpcClient, task_status, CompletedTask, ExecutorRegistration,
+    FailedTask, PartitionId, PollWorkParams, ShuffleWritePartition, TaskDefinition, TaskStatus,
+};
+use ballista_executor::executor::Executor;
+use datafusion::physical_plan::ExecutionPlan;
+use log::{debug, error, info, warn};
+use tokio::task::JoinHandle;
+use tonic::transport::Channel;
+
+use crate::cancel::abort_failed_jobs;
+
+/// Handles of the running tasks of a job, by `{stage_id}/{partition_id}`
+type JobTasks = HashMap<String, JoinHandle<()>>;
+
+/// Handles of the tasks running on this executor, by job id
+#[derive(Debug, Clone, Default)]
+pub struct RunningTasks {
+    tasks: Arc<Mutex<HashMap<String, JobTasks>>>,
+}
+
+impl RunningTasks {
+    /// Abort the running tasks of the job, returns their number
+    pub fn abort_job(&self, job_id: &str) -> usize {
+        let tasks = self.tasks.lock().unwrap().remove(job_id);
+        let tasks = tasks.unwrap_or_default();
+        for handle in tasks.values() {
+            handle.abort();
+        }
+        tasks.len()
+    }
+
+    /// The jobs that have tasks running on this executor
+    pub fn job_ids(&self) -> BTreeSet<String> {
+        self.tasks.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Spawn the task, its handle is dropped from the registry once it ends
+    pub(crate) fn spawn<F>(&self, job_id: String, task: String, future: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        // the lock is held until the handle is registered, so that a task that
+        // ends right away cannot be unregistered before
+        let mut tasks = self.tasks.lock().unwrap();
+        let guard = TaskGuard {
+            tasks: self.clone(),
+            job_id: job_id.clone(),
+            task: task.clone(),
+        };
+        let handle = tokio::spawn(async move {
+            let _guard = guard;
+            future.await
+        });
+        tasks.entry(job_id).or_default().insert(task, handle);
+    }
+}
+
+/// Unregister the task when it ends or is aborted
+struct TaskGuard {
+    tasks: RunningTasks,
+    job_id: String,
+    task: String,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        let mut tasks = self.tasks.tasks.lock().unwrap();
+        if let Some(job_tasks) = tasks.get_mut(&self.job_id) {
+            job_tasks.remove(&self.task);
+            if job_tasks.is_empty() {
+                tasks.remove(&self.job_id);
+            }
+        }
+    }
+}
+
+/// Gives the slot of a task back when the task ends or is aborted
+struct SlotGuard(Arc<AtomicUsize>);
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+pub async fn poll_loop(
+    mut scheduler: SchedulerGrpcClient<Channel>,
+    executor: Arc<Executor>,
+    executor_meta: ExecutorRegistration,
+    concurrent_tasks: usize,
+    running: RunningTasks,
+) {
+    let available_task_slots = Arc::new(AtomicUsize::new(concurrent_tasks));
+    let (task_status_sender, task_status_receiver) = std::sync::mpsc::channel::<TaskStatus>();
+
+    loop {
+        let poll_work_result = scheduler
+            .poll_work(PollWorkParams {
+                metadata: Some(executor_meta.clone()),
+                can_accept_task: available_task_slots.load(Ordering::SeqCst) > 0,
+                task_status: sample_task_status(&task_status_receiver),
+            })
+            .await;
+
+        match poll_work_result {
+            Ok(result) => {
+                if let Some(task) = result.into_inner().task {
+                    let res = run_received_task(
+                        executor.clone(),
+                        executor_meta.id.clone(),
+                        available_task_slots.clone(),
+                        task_status_sender.clone(),
+                        &running,
+                        task,
+                    );
+                    if let Err(err) = res {
+                        warn!("Failed to run task: {:?}", err);
+                    }
+                }
+            }
+            Err(err) => {
+                warn!("Executor could not poll the scheduler: {}", err);
+            }
+        }
+        // tasks of cancelled jobs are aborted right away, their outputs are removed by the
+        // periodic cleanup
+        if let Err(err) = abort_failed_jobs(&mut scheduler, &running).await {
+            warn!(
+                "Executor could not abort the tasks of failed jobs: {:?}",
+                err
+            );
+        }
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+fn run_received_task(
+    executor: Arc<Executor>,
+    executor_id: String,
+    available_task_slots: Arc<AtomicUsize>,
+    task_status_sender: Sender<TaskStatus>,
+    running: &RunningTasks,
+    task: TaskDefinition,
+) -> Result<()> {
+    let task_id = task.task_id.ok_or_else(|| anyhow!("Task without id"))?;
+    let task_name = format!("{}/{}", task_id.stage_id, task_id.partition_id);
+    info!("Received task {}/{}", task_id.job_id, task_name);
+    let plan: Arc<dyn ExecutionPlan> =
+        (&task.plan.ok_or_else(|| anyhow!("Task without plan"))?).try_into()?;
+    let shuffle_output_partitioning =
+        parse_protobuf_hash_partitioning(task.output_partitioning.as_ref(), plan.as_ref())?;
+
+    available_task_slots.fetch_sub(1, Ordering::SeqCst);
+    let slot = SlotGuard(available_task_slots);
+    running.spawn(task_id.job_id.clone(), task_name, async move {
+        let _slot = slot;
+        let execution_result = executor
+            .execute_shuffle_write(
+                task_id.job_id.clone(),
+                task_id.stage_id as usize,
+                task_id.partition_id as usize,
+                plan,
+                shuffle_output_partitioning,
+            )
+            .await
+            .map_err(|err| anyhow!("{}", err));
+        debug!("Task {:?} result: {:?}", task_id, execution_result);
+        let _ = task_status_sender.send(as_task_status(execution_result, executor_id, task_id));
+    });
+    Ok(())
+}
+
+fn as_task_status(
+    execution_result: Result<Vec<ShuffleWritePartition>>,
+    executor_id: String,
+    task_id: PartitionId,
+) -> TaskStatus {
+    let status = match execution_result {
+        Ok(partitions) => {
+            info!("Task {:?} finished", task_id);
+            task_status::Status::Completed(CompletedTask {
+                executor_id,
+                partitions,
+            })
+        }
+        Err(err) => {
+            info!("Task {:?} failed: {}", task_id, err);
+            task_status::Status::Failed(FailedTask {
+                error: err.to_string(),
+            })
+        }
+    };
+    TaskStatus {
+        partition_id: Some(task_id),
+        status: Some(status),
+    }
+}
+
+fn sample_task_status(task_status_receiver: &Receiver<TaskStatus>) -> Vec<TaskStatus> {
+    let mut task_status = vec![];
+    loop {
+        match task_status_receiver.try_recv() {
+            Ok(status) => task_status.push(status),
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+                error!("Task status channel disconnected");
+                break;
+            }
+        }
+    }
+    task_status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn aborted_tasks_are_unregistered() {
+        let running = RunningTasks::default();
+        let slots = Arc::new(AtomicUsize::new(0));
+        for partition in 0..2 {
+            let slot = SlotGuard(slots.clone());
+            running.spawn("job".to_owned(), partition.to_string(), async move {
+                let _slot = slot;
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+            });
+        }
+        running.spawn("other".to_owned(), "0".to_owned(), async {});
+        assert_eq!(running.abort_job("job"), 2);
+        assert_eq!(running.abort_job("job"), 0);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // the slots are given back and the ended task is unregistered
+        assert_eq!(slots.load(Ordering::SeqCst), 2);
+        assert!(running.job_ids().is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rusoto_ecs::{
+    Attachment, DescribeTaskDefinitionRequest, DescribeTaskDefinitionResponse,
+    DescribeTasksRequest, DescribeTasksResponse, KeyValuePair, ListTasksRequest,
+    ListTasksResponse, RunTaskRequest, RunTaskResponse, StopTaskRequest, StopTaskResponse, Task,
+    TaskDefinition,
+};
+use uuid::Uuid;
+
+use crate::fargate::EcsApi;
+
+/// Simulated durations of the Fargate task lifecycle
+#[derive(Debug, Clone)]
+pub struct FakeEcsDelays {
+    /// latency of every API call
+    pub api_call: Duration,
+    /// time spent in PROVISIONING, the IP is attached when leaving it
+    pub provisioning: Duration,
+    /// time spent in PENDING (image pull, container start)
+    pub pending: Duration,
+}
+
+impl Default for FakeEcsDelays {
+    fn default() -> Self {
+        Self {
+            api_call: Duration::from_millis(100),
+            provisioning: Duration::from_secs(2),
+            pending: Duration::from_secs(5),
+        }
+    }
+}
+
+struct FakeTask {
+    family: String,
+    created: Instant,
+}
+
+/// An in-memory ECS backend where tasks go through the Fargate lifecycle
+/// without running anything. All tasks resolve to the same host, typically
+/// a local cluster started with docker-compose.
+pub struct FakeEcs {
+    host: String,
+    delays: FakeEcsDelays,
+    capacity_provider: Option<String>,
+    tasks: Mutex<HashMap<String, FakeTask>>,
+}
+
+impl FakeEcs {
+    pub fn new(host: String, delays: FakeEcsDelays) -> Self {
+        Self {
+            host,
+            delays,
+            capacity_provider: None,
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run the tasks on the given capacity provider, as with a cluster whose
+    /// default strategy is e.g. FARGATE_SPOT
+    pub fn with_capacity_provider(mut self, capacity_provider: String) -> Self {
+        self.capacity_provider = Some(capacity_provider);
+        self
+    }
+
+    fn describe(&self, task_arn: &str, task: &FakeTask) -> Task {
+        let age = task.created.elapsed();
+        let status = if age < self.delays.provisioning {
+            "PROVISIONING"
+        } else if age < self.delays.provisioning + self.delays.pending {
+            "PENDING"
+        } else {
+            "RUNNING"
+        };
+        let mut details = vec![];
+        if age >= self.delays.provisioning {
+            details.push(KeyValuePair {
+                name: Some("privateIPv4Address".to_owned()),
+                value: Some(self.host.clone()),
+            });
+        }
+        Task {
+            task_arn: Some(task_arn.to_owned()),
+            task_definition_arn: Some(task.family.clone()),
+            last_status: Some(status.to_owned()),
+            desired_status: Some("RUNNING".to_owned()),
+            capacity_provider_name: self.capacity_provider.clone(),
+            attachments: Some(vec![Attachment {
+                details: Some(details),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl EcsApi for FakeEcs {
+    /// The family of a fake task definition is its ARN, all have the same size
+    async fn describe_task_definition(
+        &self,
+        request: DescribeTaskDefinitionRequest,
+    ) -> Result<DescribeTaskDefinitionResponse> {
+        tokio::time::sleep(self.delays.api_call).await;
+        Ok(DescribeTaskDefinitionResponse {
+            task_definition: Some(TaskDefinition {
+                family: Some(request.task_definition.clone()),
+                task_definition_arn: Some(request.task_definition),
+                // size of the tasks of the terraform stack
+                cpu: Some("2048".to_owned()),
+                memory: Some("4096".to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    async fn list_tasks(&self, request: ListTasksRequest) -> Result<ListTasksResponse> {
+        tokio::time::sleep(self.delays.api_call).await;
+        let tasks = self.tasks.lock().unwrap();
+        let task_arns = tasks
+            .iter()
+            .filter(|(_, task)| request.family.as_ref() == Some(&task.family))
+            .map(|(task_arn, _)| task_arn.clone())
+            .collect();
+        Ok(ListTasksResponse {
+            task_arns: Some(task_arns),
+            ..Default::default()
+        })
+    }
+
+    async fn run_task(&self, request: RunTaskRequest) -> Result<RunTaskResponse> {
+        tokio::time::sleep(self.delays.api_call).await;
+        let task_arn = format!("arn:fake:ecs:task/{}", Uuid::new_v4());
+        let task = FakeTask {
+            family: request.task_definition,
+            created: Instant::now(),
+        };
+        let description = self.describe(&task_arn, &task);
+        self.tasks.lock().unwrap().insert(task_arn, task);
+        Ok(RunTaskResponse {
+            tasks: Some(vec![description]),
+            ..Default::default()
+        })
+    }
+
+    async fn describe_tasks(&self, request: DescribeTasksRequest) -> Result<DescribeTasksResponse> {
+        tokio::time::sleep(self.delays.api_call).await;
+        let tasks = self.tasks.lock().unwrap();
+        let descriptions = request
+            .tasks
+            .iter()
+            .filter_map(|task_arn| {
+                tasks
+                    .get(task_arn)
+                    .map(|task| self.describe(task_arn, task))
+            })
+            .collect();
+        Ok(DescribeTasksResponse {
+            tasks: Some(descriptions),
+            ..Default::default()
+        })
+    }
+
+    /// Stopped tasks are forgotten immediately
+    async fn stop_task(&self, request: StopTaskRequest) -> Result<StopTaskResponse> {
+        tokio::time::sleep(self.delays.api_call).await;
+        let task = self
+            .tasks
+            .lock()
+            .unwrap()
+            .remove(&request.task)
+            .map(|task| Task {
+                task_arn: Some(request.task.clone()),
+                task_definition_arn: Some(task.family),
+                last_status: Some("STOPPED".to_owned()),
+                desired_status: Some("STOPPED".to_owned()),
+                ..Default::default()
+            });
+        Ok(StopTaskResponse { task })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fargate::FargateCreationClient;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn task_timings_follow_the_lifecycle() {
+        let delays = FakeEcsDelays {
+            api_call: Duration::from_millis(1),
+            provisioning: Duration::from_millis(1500),
+            pending: Duration::from_millis(1000),
+        };
+        let client = FargateCreationClient::with_api(
+            Arc::new(FakeEcs::new("localhost".to_owned(), delays)),
+            "cluster".to_owned(),
+        );
+        let tasks = client
+            .get_or_provision("executor".to_owned(), "sg".to_owned(), vec![], 2)
+            .await
+            .unwrap();
+        assert_eq!(tasks.len(), 2);
+        for task in &tasks {
+            let timings = &task.timings;
+            assert_eq!(task.private_ip, "localhost");
+            assert!(!timings.reused);
+            let run_task_ms = timings.run_task_ms.unwrap();
+            let pending_ms = timings.pending_ms.unwrap();
+            let ip_assigned_ms = timings.ip_assigned_ms.unwrap();
+            let running_ms = timings.running_ms.unwrap();
+            assert!(run_task_ms <= pending_ms, "{:?}", timings);
+            assert!(pending_ms <= ip_assigned_ms, "{:?}", timings);
+            assert!(pending_ms < running_ms, "{:?}", timings);
+            assert!(ip_assigned_ms <= running_ms, "{:?}", timings);
+        }
+
+        let reused = client
+            .get_or_provision("executor".to_owned(), "sg".to_owned(), vec![], 1)
+            .await
+            .unwrap();
+        assert!(reused[0].timings.reused);
+        assert_eq!(reused[0].timings.run_task_ms, None);
+    }
+
+    #[tokio::test]
+    async fn spot_capacity_is_recorded_for_new_and_reused_tasks() {
+        let delays = FakeEcsDelays {
+            api_call: Duration::from_millis(1),
+            provisioning: Duration::from_millis(10),
+            pending: Duration::from_millis(10),
+        };
+        let ecs = FakeEcs::new("localhost".to_owned(), delays)
+            .with_capacity_provider("FARGATE_SPOT".to_owned());
+        let client = FargateCreationClient::with_api(Arc::new(ecs), "cluster".to_owned());
+        let spot = Some("FARGATE_SPOT".to_owned());
+        let tasks = client
+            .get_or_provision("executor".to_owned(), "sg".to_owned(), vec![], 1)
+            .await
+            .unwrap();
+        assert_eq!(tasks[0].timings.capacity_provider, spot);
+
+        let reused = client
+            .get_or_provision("executor".to_owned(), "sg".to_owned(), vec![], 2)
+            .await
+            .unwrap();
+        assert!(reused[0].timings.reused);
+        assert!(!reused[1].timings.reused);
+        for task in &reused {
+            assert_eq!(task.timings.capacity_provider, spot, "{:?}", task.timings);
+        }
+    }
+}
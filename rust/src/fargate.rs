@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::env;
 use std::str;
 use std::str::FromStr;
@@ -5,19 +6,69 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
 use futures::{Future, StreamExt, TryStreamExt};
 use hyper::{body::to_bytes, Body, Client, Uri};
 use log::info;
 use rusoto_core::Region;
 use rusoto_ecs::{
-    AwsVpcConfiguration, DescribeTaskDefinitionRequest, DescribeTasksRequest, Ecs, EcsClient,
-    ListTasksRequest, NetworkConfiguration, RunTaskRequest,
+    AwsVpcConfiguration, DescribeTaskDefinitionRequest, DescribeTaskDefinitionResponse,
+    DescribeTasksRequest, DescribeTasksResponse, Ecs, EcsClient, ListTasksRequest,
+    ListTasksResponse, NetworkConfiguration, RunTaskRequest, RunTaskResponse, StopTaskRequest,
+    StopTaskResponse, Task, TaskDefinition,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::time::timeout;
+use tracing::instrument;
+
+use crate::cost::TaskResources;
+
+/// The subset of the ECS API used to manage the Ballista tasks.
+/// It can be implemented by a fake backend to run the provisioning logic locally.
+#[async_trait]
+pub trait EcsApi: Send + Sync {
+    async fn describe_task_definition(
+        &self,
+        request: DescribeTaskDefinitionRequest,
+    ) -> Result<DescribeTaskDefinitionResponse>;
+
+    async fn list_tasks(&self, request: ListTasksRequest) -> Result<ListTasksResponse>;
+
+    async fn run_task(&self, request: RunTaskRequest) -> Result<RunTaskResponse>;
+
+    async fn describe_tasks(&self, request: DescribeTasksRequest) -> Result<DescribeTasksResponse>;
+
+    async fn stop_task(&self, request: StopTaskRequest) -> Result<StopTaskResponse>;
+}
+
+#[async_trait]
+impl EcsApi for EcsClient {
+    async fn describe_task_definition(
+        &self,
+        request: DescribeTaskDefinitionRequest,
+    ) -> Result<DescribeTaskDefinitionResponse> {
+        api_timeout(Ecs::describe_task_definition(self, request)).await
+    }
+
+    async fn list_tasks(&self, request: ListTasksRequest) -> Result<ListTasksResponse> {
+        api_timeout(Ecs::list_tasks(self, request)).await
+    }
+
+    async fn run_task(&self, request: RunTaskRequest) -> Result<RunTaskResponse> {
+        api_timeout(Ecs::run_task(self, request)).await
+    }
+
+    async fn describe_tasks(&self, request: DescribeTasksRequest) -> Result<DescribeTasksResponse> {
+        api_timeout(Ecs::describe_tasks(self, request)).await
+    }
+
+    async fn stop_task(&self, request: StopTaskRequest) -> Result<StopTaskResponse> {
+        api_timeout(Ecs::stop_task(self, request)).await
+    }
+}
 
 pub struct FargateCreationClient {
-    client: Arc<EcsClient>,
+    client: Arc<dyn EcsApi>,
     cluster_name: String,
 }
 
@@ -39,71 +90,148 @@ impl FargateCreationClient {
             cluster_name,
         })
     }
+
+    /// Create a client on top of an alternative implementation of the ECS API
+    pub fn with_api(client: Arc<dyn EcsApi>, cluster_name: String) -> Self {
+        Self {
+            client,
+            cluster_name,
+        }
+    }
+}
+
+/// Lifecycle timings of a task, in ms since the start of the provisioning.
+/// Status changes are the ones observed by the polling loop.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TaskTimings {
+    pub task_arn: String,
+    /// true if the task was already running and was not started by this call
+    pub reused: bool,
+    /// latency of the RunTask API call
+    pub run_task_ms: Option<u64>,
+    pub pending_ms: Option<u64>,
+    pub running_ms: Option<u64>,
+    pub ip_assigned_ms: Option<u64>,
+    /// FARGATE_SPOT for the tasks running on spot capacity
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capacity_provider: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProvisionedTask {
+    pub private_ip: String,
+    pub timings: TaskTimings,
 }
 
 impl FargateCreationClient {
     /// Create a new fargate task and returns private IP.
     /// The task might not be ready to receive requests yet.
+    #[instrument(skip(self))]
     pub async fn get_or_provision(
         &self,
         task_def_arn: String,
         task_sg_id: String,
         subnets: Vec<String>,
         count: usize,
-    ) -> Result<Vec<String>> {
+    ) -> Result<Vec<ProvisionedTask>> {
         let start = Instant::now();
 
         let mut task_arns = self.get_existing_tasks(task_def_arn.clone()).await?;
 
         task_arns.truncate(count);
 
-        let missing_task_count = count.saturating_sub(task_arns.len());
+        let mut timings: Vec<TaskTimings> = task_arns
+            .into_iter()
+            .map(|task_arn| TaskTimings {
+                task_arn,
+                reused: true,
+                ..Default::default()
+            })
+            .collect();
+
+        let missing_task_count = count.saturating_sub(timings.len());
 
         if missing_task_count > 0 {
             let future_tasks = (0..missing_task_count).map(|_| {
-                self.start_task(task_def_arn.clone(), task_sg_id.clone(), subnets.clone())
+                let (task_def_arn, task_sg_id, subnets) =
+                    (task_def_arn.clone(), task_sg_id.clone(), subnets.clone());
+                async move {
+                    let call_start = Instant::now();
+                    let task = self.start_task(task_def_arn, task_sg_id, subnets).await?;
+                    Ok::<_, anyhow::Error>(TaskTimings {
+                        task_arn: task
+                            .task_arn
+                            .context("RunTask did not return the task ARN")?,
+                        reused: false,
+                        run_task_ms: Some(call_start.elapsed().as_millis() as u64),
+                        capacity_provider: task.capacity_provider_name,
+                        ..Default::default()
+                    })
+                }
             });
-            let mut new_task_arns = futures::stream::iter(future_tasks)
+            let mut new_tasks = futures::stream::iter(future_tasks)
                 .buffer_unordered(5)
                 .try_collect::<Vec<_>>()
                 .await?;
-            task_arns.append(&mut new_task_arns);
+            timings.append(&mut new_tasks);
 
             info!("{} task started", missing_task_count);
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
 
-        let result = self.wait_for_provisioning(task_arns).await?;
+        let ips = self.wait_for_tasks(start, &mut timings).await?;
 
         info!(
             "took {}ms to create/find tasks",
             start.elapsed().as_millis()
         );
 
-        Ok(result)
+        Ok(ips
+            .into_iter()
+            .zip(timings)
+            .map(|(private_ip, timings)| ProvisionedTask {
+                private_ip,
+                timings,
+            })
+            .collect())
     }
 
-    /// Get the family of the given task definition.
-    /// TODO better error management
-    async fn get_task_family(&self, task_def_arn: String) -> Result<String> {
+    async fn describe_task_definition(&self, task_def_arn: String) -> Result<TaskDefinition> {
         let request = DescribeTaskDefinitionRequest {
             include: None,
             task_definition: task_def_arn,
         };
 
-        let result = api_timeout(self.client.describe_task_definition(request)).await?;
+        let result = self.client.describe_task_definition(request).await?;
 
-        // if request for existing task failed for any reason, return None
         result
             .task_definition
-            .ok_or(anyhow!("Task definition object should not be undefined"))?
+            .ok_or(anyhow!("Task definition object should not be undefined"))
+    }
+
+    /// Get the family of the given task definition.
+    #[instrument(skip(self))]
+    async fn get_task_family(&self, task_def_arn: String) -> Result<String> {
+        self.describe_task_definition(task_def_arn)
+            .await?
             .family
             .ok_or(anyhow!("Task definition family should not be undefined"))
     }
 
+    /// Get the vCPU and memory of the tasks of the given task definition.
+    #[instrument(skip(self))]
+    pub async fn task_resources(&self, task_def_arn: String) -> Result<TaskResources> {
+        let task_definition = self.describe_task_definition(task_def_arn).await?;
+        TaskResources::from_task_definition(
+            task_definition.cpu.as_deref(),
+            task_definition.memory.as_deref(),
+        )
+    }
+
     /// Get existing task ARNs.
+    #[instrument(skip(self))]
     pub async fn get_existing_tasks(&self, task_def_arn: String) -> Result<Vec<String>> {
-        let family = self.get_task_family(task_def_arn).await.unwrap();
+        let family = self.get_task_family(task_def_arn).await?;
 
         let request = ListTasksRequest {
             cluster: Some(self.cluster_name.clone()),
@@ -112,21 +240,22 @@ impl FargateCreationClient {
             ..Default::default()
         };
 
-        api_timeout(self.client.list_tasks(request))
+        self.client
+            .list_tasks(request)
             .await
             .context("failed to call Fargate list_tasks")?
             .task_arns
             .context("Task arn list was undefined")
     }
 
-    /// Start new task and return its arn
-    /// TODO better error management
+    /// Start new task and return its description
+    #[instrument(skip(self))]
     async fn start_task(
         &self,
         task_def_arn: String,
         security_group: String,
         subnets: Vec<String>,
-    ) -> Result<String> {
+    ) -> Result<Task> {
         let input = RunTaskRequest {
             task_definition: task_def_arn,
             count: Some(1),
@@ -140,7 +269,7 @@ impl FargateCreationClient {
             }),
             ..Default::default()
         };
-        let result = api_timeout(self.client.run_task(input)).await?;
+        let result = self.client.run_task(input).await?;
         if let Some(failures) = result.failures {
             if failures.len() > 0 {
                 return Err(anyhow!(
@@ -150,58 +279,149 @@ impl FargateCreationClient {
             }
         }
 
-        Ok(result
+        result
             .tasks
-            .unwrap()
-            .into_iter()
-            .next()
-            .unwrap()
-            .task_arn
-            .unwrap())
+            .and_then(|tasks| tasks.into_iter().next())
+            .context("RunTask did not return the created task")
+    }
+
+    /// Stop all the running tasks of the given task definition and return their arns
+    #[instrument(skip(self))]
+    pub async fn stop_tasks(&self, task_def_arn: String, reason: &str) -> Result<Vec<String>> {
+        let task_arns = self.get_existing_tasks(task_def_arn).await?;
+        futures::future::try_join_all(
+            task_arns
+                .iter()
+                .map(|task_arn| self.stop_task(task_arn.clone(), reason)),
+        )
+        .await?;
+        info!("{} task stopped", task_arns.len());
+        Ok(task_arns)
+    }
+
+    /// Stop a single task
+    #[instrument(skip(self))]
+    pub async fn stop_task(&self, task_arn: String, reason: &str) -> Result<()> {
+        self.client
+            .stop_task(StopTaskRequest {
+                cluster: Some(self.cluster_name.clone()),
+                task: task_arn,
+                reason: Some(reason.to_owned()),
+            })
+            .await
+            .context("failed to call Fargate stop_task")?;
+        Ok(())
+    }
+
+    /// The private IP of the task, None if it is not attributed yet or the task is gone
+    #[instrument(skip(self))]
+    pub async fn task_private_ip(&self, task_arn: String) -> Result<Option<String>> {
+        let input = DescribeTasksRequest {
+            cluster: Some(self.cluster_name.clone()),
+            tasks: vec![task_arn],
+            ..Default::default()
+        };
+        let description = self
+            .client
+            .describe_tasks(input)
+            .await?
+            .tasks
+            .context("Task description list was undefined")?;
+        Ok(description.first().and_then(private_ip))
     }
 
     /// Wait for the given task to be provisioned and attributed a private IP
-    /// TODO better error management
     /// TODO fargate container lifecycle
     pub async fn wait_for_provisioning(&self, task_arns: Vec<String>) -> Result<Vec<String>> {
+        let mut timings: Vec<TaskTimings> = task_arns
+            .into_iter()
+            .map(|task_arn| TaskTimings {
+                task_arn,
+                ..Default::default()
+            })
+            .collect();
+        self.wait_for_tasks(Instant::now(), &mut timings).await
+    }
+
+    /// Poll the given tasks until they all have a private IP, recording
+    /// their status changes. IPs are returned in the order of the tasks.
+    #[instrument(skip(self, start, tasks), fields(count = tasks.len()))]
+    async fn wait_for_tasks(
+        &self,
+        start: Instant,
+        tasks: &mut [TaskTimings],
+    ) -> Result<Vec<String>> {
+        if tasks.is_empty() {
+            return Ok(vec![]);
+        }
         loop {
             let input = DescribeTasksRequest {
                 cluster: Some(self.cluster_name.clone()),
-                tasks: task_arns.clone(),
+                tasks: tasks.iter().map(|t| t.task_arn.clone()).collect(),
                 ..Default::default()
             };
-            let description = api_timeout(self.client.describe_tasks(input))
+            let description = self
+                .client
+                .describe_tasks(input)
                 .await?
                 .tasks
-                .unwrap();
-
-            let mut ips = vec![];
-
-            for _ in 0..task_arns.len() {
-                let attachment_props = description[0].attachments.as_ref().unwrap()[0]
-                    .details
-                    .as_ref()
-                    .unwrap();
-
-                for prop in attachment_props {
-                    if let Some(ref key) = prop.name {
-                        if key == "privateIPv4Address" && prop.value.as_ref().is_some() {
-                            ips.push(prop.value.as_ref().unwrap().clone());
-                        }
+                .context("Task description list was undefined")?;
+            let elapsed = start.elapsed().as_millis() as u64;
+
+            let mut ips = HashMap::new();
+            for task in &description {
+                let timings = match tasks
+                    .iter_mut()
+                    .find(|t| Some(&t.task_arn) == task.task_arn.as_ref())
+                {
+                    Some(timings) => timings,
+                    None => continue,
+                };
+                match task.last_status.as_deref() {
+                    Some("PENDING") => {
+                        timings.pending_ms.get_or_insert(elapsed);
+                    }
+                    Some("RUNNING") => {
+                        timings.pending_ms.get_or_insert(elapsed);
+                        timings.running_ms.get_or_insert(elapsed);
                     }
+                    _ => {}
+                }
+                if let Some(provider) = &task.capacity_provider_name {
+                    timings
+                        .capacity_provider
+                        .get_or_insert_with(|| provider.clone());
+                }
+                if let Some(ip) = private_ip(task) {
+                    timings.ip_assigned_ms.get_or_insert(elapsed);
+                    ips.insert(timings.task_arn.clone(), ip);
                 }
             }
-            if ips.len() == task_arns.len() {
-                return Ok(ips);
+            if ips.len() == tasks.len() {
+                return Ok(tasks
+                    .iter()
+                    .map(|t| ips.remove(&t.task_arn).unwrap())
+                    .collect());
             }
             tokio::time::sleep(Duration::from_millis(200)).await;
         }
     }
 }
 
+/// Get the private IP from the network interface attached to the task
+fn private_ip(task: &Task) -> Option<String> {
+    task.attachments
+        .as_ref()?
+        .iter()
+        .filter_map(|attachment| attachment.details.as_ref())
+        .flatten()
+        .find(|prop| prop.name.as_deref() == Some("privateIPv4Address"))
+        .and_then(|prop| prop.value.clone())
+}
+
 //// Fargate Client ////
 
-fn new_client(region: &str) -> Arc<EcsClient> {
+fn new_client(region: &str) -> Arc<dyn EcsApi> {
     let region = Region::from_str(region).unwrap();
     Arc::new(EcsClient::new(region))
 }
@@ -0,0 +1,748 @@
+//! A subset of the Arrow Flight SQL protocol on top of a Ballista context.
+//! The pinned arrow-flight version does not include the Flight SQL messages,
+//! so the ones that are supported are declared here.
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use arrow_flight::flight_service_server::FlightService;
+use arrow_flight::utils::flight_data_from_arrow_batch;
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint,
+    FlightInfo, HandshakeRequest, HandshakeResponse, IpcMessage, PutResult, SchemaAsIpc,
+    SchemaResult, Ticket,
+};
+use ballista::context::BallistaContext;
+use ballista::prelude::BallistaConfig;
+use ballista_core::serde::protobuf::PartitionLocation;
+use ballista_core::BALLISTA_VERSION;
+use datafusion::arrow::array::{
+    ArrayRef, BinaryArray, BooleanArray, Int32Array, Int64Array, StringArray, UInt32Array,
+    UnionArray,
+};
+use datafusion::arrow::buffer::Buffer;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::ipc::writer::IpcWriteOptions;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::logical_plan::LogicalPlan;
+use futures::{Stream, StreamExt};
+use log::{info, warn};
+use prost::Message;
+use tokio::sync::mpsc::{self, Sender};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+use uuid::Uuid;
+
+use crate::query::{open_partition, JobClient};
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/arrow.flight.protocol.sql.";
+
+/// Catalog and schema reported for all the tables
+const CATALOG_NAME: &str = "ballista";
+const SCHEMA_NAME: &str = "public";
+
+/// Prepared statements kept at the same time, the clients should close theirs
+const MAX_PREPARED_STATEMENTS: usize = 1024;
+
+/// Messages buffered between the fetches of the results and the client
+const RESULT_CHANNEL_SIZE: usize = 4;
+
+// ids of the `SqlInfo` enum of the Flight SQL protocol
+const SQL_INFO_SERVER_NAME: u32 = 0;
+const SQL_INFO_SERVER_VERSION: u32 = 1;
+const SQL_INFO_SERVER_READ_ONLY: u32 = 3;
+const SQL_INFO_IDENTIFIER_QUOTE_CHAR: u32 = 504;
+
+type BoxedFlightStream<T> =
+    Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+
+//// Flight SQL messages ////
+
+/// `google.protobuf.Any`, the envelope of the Flight SQL commands
+#[derive(Clone, PartialEq, Message)]
+pub struct Any {
+    #[prost(string, tag = "1")]
+    pub type_url: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CommandStatementQuery {
+    #[prost(string, tag = "1")]
+    pub query: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CommandPreparedStatementQuery {
+    #[prost(bytes = "vec", tag = "1")]
+    pub prepared_statement_handle: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ActionCreatePreparedStatementRequest {
+    #[prost(string, tag = "1")]
+    pub query: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ActionCreatePreparedStatementResult {
+    #[prost(bytes = "vec", tag = "1")]
+    pub prepared_statement_handle: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub dataset_schema: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub parameter_schema: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ActionClosePreparedStatementRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub prepared_statement_handle: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CommandGetCatalogs {}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CommandGetDbSchemas {
+    #[prost(string, optional, tag = "1")]
+    pub catalog: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub db_schema_filter_pattern: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CommandGetTables {
+    #[prost(string, optional, tag = "1")]
+    pub catalog: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub db_schema_filter_pattern: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub table_name_filter_pattern: Option<String>,
+    #[prost(string, repeated, tag = "4")]
+    pub table_types: Vec<String>,
+    #[prost(bool, tag = "5")]
+    pub include_schema: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CommandGetTableTypes {}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CommandGetSqlInfo {
+    /// the requested `SqlInfo` ids, all the supported ones if empty
+    #[prost(uint32, repeated, tag = "1")]
+    pub info: Vec<u32>,
+}
+
+fn pack<T: Message>(name: &str, message: &T) -> Vec<u8> {
+    Any {
+        type_url: format!("{}{}", TYPE_URL_PREFIX, name),
+        value: message.encode_to_vec(),
+    }
+    .encode_to_vec()
+}
+
+fn unpack<T: Message + Default>(any: &Any) -> Result<T, Status> {
+    T::decode(any.value.as_slice()).map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+fn decode_any(bytes: &[u8]) -> Result<Any, Status> {
+    Any::decode(bytes).map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+/// Name of the Flight SQL message in the envelope
+fn command_name(any: &Any) -> &str {
+    any.type_url
+        .strip_prefix(TYPE_URL_PREFIX)
+        .unwrap_or(&any.type_url)
+}
+
+/// Whether the value matches a SQL LIKE pattern with `%` and `_` wildcards
+fn like(pattern: Option<&str>, value: &str) -> bool {
+    fn matches(pattern: &[char], value: &[char]) -> bool {
+        match pattern.split_first() {
+            None => value.is_empty(),
+            Some(('%', rest)) => (0..=value.len()).any(|i| matches(rest, &value[i..])),
+            Some(('_', rest)) => !value.is_empty() && matches(rest, &value[1..]),
+            Some((c, rest)) => value.first() == Some(c) && matches(rest, &value[1..]),
+        }
+    }
+    match pattern {
+        None => true,
+        Some(pattern) => {
+            let pattern: Vec<char> = pattern.chars().collect();
+            let value: Vec<char> = value.chars().collect();
+            matches(&pattern, &value)
+        }
+    }
+}
+
+fn internal(err: impl std::fmt::Display) -> Status {
+    Status::internal(format!("{:#}", err))
+}
+
+//// Service ////
+
+/// Results of a command
+struct CommandResult {
+    schema: SchemaRef,
+    batches: Vec<RecordBatch>,
+}
+
+/// Metadata results, computed in memory as a single batch
+fn batch_result(fields: Vec<Field>, arrays: Vec<ArrayRef>) -> Result<CommandResult, Status> {
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), arrays).map_err(internal)?;
+    Ok(CommandResult {
+        schema,
+        batches: vec![batch],
+    })
+}
+
+/// Metadata results, where all the columns are strings
+fn string_result(fields: Vec<Field>, columns: Vec<Vec<String>>) -> Result<CommandResult, Status> {
+    let arrays = columns
+        .into_iter()
+        .map(|column| Arc::new(StringArray::from(column)) as ArrayRef)
+        .collect();
+    batch_result(fields, arrays)
+}
+
+enum SqlInfoValue {
+    String(String),
+    Bool(bool),
+}
+
+fn sql_info(id: u32) -> Option<SqlInfoValue> {
+    match id {
+        SQL_INFO_SERVER_NAME => Some(SqlInfoValue::String("Ballista".to_owned())),
+        SQL_INFO_SERVER_VERSION => Some(SqlInfoValue::String(BALLISTA_VERSION.to_owned())),
+        SQL_INFO_SERVER_READ_ONLY => Some(SqlInfoValue::Bool(true)),
+        SQL_INFO_IDENTIFIER_QUOTE_CHAR => Some(SqlInfoValue::String("\"".to_owned())),
+        _ => None,
+    }
+}
+
+/// The requested server infos, the ids that are not supported are rejected. The
+/// values are a dense union whose variants are declared up to the last one used
+/// here: the `string_list` and `int32_to_int32_list_map` variants of the protocol
+/// are only used by the infos that are not supported.
+fn sql_info_result(command: &CommandGetSqlInfo) -> Result<CommandResult, Status> {
+    let ids = match command.info.as_slice() {
+        [] => vec![
+            SQL_INFO_SERVER_NAME,
+            SQL_INFO_SERVER_VERSION,
+            SQL_INFO_SERVER_READ_ONLY,
+            SQL_INFO_IDENTIFIER_QUOTE_CHAR,
+        ],
+        ids => ids.to_vec(),
+    };
+    let mut names = vec![];
+    let mut type_ids: Vec<i8> = vec![];
+    let mut offsets: Vec<i32> = vec![];
+    let mut strings = vec![];
+    let mut bools = vec![];
+    for id in ids {
+        match sql_info(id) {
+            Some(SqlInfoValue::String(value)) => {
+                type_ids.push(0);
+                offsets.push(strings.len() as i32);
+                strings.push(value);
+            }
+            Some(SqlInfoValue::Bool(value)) => {
+                type_ids.push(1);
+                offsets.push(bools.len() as i32);
+                bools.push(value);
+            }
+            None => {
+                return Err(Status::invalid_argument(format!(
+                    "Unsupported SqlInfo id: {}",
+                    id
+                )))
+            }
+        }
+        names.push(id);
+    }
+    let values = UnionArray::try_new(
+        Buffer::from_slice_ref(&type_ids),
+        Some(Buffer::from_slice_ref(&offsets)),
+        vec![
+            (
+                Field::new("string_value", DataType::Utf8, false),
+                Arc::new(StringArray::from(strings)) as ArrayRef,
+            ),
+            (
+                Field::new("bool_value", DataType::Boolean, false),
+                Arc::new(BooleanArray::from(bools)),
+            ),
+            (
+                Field::new("bigint_value", DataType::Int64, false),
+                Arc::new(Int64Array::from(Vec::<i64>::new())),
+            ),
+            (
+                Field::new("int32_bitmask", DataType::Int32, false),
+                Arc::new(Int32Array::from(Vec::<i32>::new())),
+            ),
+        ],
+        None,
+    )
+    .map_err(internal)?;
+    batch_result(
+        vec![
+            Field::new("info_name", DataType::UInt32, false),
+            Field::new("value", values.data_type().clone(), false),
+        ],
+        vec![Arc::new(UInt32Array::from(names)), Arc::new(values)],
+    )
+}
+
+/// Flight SQL service that plans the queries with Ballista against the registered tables.
+/// The flight info only plans the query to return its schema, the ticket is the
+/// command itself, which is run when the ticket is redeemed.
+pub struct FlightSqlService {
+    ctx: Arc<BallistaContext>,
+    config: BallistaConfig,
+    scheduler_host: String,
+    scheduler_port: u16,
+    tables: Vec<String>,
+    prepared_statements: Mutex<HashMap<Vec<u8>, String>>,
+}
+
+impl FlightSqlService {
+    /// `ctx` should have the `tables` registered
+    pub fn new(
+        ctx: Arc<BallistaContext>,
+        config: BallistaConfig,
+        scheduler_host: String,
+        scheduler_port: u16,
+        tables: Vec<String>,
+    ) -> Self {
+        Self {
+            ctx,
+            config,
+            scheduler_host,
+            scheduler_port,
+            tables,
+            prepared_statements: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run the query on the cluster, returns its schema and the location of its results
+    async fn run_query(&self, sql: &str) -> Result<(SchemaRef, Vec<PartitionLocation>), Status> {
+        info!("Flight SQL query: {}", sql);
+        let plan = self
+            .ctx
+            .sql(sql)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?
+            .to_logical_plan();
+        let schema = Arc::new(plan.schema().as_ref().clone().into());
+        // the context registers the table, there is no job to run
+        if let LogicalPlan::CreateExternalTable { .. } = plan {
+            return Ok((schema, vec![]));
+        }
+        let mut client =
+            JobClient::try_new(&self.scheduler_host, self.scheduler_port, &self.config)
+                .await
+                .map_err(internal)?;
+        let job_id = client.submit(&plan).await.map_err(internal)?;
+        let locations = client.wait(&job_id).await.map_err(internal)?;
+        Ok((schema, locations))
+    }
+
+    fn query_schema(&self, sql: &str) -> Result<Schema, Status> {
+        let plan = self
+            .ctx
+            .sql(sql)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?
+            .to_logical_plan();
+        Ok(plan.schema().as_ref().clone().into())
+    }
+
+    fn prepared_statement(
+        &self,
+        command: &CommandPreparedStatementQuery,
+    ) -> Result<String, Status> {
+        self.prepared_statements
+            .lock()
+            .unwrap()
+            .get(&command.prepared_statement_handle)
+            .cloned()
+            .ok_or_else(|| Status::not_found("Unknown prepared statement"))
+    }
+
+    fn get_tables(&self, command: &CommandGetTables) -> Result<CommandResult, Status> {
+        let visible = command.catalog.as_deref().map_or(true, |c| c == CATALOG_NAME)
+            && like(command.db_schema_filter_pattern.as_deref(), SCHEMA_NAME)
+            && (command.table_types.is_empty()
+                || command.table_types.iter().any(|t| t == "TABLE"));
+        let tables: Vec<String> = self
+            .tables
+            .iter()
+            .filter(|table| visible && like(command.table_name_filter_pattern.as_deref(), table))
+            .cloned()
+            .collect();
+        let count = tables.len();
+        let mut fields = vec![
+            Field::new("catalog_name", DataType::Utf8, true),
+            Field::new("db_schema_name", DataType::Utf8, true),
+            Field::new("table_name", DataType::Utf8, false),
+            Field::new("table_type", DataType::Utf8, false),
+        ];
+        let mut arrays: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec![CATALOG_NAME; count])),
+            Arc::new(StringArray::from(vec![SCHEMA_NAME; count])),
+            Arc::new(StringArray::from(tables.clone())),
+            Arc::new(StringArray::from(vec!["TABLE"; count])),
+        ];
+        if command.include_schema {
+            let schemas = tables
+                .iter()
+                .map(|table| schema_bytes(&self.query_schema(&format!("SELECT * FROM {}", table))?))
+                .collect::<Result<Vec<_>, Status>>()?;
+            fields.push(Field::new("table_schema", DataType::Binary, false));
+            let schemas: Vec<&[u8]> = schemas.iter().map(|schema| schema.as_slice()).collect();
+            arrays.push(Arc::new(BinaryArray::from(schemas)));
+        }
+        batch_result(fields, arrays)
+    }
+
+    /// The schema of the results of the command, without running it
+    fn command_schema(&self, any: &Any) -> Result<SchemaRef, Status> {
+        match command_name(any) {
+            "CommandStatementQuery" => {
+                let command: CommandStatementQuery = unpack(any)?;
+                Ok(Arc::new(self.query_schema(&command.query)?))
+            }
+            "CommandPreparedStatementQuery" => {
+                let sql = self.prepared_statement(&unpack(any)?)?;
+                Ok(Arc::new(self.query_schema(&sql)?))
+            }
+            _ => Ok(self.metadata_command(any)?.schema),
+        }
+    }
+
+    /// Commands that describe the server and its tables, computed in memory
+    fn metadata_command(&self, any: &Any) -> Result<CommandResult, Status> {
+        match command_name(any) {
+            "CommandGetCatalogs" => string_result(
+                vec![Field::new("catalog_name", DataType::Utf8, false)],
+                vec![vec![CATALOG_NAME.to_owned()]],
+            ),
+            "CommandGetDbSchemas" => {
+                let command: CommandGetDbSchemas = unpack(any)?;
+                let visible = command.catalog.as_deref().map_or(true, |c| c == CATALOG_NAME)
+                    && like(command.db_schema_filter_pattern.as_deref(), SCHEMA_NAME);
+                let rows = if visible { 1 } else { 0 };
+                string_result(
+                    vec![
+                        Field::new("catalog_name", DataType::Utf8, true),
+                        Field::new("db_schema_name", DataType::Utf8, false),
+                    ],
+                    vec![
+                        vec![CATALOG_NAME.to_owned(); rows],
+                        vec![SCHEMA_NAME.to_owned(); rows],
+                    ],
+                )
+            }
+            "CommandGetTables" => self.get_tables(&unpack(any)?),
+            "CommandGetTableTypes" => string_result(
+                vec![Field::new("table_type", DataType::Utf8, false)],
+                vec![vec!["TABLE".to_owned()]],
+            ),
+            "CommandGetSqlInfo" => sql_info_result(&unpack(any)?),
+            _ => Err(Status::unimplemented(format!(
+                "Unsupported Flight SQL command: {}",
+                any.type_url
+            ))),
+        }
+    }
+}
+
+/// Send the schema, then the batches of the result partitions as they are read.
+/// The schema of the first partition is sent, as the one of the plan can differ in
+/// nullability and metadata.
+async fn send_results(
+    plan_schema: SchemaRef,
+    locations: &[PartitionLocation],
+    shuffle_host: &str,
+    sender: &Sender<Result<FlightData, Status>>,
+) -> Result<()> {
+    let options = IpcWriteOptions::default();
+    let send = |message: FlightData| async move {
+        sender
+            .send(Ok(message))
+            .await
+            .map_err(|_| anyhow!("The client closed the stream"))
+    };
+    let mut schema_sent = false;
+    for location in locations {
+        let mut stream = open_partition(location, shuffle_host).await?;
+        if !schema_sent {
+            send(SchemaAsIpc::new(&stream.schema(), &options).into()).await?;
+            schema_sent = true;
+        }
+        while let Some(batch) = stream.next().await {
+            let (dictionaries, data) = flight_data_from_arrow_batch(&batch?, &options);
+            for message in dictionaries {
+                send(message).await?;
+            }
+            send(data).await?;
+        }
+    }
+    if !schema_sent {
+        send(SchemaAsIpc::new(&plan_schema, &options).into()).await?;
+    }
+    Ok(())
+}
+
+fn schema_bytes(schema: &Schema) -> Result<Vec<u8>, Status> {
+    let options = IpcWriteOptions::default();
+    let message = IpcMessage::try_from(SchemaAsIpc::new(schema, &options)).map_err(internal)?;
+    Ok(message.0)
+}
+
+#[tonic::async_trait]
+impl FlightService for FlightSqlService {
+    type HandshakeStream = BoxedFlightStream<HandshakeResponse>;
+    type ListFlightsStream = BoxedFlightStream<FlightInfo>;
+    type DoGetStream = BoxedFlightStream<FlightData>;
+    type DoPutStream = BoxedFlightStream<PutResult>;
+    type DoActionStream = BoxedFlightStream<arrow_flight::Result>;
+    type ListActionsStream = BoxedFlightStream<ActionType>;
+    type DoExchangeStream = BoxedFlightStream<FlightData>;
+
+    /// No authentication, the scheduler is only reachable from the VPC
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        let response = HandshakeResponse {
+            protocol_version: 0,
+            payload: vec![],
+        };
+        Ok(Response::new(Box::pin(futures::stream::iter(vec![Ok(response)]))))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented("list_flights"))
+    }
+
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let descriptor = request.into_inner();
+        let any = decode_any(&descriptor.cmd)?;
+        let schema = schema_bytes(&self.command_schema(&any)?)?;
+        Ok(Response::new(FlightInfo {
+            schema,
+            endpoint: vec![FlightEndpoint {
+                ticket: Some(Ticket {
+                    ticket: descriptor.cmd.clone(),
+                }),
+                location: vec![],
+            }],
+            flight_descriptor: Some(descriptor),
+            total_records: -1,
+            total_bytes: -1,
+        }))
+    }
+
+    async fn get_schema(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        let any = decode_any(&request.into_inner().cmd)?;
+        Ok(Response::new(SchemaResult {
+            schema: schema_bytes(&self.command_schema(&any)?)?,
+        }))
+    }
+
+    /// Run the command of the ticket and stream its results. The batches of the
+    /// queries are sent as they are fetched from the executors.
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let any = decode_any(&request.into_inner().ticket)?;
+        let sql = match command_name(&any) {
+            "CommandStatementQuery" => unpack::<CommandStatementQuery>(&any)?.query,
+            "CommandPreparedStatementQuery" => self.prepared_statement(&unpack(&any)?)?,
+            _ => {
+                let result = self.metadata_command(&any)?;
+                let options = IpcWriteOptions::default();
+                let mut messages: Vec<Result<FlightData, Status>> =
+                    vec![Ok(SchemaAsIpc::new(&result.schema, &options).into())];
+                for batch in &result.batches {
+                    let (dictionaries, data) = flight_data_from_arrow_batch(batch, &options);
+                    messages.extend(dictionaries.into_iter().map(Ok));
+                    messages.push(Ok(data));
+                }
+                return Ok(Response::new(Box::pin(futures::stream::iter(messages))));
+            }
+        };
+        let (schema, locations) = self.run_query(&sql).await?;
+        let (sender, receiver) = mpsc::channel(RESULT_CHANNEL_SIZE);
+        let shuffle_host = self.scheduler_host.clone();
+        tokio::spawn(async move {
+            if let Err(err) = send_results(schema, &locations, &shuffle_host, &sender).await {
+                warn!("Could not send the Flight SQL results: {:?}", err);
+                // the client may be gone already
+                let _ = sender.send(Err(internal(err))).await;
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("Updates are not supported"))
+    }
+
+    async fn do_action(
+        &self,
+        request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        let action = request.into_inner();
+        let any = decode_any(&action.body)?;
+        let body = match action.r#type.as_str() {
+            "CreatePreparedStatement" => {
+                let request: ActionCreatePreparedStatementRequest = unpack(&any)?;
+                let dataset_schema = schema_bytes(&self.query_schema(&request.query)?)?;
+                let handle = Uuid::new_v4().as_bytes().to_vec();
+                let mut prepared_statements = self.prepared_statements.lock().unwrap();
+                if prepared_statements.len() >= MAX_PREPARED_STATEMENTS {
+                    return Err(Status::resource_exhausted(format!(
+                        "At most {} prepared statements can be open",
+                        MAX_PREPARED_STATEMENTS
+                    )));
+                }
+                prepared_statements.insert(handle.clone(), request.query);
+                pack(
+                    "ActionCreatePreparedStatementResult",
+                    &ActionCreatePreparedStatementResult {
+                        prepared_statement_handle: handle,
+                        dataset_schema,
+                        parameter_schema: vec![],
+                    },
+                )
+            }
+            "ClosePreparedStatement" => {
+                let request: ActionClosePreparedStatementRequest = unpack(&any)?;
+                self.prepared_statements
+                    .lock()
+                    .unwrap()
+                    .remove(&request.prepared_statement_handle);
+                vec![]
+            }
+            action => {
+                return Err(Status::unimplemented(format!(
+                    "Unsupported action: {}",
+                    action
+                )))
+            }
+        };
+        let result = arrow_flight::Result { body };
+        Ok(Response::new(Box::pin(futures::stream::iter(vec![Ok(result)]))))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        let actions = ["CreatePreparedStatement", "ClosePreparedStatement"]
+            .iter()
+            .map(|action| {
+                Ok(ActionType {
+                    r#type: action.to_string(),
+                    description: String::new(),
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(Response::new(Box::pin(futures::stream::iter(actions))))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("do_exchange"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn like_patterns() {
+        assert!(like(None, "lineitem"));
+        assert!(like(Some("lineitem"), "lineitem"));
+        assert!(like(Some("line%"), "lineitem"));
+        assert!(like(Some("%item"), "lineitem"));
+        assert!(like(Some("%"), ""));
+        assert!(like(Some("n_tion"), "nation"));
+        assert!(!like(Some("n_tion"), "ntion"));
+        assert!(!like(Some("line"), "lineitem"));
+        assert!(!like(Some("LINE%"), "lineitem"));
+    }
+
+    #[test]
+    fn decode_commands() {
+        let command = CommandGetTables {
+            table_name_filter_pattern: Some("line%".to_owned()),
+            include_schema: true,
+            ..Default::default()
+        };
+        let any = decode_any(&pack("CommandGetTables", &command)).unwrap();
+        assert_eq!(command_name(&any), "CommandGetTables");
+        assert_eq!(unpack::<CommandGetTables>(&any).unwrap(), command);
+
+        let any = Any {
+            type_url: "CommandStatementQuery".to_owned(),
+            value: vec![],
+        };
+        assert_eq!(command_name(&any), "CommandStatementQuery");
+        assert!(decode_any(b"\xff").is_err());
+    }
+
+    #[test]
+    fn sql_info_values() {
+        let all = sql_info_result(&CommandGetSqlInfo::default()).unwrap();
+        assert_eq!(all.batches[0].num_rows(), 4);
+        let command = CommandGetSqlInfo {
+            info: vec![SQL_INFO_SERVER_READ_ONLY, 9999, SQL_INFO_SERVER_NAME],
+        };
+        let err = sql_info_result(&command).err().unwrap();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        let command = CommandGetSqlInfo {
+            info: vec![SQL_INFO_SERVER_READ_ONLY, SQL_INFO_SERVER_NAME],
+        };
+        let result = sql_info_result(&command).unwrap();
+        let names = result.batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt32Array>()
+            .unwrap();
+        assert_eq!(
+            names.values(),
+            &[SQL_INFO_SERVER_READ_ONLY, SQL_INFO_SERVER_NAME]
+        );
+        let values = result.batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<UnionArray>()
+            .unwrap();
+        assert_eq!(values.type_id(0), 1);
+        assert_eq!(values.type_id(1), 0);
+    }
+}
@@ -0,0 +1,125 @@
+use std::env;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::future::try_join_all;
+use log::info;
+use rusoto_core::Region;
+use rusoto_lambda::{
+    GetFunctionConfigurationRequest, InvocationRequest, Lambda, LambdaClient,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::timeout;
+use tracing::instrument;
+
+use crate::cost::TaskResources;
+use crate::fargate::TaskTimings;
+use crate::SharedShuffle;
+
+/// Event sent by the trigger to each Lambda executor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LambdaExecutorEvent {
+    pub scheduler_host: String,
+    pub scheduler_port: u16,
+    #[serde(default = "default_concurrent_tasks")]
+    pub concurrent_tasks: usize,
+    /// required for the other executors to read the outputs of the Lambda executor
+    #[serde(default)]
+    pub shuffle: Option<SharedShuffle>,
+}
+
+fn default_concurrent_tasks() -> usize {
+    1
+}
+
+/// Size of the executor function. The executors run until their timeout,
+/// so each invocation is billed for about the whole timeout.
+#[derive(Debug, Clone, Copy)]
+pub struct LambdaFunctionSize {
+    pub resources: TaskResources,
+    pub timeout_ms: u64,
+}
+
+/// Starts executors as asynchronous invocations of the executor Lambda function
+pub struct LambdaExecutorClient {
+    client: LambdaClient,
+    function_name: String,
+}
+
+impl LambdaExecutorClient {
+    pub fn try_new(function_name: String) -> Result<Self> {
+        let aws_region = env::var("AWS_REGION")?;
+        Ok(Self {
+            client: LambdaClient::new(Region::from_str(&aws_region)?),
+            function_name,
+        })
+    }
+
+    /// Invoke `count` executors without waiting for them to register.
+    /// The timings only contain the latency of the Invoke API call.
+    #[instrument(skip(self))]
+    pub async fn invoke_executors(
+        &self,
+        event: &LambdaExecutorEvent,
+        count: usize,
+    ) -> Result<Vec<TaskTimings>> {
+        let payload = serde_json::to_vec(event)?;
+        let invocations = (0..count).map(|index| {
+            let payload = payload.clone();
+            async move {
+                let start = Instant::now();
+                let request = InvocationRequest {
+                    function_name: self.function_name.clone(),
+                    invocation_type: Some("Event".to_owned()),
+                    payload: Some(payload.into()),
+                    ..Default::default()
+                };
+                let response = timeout(Duration::from_secs(5), self.client.invoke(request))
+                    .await
+                    .context("Query to Lambda API timed out")??;
+                if response.status_code != Some(202) {
+                    bail!(
+                        "Invocation of {} failed with status {:?}",
+                        self.function_name,
+                        response.status_code
+                    );
+                }
+                Ok(TaskTimings {
+                    task_arn: format!("{}/{}", self.function_name, index),
+                    reused: false,
+                    run_task_ms: Some(start.elapsed().as_millis() as u64),
+                    ..Default::default()
+                })
+            }
+        });
+        let timings = try_join_all(invocations).await?;
+        info!("invoked {} Lambda executors", timings.len());
+        Ok(timings)
+    }
+
+    /// Get the memory and the timeout of the executor function
+    #[instrument(skip(self))]
+    pub async fn function_size(&self) -> Result<LambdaFunctionSize> {
+        let request = GetFunctionConfigurationRequest {
+            function_name: self.function_name.clone(),
+            qualifier: None,
+        };
+        let configuration = timeout(
+            Duration::from_secs(5),
+            self.client.get_function_configuration(request),
+        )
+        .await
+        .context("Query to Lambda API timed out")??;
+        let memory_mb = configuration
+            .memory_size
+            .ok_or_else(|| anyhow!("Function memory size should not be undefined"))?;
+        let timeout_sec = configuration
+            .timeout
+            .ok_or_else(|| anyhow!("Function timeout should not be undefined"))?;
+        Ok(LambdaFunctionSize {
+            resources: TaskResources::from_lambda_memory(memory_mb),
+            timeout_ms: timeout_sec as u64 * 1000,
+        })
+    }
+}
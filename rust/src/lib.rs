@@ -2,33 +2,46 @@ use std::process::exit;
 use std::str;
 use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use arrow_flight::flight_service_server::FlightServiceServer;
 use hyper::{body::to_bytes, Body, Client, Method, Request, Uri};
 
-use log::{debug, info};
-use serde::Deserialize;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 use tempfile::TempDir;
 use tonic::transport::Server as TonicServer;
+use tracing::instrument;
 use uuid::Uuid;
 
 use ballista_core::serde::protobuf::{
     executor_registration, scheduler_grpc_client::SchedulerGrpcClient, ExecutorRegistration,
 };
 use ballista_core::BALLISTA_VERSION;
-use ballista_executor::execution_loop;
 use ballista_executor::executor::Executor;
 use ballista_executor::flight_service::BallistaFlightService;
+use ballista_scheduler::state::ConfigBackendClient;
+
+use crate::cancel::{cleanup_jobs, has_active_jobs};
+use crate::execution::{self, RunningTasks};
+use crate::s3::{sync_configured_locations, S3Cache};
 
 ////////////////////////////////////////////////////////////
 
+/// Executors that did not poll the scheduler for this long are considered gone.
+/// The scheduler keeps them registered, for instance the Lambda executors of a
+/// previous run, but they poll every 250ms while they are alive.
+const EXECUTOR_SEEN_MS: u64 = 10_000;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct RegisteredExecutors {
     pub id: String,
     pub host: String,
     pub port: u16,
+    /// last poll of the executor, in ms since the epoch
+    #[serde(default)]
+    pub last_seen: Option<u64>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -36,21 +49,45 @@ pub struct SchedulerState {
     pub executors: Vec<RegisteredExecutors>,
 }
 
-/// connects to the scheduler and waits until there are sufficient executors connected
+impl SchedulerState {
+    /// The executors that polled the scheduler recently
+    pub fn alive_executors(&self, now_ms: u64) -> impl Iterator<Item = &RegisteredExecutors> {
+        self.executors.iter().filter(move |executor| {
+            executor
+                .last_seen
+                .map_or(true, |seen| now_ms.saturating_sub(seen) <= EXECUTOR_SEEN_MS)
+        })
+    }
+}
+
+/// Time at which an executor was first seen registered by `wait_executors`
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutorRegistrationTiming {
+    pub id: String,
+    pub host: String,
+    pub registered_ms: u64,
+}
+
+/// connects to the scheduler and waits until there are sufficient executors connected,
+/// only counting the executors that polled the scheduler recently
+#[instrument]
 pub async fn wait_executors(
     scheduler_host: &str,
     scheduler_port: u16,
     min_executor_count: usize,
-) -> Result<()> {
+) -> Result<Vec<ExecutorRegistrationTiming>> {
+    let start = Instant::now();
     let uri: Uri = format!("http://{}:{}/state", scheduler_host, scheduler_port).parse()?;
     let client = Client::new();
+    let mut registrations: Vec<ExecutorRegistrationTiming> = vec![];
     loop {
-        let req = Request::builder()
+        let mut req = Request::builder()
             .method(Method::GET)
             .uri(uri.clone())
             .header(hyper::header::ACCEPT, "application/json")
             .header("x-lifetime", "extend")
             .body(Body::empty())?;
+        telemetry::inject_http_headers(req.headers_mut());
 
         let resp = match client.request(req).await {
             Ok(resp) => resp,
@@ -69,8 +106,22 @@ pub async fn wait_executors(
                 str::from_utf8(&body_bytes).unwrap()
             )
         })?;
-        if state.executors.len() >= min_executor_count {
-            return Ok(());
+        let elapsed = start.elapsed().as_millis() as u64;
+        let now_ms = chrono::Utc::now().timestamp_millis() as u64;
+        let alive: Vec<_> = state.alive_executors(now_ms).collect();
+        for executor in &alive {
+            if !registrations.iter().any(|r| r.id == executor.id) {
+                registrations.push(ExecutorRegistrationTiming {
+                    id: executor.id.clone(),
+                    host: executor.host.clone(),
+                    registered_ms: elapsed,
+                });
+            }
+        }
+        if alive.len() >= min_executor_count {
+            // executors seen in a previous poll may have stopped since
+            registrations.retain(|r| alive.iter().any(|executor| executor.id == r.id));
+            return Ok(registrations);
         }
         tokio::time::sleep(Duration::from_millis(200)).await;
     }
@@ -96,6 +147,22 @@ fn connect(
     .boxed()
 }
 
+/// Host registered by the executors of a shared shuffle as location of their outputs
+pub const SHARED_SHUFFLE_HOST: &str = "localhost";
+
+/// Shuffle outputs written to a storage shared by all the executors, such as EFS,
+/// so they outlive the executor that wrote them. The executors register a loopback
+/// location for their outputs, so that each executor reads the outputs of the
+/// others from the shared storage with its own Flight service. All the executors
+/// sharing the storage should bind the same port. The clients read the outputs from
+/// the storage if they mount it, or through the standalone executor otherwise, see
+/// `query::fetch_partition`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedShuffle {
+    /// directory on the shared storage where the work dir of the executor is created
+    pub dir: String,
+}
+
 pub async fn start_executor(
     bind_host: String,
     bind_port: u16,
@@ -103,6 +170,7 @@ pub async fn start_executor(
     scheduler_port: u16,
     optional_host: Option<String>,
     concurrent_tasks: usize,
+    shared_shuffle: Option<SharedShuffle>,
 ) -> Result<()> {
     let addr = format!("{}:{}", bind_host, bind_port);
     let addr = addr
@@ -111,7 +179,18 @@ pub async fn start_executor(
 
     let scheduler_url = format!("http://{}:{}", scheduler_host, scheduler_port);
 
-    let work_dir = TempDir::new()?
+    // the data must be available before the executor receives tasks
+    sync_configured_locations(&S3Cache::from_env()?).await?;
+
+    let work_dir = match &shared_shuffle {
+        Some(shuffle) => {
+            std::fs::create_dir_all(&shuffle.dir)?;
+            TempDir::new_in(&shuffle.dir)
+                .with_context(|| format!("Could not create work dir in {}", shuffle.dir))?
+        }
+        None => TempDir::new()?,
+    };
+    let work_dir = work_dir
         .into_path()
         .into_os_string()
         .into_string()
@@ -120,6 +199,11 @@ pub async fn start_executor(
     info!("work_dir: {}", work_dir);
     info!("concurrent_tasks: {}", concurrent_tasks);
 
+    // the scheduler only uses the registered endpoint as location of the task outputs
+    let optional_host = match &shared_shuffle {
+        Some(_) => Some(SHARED_SHUFFLE_HOST.to_owned()),
+        None => optional_host,
+    };
     let executor_meta = ExecutorRegistration {
         id: Uuid::new_v4().to_string(), // assign this executor a unique ID
         optional_host: optional_host.map(executor_registration::OptionalHost::Host),
@@ -137,24 +221,37 @@ pub async fn start_executor(
         "Ballista v{} Rust Executor listening on {:?}",
         BALLISTA_VERSION, addr
     );
-    let server_future = tokio::spawn(TonicServer::builder().add_service(server).serve(addr));
-    tokio::spawn(execution_loop::poll_loop(
-        scheduler,
+    let running = RunningTasks::default();
+    let poll_loop = execution::poll_loop(
+        scheduler.clone(),
         executor,
         executor_meta,
         concurrent_tasks,
-    ));
-
-    server_future
-        .await
-        .context("Tokio error")?
-        .context("Could not start executor server")?;
+        running.clone(),
+    );
+    let shared_dir = shared_shuffle.map(|shuffle| shuffle.dir);
+    // the components are not spawned so that dropping this future stops the executor
+    tokio::select! {
+        res = TonicServer::builder().add_service(server).serve(addr) => {
+            res.context("Could not start executor server")?;
+        }
+        _ = poll_loop => {
+            info!("Executor poll loop stopped");
+        }
+        _ = cleanup_jobs(scheduler, work_dir, shared_dir, running) => {}
+    }
     Ok(())
 }
 
 //////////////////////////////////////////////////////
 
-pub fn shutdown_ticker(task_expiration_sec: i64) -> Arc<AtomicI64> {
+/// Exit once no request extended the lifetime of the task for `task_expiration_sec`
+/// and no job of the scheduler state is still queued or running
+pub fn shutdown_ticker(
+    task_expiration_sec: i64,
+    config_backend: Arc<dyn ConfigBackendClient>,
+    namespace: String,
+) -> Arc<AtomicI64> {
     let last_query = Arc::new(AtomicI64::new(chrono::Utc::now().timestamp()));
     let last_query_ref = Arc::clone(&last_query);
     tokio::spawn(async move {
@@ -162,13 +259,22 @@ pub fn shutdown_ticker(task_expiration_sec: i64) -> Arc<AtomicI64> {
         loop {
             interval.tick().await;
             let elapsed = chrono::Utc::now().timestamp() - last_query_ref.load(Ordering::Relaxed);
-            if elapsed >= task_expiration_sec {
-                info!(
-                    "task expired after {}s of inactivity, shutting down...",
-                    elapsed
-                );
-                exit(0);
+            if elapsed < task_expiration_sec {
+                continue;
+            }
+            match has_active_jobs(config_backend.as_ref(), &namespace).await {
+                Ok(true) => {
+                    debug!("task expired but jobs are still running");
+                    continue;
+                }
+                Ok(false) => {}
+                Err(err) => warn!("could not read the jobs of the scheduler: {:?}", err),
             }
+            info!(
+                "task expired after {}s of inactivity, shutting down...",
+                elapsed
+            );
+            exit(0);
         }
     });
     last_query
@@ -176,5 +282,51 @@ pub fn shutdown_ticker(task_expiration_sec: i64) -> Arc<AtomicI64> {
 
 ///////////////////////////////////////////////////////
 
+pub mod autoscale;
+pub mod batch;
+pub mod bench;
+pub mod cancel;
+pub mod catalog;
+pub mod cost;
+pub mod discovery;
+pub mod execution;
+pub mod fake_ecs;
 pub mod fargate;
+pub mod flight_sql;
+pub mod lambda;
+pub mod query;
+pub mod rest;
+pub mod result_cache;
+pub mod results;
+pub mod s3;
+pub mod telemetry;
 pub mod tpch;
+pub mod tpchgen;
+pub mod verify;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_executors_are_not_alive() {
+        let state: SchedulerState = serde_json::from_str(
+            r#"{"executors": [
+                {"id": "alive", "host": "10.0.0.1", "port": 50051, "last_seen": 100000},
+                {"id": "gone", "host": "10.0.0.2", "port": 50051, "last_seen": 50000},
+                {"id": "unknown", "host": "10.0.0.3", "port": 50051}
+            ], "started": 0}"#,
+        )
+        .unwrap();
+        let alive = |now_ms| {
+            state
+                .alive_executors(now_ms)
+                .map(|executor| executor.id.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(alive(105_000), vec!["alive", "unknown"]);
+        // the clock of the scheduler can be ahead
+        assert_eq!(alive(99_000), vec!["alive", "unknown"]);
+        assert_eq!(alive(200_000), vec!["unknown"]);
+    }
+}
@@ -0,0 +1,680 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ballista::context::BallistaContext;
+use ballista::prelude::BallistaConfig;
+use ballista_core::client::BallistaClient;
+use ballista_core::serde::protobuf::{
+    execute_query_params::Query, job_status, scheduler_grpc_client::SchedulerGrpcClient,
+    ExecuteQueryParams, GetJobStatusParams, KeyValuePair, LogicalPlanNode, PartitionLocation,
+};
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::arrow::ipc::reader::FileReader;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::logical_plan::LogicalPlan;
+use datafusion::physical_plan::common::collect;
+use datafusion::physical_plan::memory::MemoryStream;
+use datafusion::physical_plan::{RecordBatchStream, SendableRecordBatchStream};
+use futures::future;
+use hyper::{body::to_bytes, Body, Client, Method, Request, StatusCode};
+use log::debug;
+use serde::Serialize;
+use serde_json::Value;
+use tonic::metadata::MetadataValue;
+use tonic::transport::Channel;
+use tracing::{info_span, instrument, Instrument};
+
+use crate::cancel::CancelOutcome;
+use crate::telemetry::{inject_http_headers, traced_request};
+use crate::SHARED_SHUFFLE_HOST;
+
+/// Durations of the phases of a query run, in ms
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueryTimings {
+    /// SQL parsing and logical planning on the client
+    pub planning_ms: u64,
+    /// from the job submission to its completion on the cluster
+    pub execution_ms: u64,
+    /// download of the result partitions from the executors
+    pub result_transfer_ms: u64,
+}
+
+/// A client that submits logical plans to the scheduler and fetches their results.
+/// Unlike `DataFrame::collect()`, every gRPC call carries the current trace context.
+pub struct JobClient {
+    host: String,
+    scheduler_url: String,
+    scheduler: SchedulerGrpcClient<Channel>,
+    settings: Vec<KeyValuePair>,
+}
+
+impl JobClient {
+    pub async fn try_new(host: &str, port: u16, config: &BallistaConfig) -> Result<Self> {
+        let scheduler_url = format!("http://{}:{}", host, port);
+        let scheduler = SchedulerGrpcClient::connect(scheduler_url.clone())
+            .await
+            .with_context(|| format!("Connection failed to scheduler at {}", scheduler_url))?;
+        let settings = config
+            .settings()
+            .iter()
+            .map(|(key, value)| KeyValuePair {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect();
+        Ok(Self {
+            host: host.to_owned(),
+            scheduler_url,
+            scheduler,
+            settings,
+        })
+    }
+
+    /// A traced request that also extends the lifetime of the scheduler,
+    /// like the `x-lifetime` header of `wait_executors`
+    fn request<T>(message: T) -> tonic::Request<T> {
+        let mut request = traced_request(message);
+        request
+            .metadata_mut()
+            .insert("x-lifetime", MetadataValue::from_static("extend"));
+        request
+    }
+
+    /// Submit the plan for execution and return the job id
+    #[instrument(skip(self, plan))]
+    pub async fn submit(&mut self, plan: &LogicalPlan) -> Result<String> {
+        let plan: LogicalPlanNode = plan.try_into()?;
+        let params = ExecuteQueryParams {
+            query: Some(Query::LogicalPlan(plan)),
+            settings: self.settings.clone(),
+        };
+        let job_id = self
+            .scheduler
+            .execute_query(Self::request(params))
+            .await?
+            .into_inner()
+            .job_id;
+        debug!("submitted job {}", job_id);
+        Ok(job_id)
+    }
+
+    /// Get the current status of the given job
+    pub async fn status(&mut self, job_id: &str) -> Result<job_status::Status> {
+        self.try_status(job_id)
+            .await?
+            .ok_or_else(|| anyhow!("Scheduler returned no status for job {}", job_id))
+    }
+
+    /// Get the current status of the given job, None if the scheduler does not know it
+    pub async fn try_status(&mut self, job_id: &str) -> Result<Option<job_status::Status>> {
+        let params = GetJobStatusParams {
+            job_id: job_id.to_owned(),
+        };
+        Ok(self
+            .scheduler
+            .get_job_status(Self::request(params))
+            .await?
+            .into_inner()
+            .status
+            .and_then(|status| status.status))
+    }
+
+    /// Cancel the job through the `/jobs/{job_id}/cancel` endpoint of the standalone scheduler
+    #[instrument(skip(self))]
+    pub async fn cancel(&self, job_id: &str) -> Result<CancelOutcome> {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{}/jobs/{}/cancel", self.scheduler_url, job_id))
+            .header("x-lifetime", "extend")
+            .body(Body::empty())?;
+        inject_http_headers(req.headers_mut());
+        let resp = Client::new().request(req).await?;
+        let status = resp.status();
+        let body = to_bytes(resp.into_body()).await?;
+        match status {
+            StatusCode::OK => Ok(serde_json::from_slice(&body)?),
+            StatusCode::NOT_FOUND => bail!("Unknown job {}", job_id),
+            _ => bail!(
+                "Could not cancel job {}: {}",
+                job_id,
+                String::from_utf8_lossy(&body)
+            ),
+        }
+    }
+
+    /// Poll the scheduler until the job completes and return the location of its output
+    #[instrument(skip(self))]
+    pub async fn wait(&mut self, job_id: &str) -> Result<Vec<PartitionLocation>> {
+        loop {
+            match self.status(job_id).await? {
+                job_status::Status::Queued(_) | job_status::Status::Running(_) => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                job_status::Status::Failed(failed) => {
+                    bail!("Job {} failed: {}", job_id, failed.error)
+                }
+                job_status::Status::Completed(completed) => {
+                    return Ok(completed.partition_location)
+                }
+            }
+        }
+    }
+
+    /// Submit the plan, wait for its completion and fetch all the result batches
+    pub async fn execute(&mut self, plan: &LogicalPlan) -> Result<Vec<RecordBatch>> {
+        let job_id = self.submit(plan).await?;
+        let locations = self.wait(&job_id).await?;
+        fetch_results(locations, &self.host).await
+    }
+
+    /// Host of the scheduler, that also serves the shared shuffle outputs
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+}
+
+/// Status of a submitted job as returned to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct JobState {
+    pub job_id: String,
+    /// queued, running, failed or completed
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// number of result partitions, once completed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partitions: Option<usize>,
+}
+
+impl JobState {
+    pub fn new(job_id: &str, status: &job_status::Status) -> Self {
+        let (status, error, partitions) = match status {
+            job_status::Status::Queued(_) => ("queued", None, None),
+            job_status::Status::Running(_) => ("running", None, None),
+            job_status::Status::Failed(failed) => ("failed", Some(failed.error.clone()), None),
+            job_status::Status::Completed(completed) => {
+                ("completed", None, Some(completed.partition_location.len()))
+            }
+        };
+        Self {
+            job_id: job_id.to_owned(),
+            status: status.to_owned(),
+            error,
+            partitions,
+        }
+    }
+}
+
+/// Output of the last statement of a query run
+pub struct StatementsOutput {
+    pub schema: SchemaRef,
+    pub batches: Vec<RecordBatch>,
+    pub timings: QueryTimings,
+}
+
+/// Run the statements in order on the cluster and return the results of the last one.
+/// DDL statements such as CREATE EXTERNAL TABLE only register tables in the context.
+/// Timings are summed over all the statements.
+#[instrument(skip(ctx, config, statements))]
+pub async fn run_statements(
+    ctx: &BallistaContext,
+    host: &str,
+    port: u16,
+    config: &BallistaConfig,
+    statements: &[String],
+) -> Result<StatementsOutput> {
+    let mut output = StatementsOutput {
+        schema: Arc::new(Schema::empty()),
+        batches: vec![],
+        timings: QueryTimings::default(),
+    };
+    // submit through our own client so that the trace context reaches the scheduler
+    let mut client = JobClient::try_new(host, port, config).await?;
+    for sql in statements {
+        debug!("Running query: {}", sql);
+        let start = Instant::now();
+        let plan =
+            info_span!("plan_sql").in_scope(|| ctx.sql(sql).map(|df| df.to_logical_plan()))?;
+        debug!("plan: {:?}", &plan);
+        output.timings.planning_ms += start.elapsed().as_millis() as u64;
+        if let LogicalPlan::CreateExternalTable { .. } = plan {
+            continue;
+        }
+
+        let start = Instant::now();
+        let job_id = client.submit(&plan).await?;
+        let locations = client.wait(&job_id).await?;
+        output.timings.execution_ms += start.elapsed().as_millis() as u64;
+
+        let start = Instant::now();
+        output.batches = fetch_results(locations, host).await?;
+        output.timings.result_transfer_ms += start.elapsed().as_millis() as u64;
+        // the plan schema can differ from the batches in nullability and metadata
+        output.schema = match output.batches.first() {
+            Some(batch) => batch.schema(),
+            None => Arc::new(plan.schema().as_ref().clone().into()),
+        };
+    }
+    Ok(output)
+}
+
+/// Run all the statements but the last one, then submit the last one without
+/// waiting for its completion and return its job id
+#[instrument(skip(ctx, config, statements))]
+pub async fn submit_statements(
+    ctx: &BallistaContext,
+    host: &str,
+    port: u16,
+    config: &BallistaConfig,
+    statements: &[String],
+) -> Result<String> {
+    let (last, leading) = statements
+        .split_last()
+        .ok_or_else(|| anyhow!("No statement to submit"))?;
+    run_statements(ctx, host, port, config, leading).await?;
+    let plan = ctx.sql(last)?.to_logical_plan();
+    if let LogicalPlan::CreateExternalTable { .. } = plan {
+        bail!("The last statement of a submitted job should be a query");
+    }
+    JobClient::try_new(host, port, config)
+        .await?
+        .submit(&plan)
+        .await
+}
+
+/// Download the given output partitions from the executors that hold them
+#[instrument(skip(locations), fields(partitions = locations.len()))]
+pub async fn fetch_results(
+    locations: Vec<PartitionLocation>,
+    shuffle_host: &str,
+) -> Result<Vec<RecordBatch>> {
+    let fetches = locations
+        .iter()
+        .map(|location| fetch_partition(location, shuffle_host));
+    let partitions = future::try_join_all(fetches).await?;
+    Ok(partitions
+        .into_iter()
+        .flat_map(|(_, batches)| batches)
+        .collect())
+}
+
+/// Download a single output partition, with its schema, see `open_partition`
+pub async fn fetch_partition(
+    location: &PartitionLocation,
+    shuffle_host: &str,
+) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    let stream = open_partition(location, shuffle_host).await?;
+    Ok((stream.schema(), collect(stream).await?))
+}
+
+/// Open a single output partition as a stream of batches. The outputs of a shared
+/// shuffle are read from the storage when it is mounted here, or else through the
+/// Flight service of the standalone executor on `shuffle_host`, as their registered
+/// host is a loopback address.
+pub async fn open_partition(
+    location: &PartitionLocation,
+    shuffle_host: &str,
+) -> Result<SendableRecordBatchStream> {
+    let metadata = location
+        .executor_meta
+        .as_ref()
+        .ok_or_else(|| anyhow!("Partition location is missing executor metadata"))?;
+    let partition_id = location
+        .partition_id
+        .as_ref()
+        .ok_or_else(|| anyhow!("Partition location is missing partition id"))?;
+    let host = match metadata.host.as_str() {
+        SHARED_SHUFFLE_HOST if Path::new(&location.path).is_file() => {
+            let path = location.path.clone();
+            let (schema, batches) = tokio::task::spawn_blocking(move || read_partition_file(&path))
+                .instrument(info_span!("read_partition", path = %location.path))
+                .await??;
+            return Ok(Box::pin(MemoryStream::try_new(batches, schema, None)?));
+        }
+        SHARED_SHUFFLE_HOST => shuffle_host,
+        host => host,
+    };
+    async {
+        let mut client = BallistaClient::try_new(host, metadata.port as u16).await?;
+        let stream = client
+            .fetch_partition(
+                &partition_id.job_id,
+                partition_id.stage_id as usize,
+                partition_id.partition_id as usize,
+                &location.path,
+            )
+            .await?;
+        Ok(stream)
+    }
+    .instrument(info_span!(
+        "fetch_partition",
+        executor = %host,
+        partition = partition_id.partition_id
+    ))
+    .await
+}
+
+/// Read an output partition from the Arrow IPC file written by the executor
+fn read_partition_file(path: &str) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    let file = File::open(path).with_context(|| format!("Could not open partition {}", path))?;
+    let reader = FileReader::try_new(file)?;
+    let schema = reader.schema();
+    Ok((schema, reader.collect::<std::result::Result<_, _>>()?))
+}
+
+/// A run of SQL text, either code or a string literal, a quoted identifier or
+/// a comment that is kept verbatim
+#[derive(Debug, PartialEq)]
+enum SqlSegment<'a> {
+    Code(&'a str),
+    Verbatim(&'a str),
+}
+
+/// Cut the SQL text into code and verbatim segments. Unterminated literals and
+/// comments run until the end of the text.
+fn sql_segments(sql: &str) -> Vec<SqlSegment<'_>> {
+    let mut segments = vec![];
+    let mut code_start = 0;
+    let mut pos = 0;
+    while pos < sql.len() {
+        let rest = &sql[pos..];
+        let verbatim_len = if rest.starts_with('\'') || rest.starts_with('"') {
+            let quote = &rest[..1];
+            rest[1..].find(quote).map_or(rest.len(), |end| end + 2)
+        } else if rest.starts_with("--") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            comment.find("*/").map_or(rest.len(), |end| end + 4)
+        } else {
+            pos += rest.chars().next().map_or(1, |c| c.len_utf8());
+            continue;
+        };
+        if code_start < pos {
+            segments.push(SqlSegment::Code(&sql[code_start..pos]));
+        }
+        segments.push(SqlSegment::Verbatim(&rest[..verbatim_len]));
+        pos += verbatim_len;
+        code_start = pos;
+    }
+    if code_start < sql.len() {
+        segments.push(SqlSegment::Code(&sql[code_start..]));
+    }
+    segments
+}
+
+/// Replace the `$name` placeholders of the SQL text by the given values as SQL
+/// literals. Placeholders inside string literals, quoted identifiers and
+/// comments are left untouched.
+pub fn bind_params(sql: &str, params: &HashMap<String, Value>) -> Result<String> {
+    let mut bound = String::with_capacity(sql.len());
+    for segment in sql_segments(sql) {
+        let code = match segment {
+            SqlSegment::Code(code) => code,
+            SqlSegment::Verbatim(text) => {
+                bound.push_str(text);
+                continue;
+            }
+        };
+        let mut chars = code.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                bound.push(c);
+                continue;
+            }
+            let mut name = String::new();
+            while let Some(&next) = chars.peek() {
+                if !next.is_ascii_alphanumeric() && next != '_' {
+                    break;
+                }
+                name.push(next);
+                chars.next();
+            }
+            if name.is_empty() {
+                bound.push(c);
+                continue;
+            }
+            let value = params
+                .get(&name)
+                .ok_or_else(|| anyhow!("Missing value for query parameter ${}", name))?;
+            bound.push_str(&sql_literal(value)?);
+        }
+    }
+    Ok(bound)
+}
+
+fn sql_literal(value: &Value) -> Result<String> {
+    match value {
+        Value::Null => Ok("NULL".to_owned()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::String(s) => Ok(format!("'{}'", s.replace('\'', "''"))),
+        _ => bail!("Unsupported query parameter value: {}", value),
+    }
+}
+
+/// Split a SQL script into statements on the `;` that are outside of string
+/// literals, quoted identifiers and comments. Statements made only of comments
+/// are dropped.
+pub fn split_statements(script: &str) -> Vec<String> {
+    let mut statements = vec![];
+    let mut current = String::new();
+    let mut has_code = false;
+    for segment in sql_segments(script) {
+        match segment {
+            SqlSegment::Code(code) => {
+                for (i, part) in code.split(';').enumerate() {
+                    if i > 0 {
+                        if has_code {
+                            statements.push(current.trim().to_owned());
+                        }
+                        current.clear();
+                        has_code = false;
+                    }
+                    current.push_str(part);
+                    has_code |= !part.trim().is_empty();
+                }
+            }
+            SqlSegment::Verbatim(text) => {
+                has_code |= !(text.starts_with("--") || text.starts_with("/*"));
+                current.push_str(text);
+            }
+        }
+    }
+    if has_code {
+        statements.push(current.trim().to_owned());
+    }
+    statements
+}
+
+/// True if the script ends with a `;` outside of literals and comments, so that
+/// no statement is left open
+pub fn ends_statement(script: &str) -> bool {
+    let last = sql_segments(script)
+        .into_iter()
+        .rev()
+        .find(|segment| match segment {
+            SqlSegment::Code(code) => !code.trim().is_empty(),
+            // unterminated block comments leave the statement open
+            SqlSegment::Verbatim(text) => {
+                !(text.starts_with("--")
+                    || text.starts_with("/*") && text.len() >= 4 && text.ends_with("*/"))
+            }
+        });
+    matches!(last, Some(SqlSegment::Code(code)) if code.trim_end().ends_with(';'))
+}
+
+/// The words of the SQL text outside of string literals and comments, lowercased
+/// unless they are quoted identifiers
+pub fn sql_identifiers(sql: &str) -> HashSet<String> {
+    let mut identifiers = HashSet::new();
+    for segment in sql_segments(sql) {
+        match segment {
+            SqlSegment::Code(code) => identifiers.extend(
+                code.split(|c: char| !c.is_alphanumeric() && c != '_')
+                    .filter(|word| !word.is_empty())
+                    .map(|word| word.to_lowercase()),
+            ),
+            SqlSegment::Verbatim(text) if text.starts_with('"') => {
+                identifiers.insert(text.trim_matches('"').to_owned());
+            }
+            SqlSegment::Verbatim(_) => {}
+        }
+    }
+    identifiers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn params() -> HashMap<String, Value> {
+        vec![
+            ("date".to_owned(), json!("1995-03-15")),
+            ("qty".to_owned(), json!(24)),
+            ("name".to_owned(), json!("O'Brien")),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn sql_segments_keep_literals_and_comments() {
+        assert_eq!(
+            sql_segments("select 'a;b', \"c\" -- d\nfrom /* e */ t"),
+            vec![
+                SqlSegment::Code("select "),
+                SqlSegment::Verbatim("'a;b'"),
+                SqlSegment::Code(", "),
+                SqlSegment::Verbatim("\"c\""),
+                SqlSegment::Code(" "),
+                SqlSegment::Verbatim("-- d"),
+                SqlSegment::Code("\nfrom "),
+                SqlSegment::Verbatim("/* e */"),
+                SqlSegment::Code(" t"),
+            ]
+        );
+        assert_eq!(
+            sql_segments("select 1 /* unterminated"),
+            vec![
+                SqlSegment::Code("select 1 "),
+                SqlSegment::Verbatim("/* unterminated"),
+            ]
+        );
+    }
+
+    #[test]
+    fn bind_params_replaces_placeholders() {
+        let sql = "select * from orders where o_orderdate < $date and qty = $qty";
+        assert_eq!(
+            bind_params(sql, &params()).unwrap(),
+            "select * from orders where o_orderdate < '1995-03-15' and qty = 24"
+        );
+        assert_eq!(
+            bind_params("select $name", &params()).unwrap(),
+            "select 'O''Brien'"
+        );
+        assert!(bind_params("select $1, $", &params()).is_err());
+        assert_eq!(bind_params("select 1 $", &params()).unwrap(), "select 1 $");
+    }
+
+    #[test]
+    fn bind_params_skips_literals_identifiers_and_comments() {
+        let sql = "select '$date', \"$qty\" -- $name\nfrom t /* $missing */ where d = $date";
+        assert_eq!(
+            bind_params(sql, &params()).unwrap(),
+            "select '$date', \"$qty\" -- $name\nfrom t /* $missing */ where d = '1995-03-15'"
+        );
+        assert_eq!(
+            bind_params("select 'it''s $date'", &params()).unwrap(),
+            "select 'it''s $date'"
+        );
+    }
+
+    #[test]
+    fn bind_params_fails_on_missing_values() {
+        assert!(bind_params("select $missing", &params()).is_err());
+    }
+
+    #[test]
+    fn split_statements_skips_literals_and_comments() {
+        let script = "select 'a;b' from t; -- drop; this\nselect \"c;d\" /* ; */ from u;\n";
+        assert_eq!(
+            split_statements(script),
+            vec![
+                "select 'a;b' from t",
+                "-- drop; this\nselect \"c;d\" /* ; */ from u",
+            ]
+        );
+        assert_eq!(
+            split_statements("select 1;;select 2"),
+            vec!["select 1", "select 2"]
+        );
+        assert!(split_statements("-- only a comment;\n/* and; another */ ;").is_empty());
+    }
+
+    #[test]
+    fn ends_statement_ignores_literals_and_comments() {
+        assert!(ends_statement("select 1;\n"));
+        assert!(ends_statement("select 1; -- done\n"));
+        assert!(ends_statement("select 1; /* done */"));
+        assert!(!ends_statement("select 'a;\n"));
+        assert!(!ends_statement("select 1 -- not yet;\n"));
+        assert!(!ends_statement("select 1; /* open;\n"));
+        assert!(!ends_statement(""));
+    }
+
+    #[test]
+    fn sql_identifiers_skip_literals_and_comments() {
+        let sql = "select \"Name\", 'lineitem' from Nation n -- orders\njoin /* part */ region_2";
+        let mut identifiers = sql_identifiers(sql).into_iter().collect::<Vec<_>>();
+        identifiers.sort();
+        assert_eq!(
+            identifiers,
+            vec!["Name", "from", "join", "n", "nation", "region_2", "select"]
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_shuffle_partitions_are_read_from_the_storage() {
+        use ballista_core::serde::protobuf::{ExecutorMetadata, PartitionId};
+        use datafusion::arrow::array::Int64Array;
+        use datafusion::arrow::datatypes::{DataType, Field};
+        use datafusion::arrow::ipc::writer::FileWriter;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.arrow");
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let column = Arc::new(Int64Array::from(vec![1, 2, 3]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![column]).unwrap();
+        let mut writer = FileWriter::try_new(File::create(&path).unwrap(), &schema).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+
+        let location = |path: &Path| PartitionLocation {
+            partition_id: Some(PartitionId::default()),
+            executor_meta: Some(ExecutorMetadata {
+                host: SHARED_SHUFFLE_HOST.to_owned(),
+                port: 1,
+                ..Default::default()
+            }),
+            path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        // the shuffle host is not reachable, the file is read directly
+        let (partition_schema, batches) = fetch_partition(&location(&path), "invalid.host")
+            .await
+            .unwrap();
+        assert_eq!(partition_schema, schema);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 3);
+        // a missing file is fetched from the shuffle host
+        let missing = fetch_partition(&location(&dir.path().join("missing")), "invalid.host");
+        assert!(missing.await.is_err());
+    }
+}
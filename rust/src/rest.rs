@@ -0,0 +1,441 @@
+//! HTTP API to submit SQL queries and stream their results without a Ballista client.
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use ballista::context::BallistaContext;
+use ballista::prelude::BallistaConfig;
+use ballista_core::serde::protobuf::{job_status, PartitionLocation};
+use datafusion::arrow::csv;
+use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::json::writer::record_batches_to_json_rows;
+use datafusion::error::DataFusionError;
+use hyper::{Body, Response, StatusCode};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use warp::filters::BoxedFilter;
+use warp::{Filter, Rejection};
+
+use crate::query::{fetch_partition, split_statements, submit_statements, JobClient, JobState};
+
+/// Number of encoded chunks buffered ahead of the client
+const RESULT_CHANNEL_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub enum StreamFormat {
+    /// Arrow IPC stream format
+    Arrow,
+    Csv,
+    /// one JSON object per line
+    Ndjson,
+}
+
+impl FromStr for StreamFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "arrow" => Ok(StreamFormat::Arrow),
+            "csv" => Ok(StreamFormat::Csv),
+            "ndjson" => Ok(StreamFormat::Ndjson),
+            _ => bail!("Unknown result format: {}", s),
+        }
+    }
+}
+
+impl StreamFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            StreamFormat::Arrow => "application/vnd.apache.arrow.stream",
+            StreamFormat::Csv => "text/csv",
+            StreamFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryRequest {
+    /// statements separated by `;`, the last one is the query
+    pub sql: String,
+}
+
+#[derive(Debug, Serialize)]
+struct QuerySubmitted {
+    query_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResultParams {
+    format: Option<String>,
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(body).unwrap_or_default()))
+        .expect("Could not build JSON response")
+}
+
+fn error_response(status: StatusCode, error: impl std::fmt::Display) -> Response<Body> {
+    json_response(
+        status,
+        &ErrorBody {
+            error: format!("{:#}", error),
+        },
+    )
+}
+
+/// Status of a failed submission: the errors of the scheduler, of the connection to
+/// it and the internal ones are on the server side, the others come from the SQL
+fn submit_error_status(err: &anyhow::Error) -> StatusCode {
+    for cause in err.chain() {
+        if cause.is::<tonic::transport::Error>() {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        if cause.is::<tonic::Status>() {
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+        if let Some(DataFusionError::Internal(_)) = cause.downcast_ref() {
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+    StatusCode::BAD_REQUEST
+}
+
+/// Queries are Ballista jobs, the query id is the job id
+pub struct RestApi {
+    ctx: Arc<BallistaContext>,
+    config: BallistaConfig,
+    scheduler_host: String,
+    scheduler_port: u16,
+}
+
+impl RestApi {
+    pub fn new(
+        ctx: Arc<BallistaContext>,
+        config: BallistaConfig,
+        scheduler_host: String,
+        scheduler_port: u16,
+    ) -> Self {
+        Self {
+            ctx,
+            config,
+            scheduler_host,
+            scheduler_port,
+        }
+    }
+
+    async fn job_client(&self) -> Result<JobClient> {
+        JobClient::try_new(&self.scheduler_host, self.scheduler_port, &self.config).await
+    }
+
+    async fn submit(&self, request: QueryRequest) -> Response<Body> {
+        let statements = split_statements(&request.sql);
+        if statements.is_empty() {
+            return error_response(StatusCode::BAD_REQUEST, "sql contains no statement");
+        }
+        let submitted = submit_statements(
+            &self.ctx,
+            &self.scheduler_host,
+            self.scheduler_port,
+            &self.config,
+            &statements,
+        )
+        .await;
+        match submitted {
+            Ok(query_id) => json_response(StatusCode::ACCEPTED, &QuerySubmitted { query_id }),
+            Err(err) => error_response(submit_error_status(&err), err),
+        }
+    }
+
+    async fn job_status(&self, query_id: &str) -> Result<Option<job_status::Status>> {
+        self.job_client().await?.try_status(query_id).await
+    }
+
+    async fn status(&self, query_id: String) -> Response<Body> {
+        match self.job_status(&query_id).await {
+            Ok(Some(status)) => json_response(StatusCode::OK, &JobState::new(&query_id, &status)),
+            Ok(None) => error_response(StatusCode::NOT_FOUND, "Unknown query"),
+            Err(err) => error_response(StatusCode::INTERNAL_SERVER_ERROR, err),
+        }
+    }
+
+    async fn result(&self, query_id: String, params: ResultParams) -> Response<Body> {
+        let format = match params.format.as_deref().unwrap_or("ndjson").parse() {
+            Ok(format) => format,
+            Err(err) => return error_response(StatusCode::BAD_REQUEST, err),
+        };
+        let locations = match self.job_status(&query_id).await {
+            Ok(Some(job_status::Status::Completed(completed))) => completed.partition_location,
+            Ok(Some(status)) => {
+                return json_response(StatusCode::CONFLICT, &JobState::new(&query_id, &status))
+            }
+            Ok(None) => return error_response(StatusCode::NOT_FOUND, "Unknown query"),
+            Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, err),
+        };
+        let (sender, receiver) = mpsc::channel(RESULT_CHANNEL_SIZE);
+        let shuffle_host = self.scheduler_host.clone();
+        tokio::spawn(async move {
+            if let Err(err) = stream_results(locations, &shuffle_host, format, &sender).await {
+                warn!("could not stream the results of {}: {:?}", query_id, err);
+                sender.send(Err(err)).await.ok();
+            }
+        });
+        Response::builder()
+            .header(hyper::header::CONTENT_TYPE, format.content_type())
+            .body(Body::wrap_stream(ReceiverStream::new(receiver)))
+            .expect("Could not build result response")
+    }
+}
+
+/// Shared buffer that the Arrow writers write to, drained after every batch
+#[derive(Clone, Default)]
+struct ChunkBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl ChunkBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for ChunkBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Fetch the partitions one by one and send the encoded batches as they arrive
+async fn stream_results(
+    locations: Vec<PartitionLocation>,
+    shuffle_host: &str,
+    format: StreamFormat,
+    sender: &mpsc::Sender<Result<Vec<u8>>>,
+) -> Result<()> {
+    let mut buffer = ChunkBuffer::default();
+    let mut arrow_writer = None;
+    let mut csv_writer = csv::Writer::new(buffer.clone());
+    let mut schema = None;
+    for location in &locations {
+        let (partition_schema, batches) = fetch_partition(location, shuffle_host).await?;
+        schema = Some(partition_schema);
+        for batch in batches {
+            match format {
+                StreamFormat::Arrow => {
+                    if arrow_writer.is_none() {
+                        let writer = StreamWriter::try_new(buffer.clone(), &batch.schema())?;
+                        arrow_writer = Some(writer);
+                    }
+                    arrow_writer.as_mut().unwrap().write(&batch)?;
+                }
+                StreamFormat::Csv => csv_writer.write(&batch)?,
+                StreamFormat::Ndjson => {
+                    for row in record_batches_to_json_rows(std::slice::from_ref(&batch)) {
+                        serde_json::to_writer(&mut buffer, &row)?;
+                        buffer.write_all(b"\n")?;
+                    }
+                }
+            }
+            if sender.send(Ok(buffer.take())).await.is_err() {
+                // the client disconnected
+                return Ok(());
+            }
+        }
+    }
+    if let StreamFormat::Arrow = format {
+        match arrow_writer {
+            Some(mut writer) => writer.finish()?,
+            None => {
+                // the partitions of an empty result still carry the output schema
+                let schema = schema.unwrap_or_else(|| Arc::new(Schema::empty()));
+                StreamWriter::try_new(buffer.clone(), &schema)?.finish()?
+            }
+        }
+        sender.send(Ok(buffer.take())).await.ok();
+    }
+    Ok(())
+}
+
+/// `POST /query`, `GET /query/{id}` and `GET /query/{id}/result?format=arrow|csv|ndjson`
+pub fn routes(api: Arc<RestApi>) -> BoxedFilter<(Response<Body>,)> {
+    let with_api = warp::any().map(move || api.clone());
+    let submit = warp::post()
+        .and(warp::path!("query"))
+        .and(warp::body::json())
+        .and(with_api.clone())
+        .and_then(|request: QueryRequest, api: Arc<RestApi>| async move {
+            Ok::<_, Rejection>(api.submit(request).await)
+        });
+    let status = warp::get()
+        .and(warp::path!("query" / String))
+        .and(with_api.clone())
+        .and_then(|query_id: String, api: Arc<RestApi>| async move {
+            Ok::<_, Rejection>(api.status(query_id).await)
+        });
+    let result = warp::get()
+        .and(warp::path!("query" / String / "result"))
+        .and(warp::query::<ResultParams>())
+        .and(with_api)
+        .and_then(
+            |query_id: String, params: ResultParams, api: Arc<RestApi>| async move {
+                Ok::<_, Rejection>(api.result(query_id, params).await)
+            },
+        );
+    submit.or(status).unify().or(result).unify().boxed()
+}
+
+/// Answers the REST requests when the API could not be started
+pub fn unavailable_routes() -> BoxedFilter<(Response<Body>,)> {
+    warp::path("query")
+        .map(|| error_response(StatusCode::SERVICE_UNAVAILABLE, "The REST API is disabled"))
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::path::Path;
+
+    use ballista_core::serde::protobuf::{ExecutorMetadata, PartitionId};
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::{DataType, Field, SchemaRef};
+    use datafusion::arrow::ipc::reader::StreamReader;
+    use datafusion::arrow::ipc::writer::FileWriter;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use serde_json::json;
+
+    use crate::SHARED_SHUFFLE_HOST;
+
+    fn api() -> Arc<RestApi> {
+        let config = BallistaConfig::new().unwrap();
+        // no scheduler listens on this port
+        let ctx = BallistaContext::remote("localhost", 1, &config);
+        let host = "localhost".to_owned();
+        Arc::new(RestApi::new(Arc::new(ctx), config, host, 1))
+    }
+
+    #[tokio::test]
+    async fn routes_status_codes() {
+        let routes = routes(api());
+        let submit = |sql: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/query")
+                .json(&json!({ "sql": sql }))
+        };
+        let res = submit("-- nothing ;").reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        // the scheduler is not reachable
+        let res = submit("select 1").reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let res = warp::test::request()
+            .path("/query/job")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let res = warp::test::request()
+            .path("/query/job/result?format=xml")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(!warp::test::request().path("/jobs").matches(&routes).await);
+
+        let res = warp::test::request()
+            .path("/query/job")
+            .reply(&unavailable_routes())
+            .await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn submit_errors_status() {
+        let status = |err: anyhow::Error| submit_error_status(&err);
+        let plan_error = DataFusionError::Plan("Table not found".to_owned());
+        assert_eq!(status(plan_error.into()), StatusCode::BAD_REQUEST);
+        let internal = DataFusionError::Internal("bug".to_owned());
+        assert_eq!(status(internal.into()), StatusCode::INTERNAL_SERVER_ERROR);
+        let scheduler = anyhow::Error::from(tonic::Status::internal("failed")).context("submit");
+        assert_eq!(status(scheduler), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    /// Write a partition as the executors of a shared shuffle do
+    fn partition(path: &Path, schema: &SchemaRef, values: &[i64]) -> PartitionLocation {
+        let mut writer = FileWriter::try_new(File::create(path).unwrap(), schema).unwrap();
+        if !values.is_empty() {
+            let column = Arc::new(Int64Array::from(values.to_vec()));
+            writer
+                .write(&RecordBatch::try_new(schema.clone(), vec![column]).unwrap())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        PartitionLocation {
+            partition_id: Some(PartitionId::default()),
+            executor_meta: Some(ExecutorMetadata {
+                host: SHARED_SHUFFLE_HOST.to_owned(),
+                port: 1,
+                ..Default::default()
+            }),
+            path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        }
+    }
+
+    async fn stream(locations: Vec<PartitionLocation>, format: StreamFormat) -> Vec<u8> {
+        let (sender, mut receiver) = mpsc::channel(16);
+        stream_results(locations, "invalid.host", format, &sender)
+            .await
+            .unwrap();
+        drop(sender);
+        let mut output = vec![];
+        while let Some(chunk) = receiver.recv().await {
+            output.extend(chunk.unwrap());
+        }
+        output
+    }
+
+    #[tokio::test]
+    async fn stream_results_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let locations = vec![
+            partition(&dir.path().join("0.arrow"), &schema, &[1, 2]),
+            partition(&dir.path().join("1.arrow"), &schema, &[]),
+            partition(&dir.path().join("2.arrow"), &schema, &[3]),
+        ];
+
+        let ndjson = stream(locations.clone(), StreamFormat::Ndjson).await;
+        assert_eq!(
+            String::from_utf8(ndjson).unwrap(),
+            "{\"a\":1}\n{\"a\":2}\n{\"a\":3}\n"
+        );
+        let csv = stream(locations.clone(), StreamFormat::Csv).await;
+        assert_eq!(String::from_utf8(csv).unwrap(), "a\n1\n2\n3\n");
+
+        let arrow = stream(locations, StreamFormat::Arrow).await;
+        let reader = StreamReader::try_new(arrow.as_slice()).unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 3);
+
+        // the schema of an empty result is the one of its partitions
+        let empty = vec![partition(&dir.path().join("3.arrow"), &schema, &[])];
+        let arrow = stream(empty, StreamFormat::Arrow).await;
+        let reader = StreamReader::try_new(arrow.as_slice()).unwrap();
+        assert_eq!(reader.schema(), schema);
+        assert_eq!(reader.count(), 0);
+    }
+}
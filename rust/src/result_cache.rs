@@ -0,0 +1,412 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::ipc::reader::FileReader;
+use datafusion::arrow::ipc::writer::FileWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::query::sql_identifiers;
+use crate::s3::{is_s3_uri, list_objects, new_client, S3Location};
+
+const ENTRY_FILE: &str = "entry.json";
+const RESULT_FILE: &str = "result.arrow";
+
+/// A registered table, with the options that change how its files are read
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableSource {
+    pub name: String,
+    /// local file or directory, or `s3://` location
+    pub path: String,
+    /// format, schema and CSV options of the registration
+    pub options: String,
+}
+
+/// What the results of a query depend on, besides the data of its tables
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheKey {
+    pub statements: Vec<String>,
+    /// the registered tables referenced by the statements, sorted by name
+    pub tables: Vec<TableSource>,
+}
+
+impl CacheKey {
+    pub fn new(statements: &[String], mut tables: Vec<TableSource>) -> Self {
+        let identifiers: HashSet<String> = statements
+            .iter()
+            .flat_map(|sql| sql_identifiers(sql))
+            .collect();
+        tables.retain(|table| {
+            identifiers.contains(&table.name) || identifiers.contains(&table.name.to_lowercase())
+        });
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+        Self {
+            statements: statements.iter().map(|sql| normalize_sql(sql)).collect(),
+            tables,
+        }
+    }
+
+    /// True if the statements register tables, which are not versioned
+    pub fn creates_tables(&self) -> bool {
+        self.statements
+            .iter()
+            .any(|sql| sql.starts_with("create external table"))
+    }
+
+    /// Name of the directory of the entry, stable across builds
+    fn fingerprint(&self) -> Result<String> {
+        // FNV-1a, the entry also stores the key to rule out collisions
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in serde_json::to_vec(self)? {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        Ok(format!("{:016x}", hash))
+    }
+}
+
+/// Collapse the whitespace and lowercase the SQL outside of the string literals
+/// and the quoted identifiers, and remove the trailing `;`
+pub fn normalize_sql(sql: &str) -> String {
+    let mut normalized = String::with_capacity(sql.len());
+    let mut quote = None;
+    let mut pending_space = false;
+    for c in sql.trim().trim_end_matches(';').trim_end().chars() {
+        match quote {
+            Some(closing) => {
+                normalized.push(c);
+                if c == closing {
+                    quote = None;
+                }
+            }
+            None if c.is_whitespace() => pending_space = true,
+            None => {
+                if pending_space && !normalized.is_empty() {
+                    normalized.push(' ');
+                }
+                pending_space = false;
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+                normalized.extend(c.to_lowercase());
+            }
+        }
+    }
+    normalized
+}
+
+/// A data file with its modification time and size, or its ETag for S3 objects
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileVersion {
+    pub path: String,
+    pub version: String,
+}
+
+/// Version of the data of the tables, from the metadata of their files or the S3 listing
+pub async fn data_version(tables: &[TableSource]) -> Result<Vec<FileVersion>> {
+    let mut versions = vec![];
+    let mut s3_client = None;
+    for table in tables {
+        if !is_s3_uri(&table.path) {
+            local_versions(Path::new(&table.path), &mut versions)?;
+            continue;
+        }
+        if s3_client.is_none() {
+            s3_client = Some(new_client()?);
+        }
+        let location: S3Location = table.path.parse()?;
+        for object in list_objects(s3_client.as_ref().unwrap(), &location).await? {
+            versions.push(FileVersion {
+                path: format!("s3://{}/{}", location.bucket, object.key),
+                version: object.e_tag.unwrap_or_else(|| object.size.to_string()),
+            });
+        }
+    }
+    Ok(versions)
+}
+
+/// Versions of the file, or of all the files under the directory
+fn local_versions(path: &Path, versions: &mut Vec<FileVersion>) -> Result<()> {
+    let metadata =
+        fs::metadata(path).with_context(|| format!("Could not read {}", path.display()))?;
+    if metadata.is_dir() {
+        let mut children = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        children.sort();
+        for child in children {
+            local_versions(&child, versions)?;
+        }
+        return Ok(());
+    }
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+    versions.push(FileVersion {
+        path: path.to_string_lossy().into_owned(),
+        version: format!("{}-{}", modified, metadata.len()),
+    });
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    key: CacheKey,
+    data_version: Vec<FileVersion>,
+    /// unix timestamp (s)
+    created_at: i64,
+}
+
+pub struct CachedResult {
+    pub schema: SchemaRef,
+    pub batches: Vec<RecordBatch>,
+    pub age_sec: u64,
+}
+
+/// Results of queries stored as Arrow IPC files in a local or EFS directory,
+/// one directory per key
+pub struct ResultCache {
+    dir: PathBuf,
+}
+
+impl ResultCache {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    /// The results cached for the key, if the data of the tables did not change
+    /// since and they are not older than `ttl`
+    pub fn get(
+        &self,
+        key: &CacheKey,
+        data_version: &[FileVersion],
+        ttl: Duration,
+    ) -> Result<Option<CachedResult>> {
+        let dir = self.dir.join(key.fingerprint()?);
+        let entry: CacheEntry = match File::open(dir.join(ENTRY_FILE)) {
+            Ok(file) => serde_json::from_reader(file)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let age_sec = (chrono::Utc::now().timestamp() - entry.created_at).max(0) as u64;
+        if entry.key != *key || entry.data_version != data_version || age_sec > ttl.as_secs() {
+            return Ok(None);
+        }
+        let reader = FileReader::try_new(File::open(dir.join(RESULT_FILE))?)?;
+        let schema = reader.schema();
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(CachedResult {
+            schema,
+            batches,
+            age_sec,
+        }))
+    }
+
+    /// Store the results of the key, replacing its previous entry
+    pub fn put(
+        &self,
+        key: &CacheKey,
+        data_version: Vec<FileVersion>,
+        schema: SchemaRef,
+        batches: &[RecordBatch],
+    ) -> Result<()> {
+        let entry = CacheEntry {
+            key: key.clone(),
+            data_version,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        // written aside and renamed so that readers never see a partial entry
+        let tmp_dir = self.dir.join(format!(".{}", Uuid::new_v4()));
+        let written = write_entry(&tmp_dir, &entry, schema, batches);
+        if written.is_err() {
+            fs::remove_dir_all(&tmp_dir).ok();
+            return written;
+        }
+        let dir = self.dir.join(key.fingerprint()?);
+        remove_dir(&dir)?;
+        fs::rename(&tmp_dir, &dir)
+            .with_context(|| format!("Could not create cache entry {}", dir.display()))
+    }
+
+    /// Remove the entry of the key, false if there was none
+    pub fn invalidate(&self, key: &CacheKey) -> Result<bool> {
+        remove_dir(&self.dir.join(key.fingerprint()?))
+    }
+
+    /// Remove all the entries and return their count
+    pub fn clear(&self) -> Result<usize> {
+        let children = match fs::read_dir(&self.dir) {
+            Ok(children) => children,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        let mut removed = 0;
+        for child in children {
+            let path = child?.path();
+            // entries being written start with a dot
+            let is_entry = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| !name.starts_with('.'));
+            if remove_dir(&path)? && is_entry {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn write_entry(
+    dir: &Path,
+    entry: &CacheEntry,
+    schema: SchemaRef,
+    batches: &[RecordBatch],
+) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Could not create cache directory {}", dir.display()))?;
+    let mut writer = FileWriter::try_new(File::create(dir.join(RESULT_FILE))?, &schema)?;
+    for batch in batches {
+        writer.write(batch)?;
+    }
+    writer.finish()?;
+    serde_json::to_writer(File::create(dir.join(ENTRY_FILE))?, entry)?;
+    Ok(())
+}
+
+/// Remove the directory if it exists, false if it did not
+fn remove_dir(dir: &Path) -> Result<bool> {
+    match fs::remove_dir_all(dir) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => {
+            Err(err).with_context(|| format!("Could not remove cache entry {}", dir.display()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    fn table(name: &str) -> TableSource {
+        TableSource {
+            name: name.to_owned(),
+            path: format!("/data/{}", name),
+            options: "csv".to_owned(),
+        }
+    }
+
+    fn statements(sql: &[&str]) -> Vec<String> {
+        sql.iter().map(|sql| sql.to_string()).collect()
+    }
+
+    #[test]
+    fn normalize_sql_keeps_literals_and_identifiers() {
+        assert_eq!(
+            normalize_sql("  SELECT *\n\tFROM  Nation ;\n"),
+            "select * from nation"
+        );
+        assert_eq!(
+            normalize_sql("select 'A  B', \"Col  X\" from t where c = 'It''s'"),
+            "select 'A  B', \"Col  X\" from t where c = 'It''s'"
+        );
+        assert_eq!(normalize_sql("select 1;"), normalize_sql("SELECT  1"));
+    }
+
+    #[test]
+    fn creates_tables() {
+        let create = statements(&[
+            "CREATE EXTERNAL TABLE t STORED AS CSV LOCATION '/data/t'",
+            "select * from t",
+        ]);
+        assert!(CacheKey::new(&create, vec![]).creates_tables());
+        let select = statements(&["select * from t"]);
+        assert!(!CacheKey::new(&select, vec![table("t")]).creates_tables());
+    }
+
+    #[test]
+    fn key_only_has_the_referenced_tables() {
+        let tables = vec![
+            table("region"),
+            table("orders"),
+            table("nation"),
+            table("part"),
+        ];
+        let sql = statements(&["select * from NATION n join region r -- part\nwhere 'orders'"]);
+        let key = CacheKey::new(&sql, tables);
+        let names: Vec<&str> = key.tables.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["nation", "region"]);
+    }
+
+    fn result() -> (SchemaRef, Vec<RecordBatch>) {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let column = Arc::new(Int64Array::from(vec![1, 2, 3]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![column]).unwrap();
+        (schema, vec![batch])
+    }
+
+    fn version(version: &str) -> Vec<FileVersion> {
+        vec![FileVersion {
+            path: "/data/t".to_owned(),
+            version: version.to_owned(),
+        }]
+    }
+
+    #[test]
+    fn put_get_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResultCache::new(dir.path().to_str().unwrap());
+        let key = CacheKey::new(&statements(&["select a from t"]), vec![table("t")]);
+        let ttl = Duration::from_secs(60);
+        assert!(cache.get(&key, &version("1"), ttl).unwrap().is_none());
+
+        let (schema, batches) = result();
+        cache
+            .put(&key, version("1"), schema.clone(), &batches)
+            .unwrap();
+        let cached = cache.get(&key, &version("1"), ttl).unwrap().unwrap();
+        assert_eq!(cached.schema, schema);
+        assert_eq!(cached.batches.len(), 1);
+        assert_eq!(cached.batches[0].column(0), batches[0].column(0));
+        // same query written differently
+        let same = CacheKey::new(&statements(&["SELECT a\nFROM t;"]), vec![table("t")]);
+        assert!(cache.get(&same, &version("1"), ttl).unwrap().is_some());
+        // the data changed
+        assert!(cache.get(&key, &version("2"), ttl).unwrap().is_none());
+
+        assert!(cache.invalidate(&key).unwrap());
+        assert!(cache.get(&key, &version("1"), ttl).unwrap().is_none());
+        assert!(!cache.invalidate(&key).unwrap());
+    }
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResultCache::new(dir.path().to_str().unwrap());
+        let key = CacheKey::new(&statements(&["select a from t"]), vec![table("t")]);
+        let (schema, batches) = result();
+        cache.put(&key, version("1"), schema, &batches).unwrap();
+
+        // age the entry by two minutes
+        let entry_path = dir.path().join(key.fingerprint().unwrap()).join(ENTRY_FILE);
+        let mut entry: CacheEntry =
+            serde_json::from_reader(File::open(&entry_path).unwrap()).unwrap();
+        entry.created_at -= 120;
+        serde_json::to_writer(File::create(&entry_path).unwrap(), &entry).unwrap();
+
+        let expired = cache.get(&key, &version("1"), Duration::from_secs(60));
+        assert!(expired.unwrap().is_none());
+        let fresh = cache.get(&key, &version("1"), Duration::from_secs(300));
+        assert!(fresh.unwrap().unwrap().age_sec >= 120);
+        assert_eq!(cache.clear().unwrap(), 1);
+    }
+}
@@ -0,0 +1,214 @@
+use std::fs::{self, File};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use datafusion::arrow::csv;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::ipc::writer::FileWriter;
+use datafusion::arrow::json::writer::record_batches_to_json_rows;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::parquet::arrow::ArrowWriter;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Default cap of the inline results, Lambda responses are limited to 6MB
+const DEFAULT_MAX_ROWS: usize = 10_000;
+const DEFAULT_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultFormat {
+    /// rows returned inline as JSON objects
+    Json,
+    Parquet,
+    Csv,
+    /// Arrow IPC file format
+    Arrow,
+}
+
+impl ResultFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ResultFormat::Json => "json",
+            ResultFormat::Parquet => "parquet",
+            ResultFormat::Csv => "csv",
+            ResultFormat::Arrow => "arrow",
+        }
+    }
+}
+
+/// How the results of a query should be returned
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ResultOptions {
+    pub format: ResultFormat,
+    /// directory where the file formats are written
+    pub location: Option<String>,
+    /// caps of the rows returned inline
+    pub max_rows: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for ResultOptions {
+    fn default() -> Self {
+        Self {
+            format: ResultFormat::Json,
+            location: None,
+            max_rows: DEFAULT_MAX_ROWS,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnDescription {
+    pub name: String,
+    pub data_type: String,
+    pub nullable: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryResult {
+    pub row_count: usize,
+    pub schema: Vec<ColumnDescription>,
+    /// inline rows, only for the JSON format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<Vec<Map<String, Value>>>,
+    /// true if the inline rows were capped
+    pub truncated: bool,
+    /// file the results were written to, for the other formats
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+/// Convert the result batches of the query `query_id` according to the options
+pub fn export_results(
+    query_id: &str,
+    schema: SchemaRef,
+    batches: &[RecordBatch],
+    options: &ResultOptions,
+) -> Result<QueryResult> {
+    let mut result = QueryResult {
+        row_count: batches.iter().map(|batch| batch.num_rows()).sum(),
+        schema: schema
+            .fields()
+            .iter()
+            .map(|field| ColumnDescription {
+                name: field.name().clone(),
+                data_type: field.data_type().to_string(),
+                nullable: field.is_nullable(),
+            })
+            .collect(),
+        rows: None,
+        truncated: false,
+        path: None,
+    };
+    if options.format == ResultFormat::Json {
+        let (rows, truncated) = json_rows(batches, options.max_rows, options.max_bytes)?;
+        result.rows = Some(rows);
+        result.truncated = truncated;
+        return Ok(result);
+    }
+
+    let location = match &options.location {
+        Some(location) => location,
+        None => bail!("A location is required for {:?} results", options.format),
+    };
+    fs::create_dir_all(location)
+        .with_context(|| format!("Could not create result directory {}", location))?;
+    let path = Path::new(location).join(format!("{}.{}", query_id, options.format.extension()));
+    let file = File::create(&path)
+        .with_context(|| format!("Could not create result file {}", path.display()))?;
+    write_file(file, options.format, schema, batches)?;
+    result.path = Some(path.to_string_lossy().into_owned());
+    Ok(result)
+}
+
+/// Convert the batches to JSON rows, stopping once one of the caps is reached
+fn json_rows(
+    batches: &[RecordBatch],
+    max_rows: usize,
+    max_bytes: usize,
+) -> Result<(Vec<Map<String, Value>>, bool)> {
+    let mut rows = vec![];
+    let mut bytes = 0;
+    for batch in batches {
+        // one row past the cap tells whether the rows are truncated
+        let len = batch.num_rows().min(max_rows - rows.len() + 1);
+        let batch = RecordBatch::try_new(
+            batch.schema(),
+            batch.columns().iter().map(|column| column.slice(0, len)).collect(),
+        )?;
+        for row in record_batches_to_json_rows(&[batch]) {
+            bytes += serde_json::to_vec(&row)?.len();
+            if rows.len() >= max_rows || bytes > max_bytes {
+                return Ok((rows, true));
+            }
+            rows.push(row);
+        }
+    }
+    Ok((rows, false))
+}
+
+fn write_file(
+    file: File,
+    format: ResultFormat,
+    schema: SchemaRef,
+    batches: &[RecordBatch],
+) -> Result<()> {
+    match format {
+        ResultFormat::Csv => {
+            let mut writer = csv::Writer::new(file);
+            for batch in batches {
+                writer.write(batch)?;
+            }
+        }
+        ResultFormat::Arrow => {
+            let mut writer = FileWriter::try_new(file, &schema)?;
+            for batch in batches {
+                writer.write(batch)?;
+            }
+            writer.finish()?;
+        }
+        ResultFormat::Parquet => {
+            let mut writer = ArrowWriter::try_new(file, schema, None)?;
+            for batch in batches {
+                writer.write(batch)?;
+            }
+            writer.close()?;
+        }
+        ResultFormat::Json => unreachable!("JSON results are returned inline"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::Int32Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    fn batch(values: Vec<i32>) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![Field::new("v", DataType::Int32, false)]));
+        RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(values))]).unwrap()
+    }
+
+    #[test]
+    fn json_rows_are_capped() {
+        let batches = vec![batch((0..5).collect()), batch((5..10).collect())];
+        let (rows, truncated) = json_rows(&batches, 7, usize::MAX).unwrap();
+        assert_eq!(rows.len(), 7);
+        assert!(truncated);
+        assert_eq!(rows[6]["v"], 6);
+
+        let (rows, truncated) = json_rows(&batches, 10, usize::MAX).unwrap();
+        assert_eq!(rows.len(), 10);
+        assert!(!truncated);
+
+        // every row is `{"v":n}`, 7 bytes
+        let (rows, truncated) = json_rows(&batches, 10, 20).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(truncated);
+    }
+}
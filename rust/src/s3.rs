@@ -0,0 +1,696 @@
+use std::convert::TryInto;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{StreamExt, TryStreamExt};
+use log::{debug, info};
+use rusoto_core::credential::DefaultCredentialsProvider;
+use rusoto_core::{HttpClient, Region};
+use rusoto_s3::{GetObjectRequest, ListObjectsV2Request, S3Client, S3};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+/// Size of the range requests used to download objects
+const RANGE_SIZE: i64 = 8 * 1024 * 1024;
+/// Number of concurrent range requests per object
+const RANGE_CONCURRENCY: usize = 8;
+/// Number of objects downloaded concurrently
+const OBJECT_CONCURRENCY: usize = 4;
+
+/// Size of the end of the Parquet files fetched at once, which usually holds the
+/// whole footer
+const PARQUET_TAIL_SIZE: i64 = 64 * 1024;
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+
+/// Default directory where S3 prefixes are synced, overridden by `BALLISTA_S3_CACHE_DIR`
+const DEFAULT_CACHE_DIR: &str = "/tmp/s3cache";
+
+/// Comma separated list of the locations synced by the executors
+const SYNC_ENV: &str = "BALLISTA_S3_SYNC";
+
+/// Directory of the cache where the ETags of the cached objects are stored, out of
+/// the directories of the tables that are read whole
+const ETAG_DIR: &str = ".etags";
+
+/// An `s3://bucket/prefix` location
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3Location {
+    pub bucket: String,
+    pub prefix: String,
+}
+
+impl FromStr for S3Location {
+    type Err = anyhow::Error;
+
+    fn from_str(uri: &str) -> Result<Self> {
+        let path = uri
+            .strip_prefix("s3://")
+            .ok_or_else(|| anyhow!("S3 URI should start with s3://: {}", uri))?;
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        if bucket.is_empty() {
+            bail!("S3 URI is missing a bucket: {}", uri);
+        }
+        Ok(Self {
+            bucket: bucket.to_owned(),
+            prefix: prefix.to_owned(),
+        })
+    }
+}
+
+impl S3Location {
+    /// True if the objects of `other` are all under this location
+    pub fn contains(&self, other: &S3Location) -> bool {
+        if self.bucket != other.bucket {
+            return false;
+        }
+        match other.prefix.strip_prefix(&self.prefix) {
+            Some(rest) => {
+                self.prefix.is_empty()
+                    || self.prefix.ends_with('/')
+                    || rest.is_empty()
+                    || rest.starts_with('/')
+            }
+            None => false,
+        }
+    }
+}
+
+/// Parse a comma separated list of S3 URIs
+pub fn parse_locations(uris: &str) -> Result<Vec<S3Location>> {
+    uris.split(',')
+        .map(str::trim)
+        .filter(|uri| !uri.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+pub fn is_s3_uri(path: &str) -> bool {
+    path.starts_with("s3://")
+}
+
+/// The local copy of the S3 locations synced by the executors at startup
+#[derive(Debug, Clone)]
+pub struct S3Cache {
+    /// the objects are cached in `{dir}/{bucket}/{key}`
+    pub dir: PathBuf,
+    pub synced: Vec<S3Location>,
+}
+
+impl S3Cache {
+    /// From `BALLISTA_S3_CACHE_DIR` and `BALLISTA_S3_SYNC`. The clients read the same
+    /// variables to reject the S3 tables that are not synced.
+    pub fn from_env() -> Result<Self> {
+        let dir =
+            env::var("BALLISTA_S3_CACHE_DIR").unwrap_or_else(|_| DEFAULT_CACHE_DIR.to_owned());
+        let synced = match env::var(SYNC_ENV) {
+            Ok(uris) => parse_locations(&uris).with_context(|| format!("Invalid {}", SYNC_ENV))?,
+            Err(_) => vec![],
+        };
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            synced,
+        })
+    }
+
+    /// Local directory (or file) where the location is synced
+    pub fn path(&self, location: &S3Location) -> PathBuf {
+        self.dir.join(&location.bucket).join(&location.prefix)
+    }
+
+    /// True if the location is under one of the synced locations
+    pub fn is_synced(&self, location: &S3Location) -> bool {
+        self.synced.iter().any(|synced| synced.contains(location))
+    }
+
+    fn object_path(&self, bucket: &str, key: &str) -> PathBuf {
+        self.dir.join(bucket).join(key)
+    }
+
+    fn etag_path(&self, bucket: &str, key: &str) -> PathBuf {
+        self.dir.join(ETAG_DIR).join(bucket).join(key)
+    }
+
+    /// True if the object is cached with the given ETag. Objects without ETag
+    /// are always downloaded again.
+    fn is_cached(&self, bucket: &str, object: &S3Object) -> bool {
+        let e_tag = match &object.e_tag {
+            Some(e_tag) => e_tag,
+            None => return false,
+        };
+        let cached_e_tag = fs::read_to_string(self.etag_path(bucket, &object.key));
+        self.object_path(bucket, &object.key).is_file()
+            && cached_e_tag.map_or(false, |cached| &cached == e_tag)
+    }
+}
+
+/// Create an S3 client. Credentials come from the default chain, which includes
+/// the ECS task role. A custom endpoint can be set with `BALLISTA_S3_ENDPOINT`
+/// to use an S3 compatible server such as MinIO.
+pub fn new_client() -> Result<S3Client> {
+    let region_name = env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_owned());
+    let region = match env::var("BALLISTA_S3_ENDPOINT") {
+        Ok(endpoint) => Region::Custom {
+            name: region_name,
+            endpoint,
+        },
+        Err(_) => Region::from_str(&region_name)?,
+    };
+    Ok(S3Client::new_with(
+        HttpClient::new()?,
+        DefaultCredentialsProvider::new()?,
+        region,
+    ))
+}
+
+/// An object listed under a location
+#[derive(Debug, Clone)]
+pub struct S3Object {
+    pub key: String,
+    pub size: i64,
+    pub e_tag: Option<String>,
+}
+
+/// List the objects under the location
+pub async fn list_objects(client: &S3Client, location: &S3Location) -> Result<Vec<S3Object>> {
+    let mut objects = vec![];
+    let mut continuation_token = None;
+    loop {
+        let response = client
+            .list_objects_v2(ListObjectsV2Request {
+                bucket: location.bucket.clone(),
+                prefix: Some(location.prefix.clone()),
+                continuation_token: continuation_token.clone(),
+                ..Default::default()
+            })
+            .await
+            .with_context(|| {
+                format!(
+                    "Could not list s3://{}/{}",
+                    location.bucket, location.prefix
+                )
+            })?;
+        for object in response.contents.unwrap_or_default() {
+            if let (Some(key), Some(size)) = (object.key, object.size) {
+                if !key.ends_with('/') {
+                    objects.push(S3Object {
+                        key,
+                        size,
+                        e_tag: object.e_tag,
+                    });
+                }
+            }
+        }
+        continuation_token = response.next_continuation_token;
+        if continuation_token.is_none() {
+            return Ok(objects);
+        }
+    }
+}
+
+async fn get_range(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    start: i64,
+    end: i64,
+) -> Result<Vec<u8>> {
+    let response = client
+        .get_object(GetObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            range: Some(format!("bytes={}-{}", start, end - 1)),
+            ..Default::default()
+        })
+        .await
+        .with_context(|| format!("Could not get s3://{}/{}", bucket, key))?;
+    let mut bytes = Vec::with_capacity((end - start) as usize);
+    response
+        .body
+        .ok_or_else(|| anyhow!("Empty body for s3://{}/{}", bucket, key))?
+        .into_async_read()
+        .read_to_end(&mut bytes)
+        .await?;
+    Ok(bytes)
+}
+
+/// Download an object with concurrent range requests
+pub async fn download_object(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    size: i64,
+    destination: &Path,
+) -> Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // write to a temporary file so that partial downloads are never read
+    let mut tmp_path = destination.as_os_str().to_owned();
+    tmp_path.push(".part");
+    let mut file = File::create(&tmp_path)?;
+    let ranges = (0..size)
+        .step_by(RANGE_SIZE as usize)
+        .map(|start| (start, (start + RANGE_SIZE).min(size)));
+    let mut chunks = futures::stream::iter(ranges)
+        .map(|(start, end)| get_range(client, bucket, key, start, end))
+        .buffered(RANGE_CONCURRENCY);
+    while let Some(chunk) = chunks.next().await {
+        file.write_all(&chunk?)?;
+    }
+    fs::rename(&tmp_path, destination)?;
+    debug!("downloaded s3://{}/{} ({} bytes)", bucket, key, size);
+    Ok(())
+}
+
+/// Download all the objects under the location into the cache and return the
+/// local path. Objects already cached with the same ETag are skipped, the ETag is
+/// stored once the object is downloaded.
+pub async fn sync_location(
+    client: &S3Client,
+    cache: &S3Cache,
+    location: &S3Location,
+) -> Result<PathBuf> {
+    let objects = list_objects(client, location).await?;
+    if objects.is_empty() {
+        bail!(
+            "No object found under s3://{}/{}",
+            location.bucket,
+            location.prefix
+        );
+    }
+    let start = Instant::now();
+    let missing = objects
+        .into_iter()
+        .filter(|object| !cache.is_cached(&location.bucket, object));
+    let sizes = futures::stream::iter(missing)
+        .map(|object| async move {
+            let bucket = &location.bucket;
+            let destination = cache.object_path(bucket, &object.key);
+            download_object(client, bucket, &object.key, object.size, &destination).await?;
+            if let Some(e_tag) = &object.e_tag {
+                let etag_path = cache.etag_path(bucket, &object.key);
+                if let Some(parent) = etag_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(etag_path, e_tag)?;
+            }
+            Ok::<_, anyhow::Error>(object.size)
+        })
+        .buffer_unordered(OBJECT_CONCURRENCY)
+        .try_collect::<Vec<_>>()
+        .await?;
+    let bytes: i64 = sizes.iter().sum();
+    let elapsed = start.elapsed().as_secs_f64();
+    info!(
+        "synced s3://{}/{} ({} objects, {} bytes downloaded in {:.1}s, {:.1} MB/s)",
+        location.bucket,
+        location.prefix,
+        sizes.len(),
+        bytes,
+        elapsed,
+        bytes as f64 / 1e6 / elapsed.max(1e-3)
+    );
+    Ok(cache.path(location))
+}
+
+/// Create in the cache, for the objects under the location that are not there yet,
+/// files of the same size with only what the clients read when they register a
+/// table: nothing for CSV files, the footer for Parquet files. The files are sparse
+/// and are replaced when the executors download the objects.
+pub async fn sync_metadata(
+    client: &S3Client,
+    cache: &S3Cache,
+    location: &S3Location,
+    parquet: bool,
+) -> Result<PathBuf> {
+    let objects = list_objects(client, location).await?;
+    if objects.is_empty() {
+        bail!(
+            "No object found under s3://{}/{}",
+            location.bucket,
+            location.prefix
+        );
+    }
+    for object in &objects {
+        let destination = cache.object_path(&location.bucket, &object.key);
+        if destination.exists() {
+            continue;
+        }
+        if parquet {
+            let (footer_start, footer) = parquet_footer(client, &location.bucket, object).await?;
+            let parts = [(0, PARQUET_MAGIC), (footer_start, footer.as_slice())];
+            write_sparse(&destination, object.size, &parts)?;
+        } else {
+            write_sparse(&destination, object.size, &[])?;
+        }
+    }
+    debug!(
+        "fetched the metadata of s3://{}/{} ({} objects)",
+        location.bucket,
+        location.prefix,
+        objects.len()
+    );
+    Ok(cache.path(location))
+}
+
+/// The footer of a Parquet object and its offset. Parquet files end with the
+/// metadata, its length on 4 bytes and the magic number.
+async fn parquet_footer(
+    client: &S3Client,
+    bucket: &str,
+    object: &S3Object,
+) -> Result<(i64, Vec<u8>)> {
+    let size = object.size;
+    let invalid = || anyhow!("s3://{}/{} is not a Parquet file", bucket, object.key);
+    if size < 12 {
+        return Err(invalid());
+    }
+    let tail_start = (size - PARQUET_TAIL_SIZE).max(0);
+    let mut tail = get_range(client, bucket, &object.key, tail_start, size).await?;
+    let len = tail.len();
+    if len != (size - tail_start) as usize || &tail[len - 4..] != PARQUET_MAGIC {
+        return Err(invalid());
+    }
+    let metadata_len = u32::from_le_bytes(tail[len - 8..len - 4].try_into()?) as i64;
+    let footer_start = size - 8 - metadata_len;
+    if footer_start < 4 {
+        return Err(invalid());
+    }
+    if footer_start >= tail_start {
+        return Ok((tail_start, tail));
+    }
+    let mut footer = get_range(client, bucket, &object.key, footer_start, tail_start).await?;
+    footer.append(&mut tail);
+    Ok((footer_start, footer))
+}
+
+/// Create a sparse file of the given size with the parts at their offset. A file
+/// created in the meantime, for instance by a download, is not replaced.
+fn write_sparse(destination: &Path, size: i64, parts: &[(i64, &[u8])]) -> Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp_path = destination.as_os_str().to_owned();
+    tmp_path.push(format!(".{}", Uuid::new_v4()));
+    let mut file = File::create(&tmp_path)?;
+    file.set_len(size as u64)?;
+    for (offset, bytes) in parts {
+        file.seek(SeekFrom::Start(*offset as u64))?;
+        file.write_all(bytes)?;
+    }
+    drop(file);
+    let linked = fs::hard_link(&tmp_path, destination);
+    fs::remove_file(&tmp_path)?;
+    match linked {
+        Err(err) if err.kind() != io::ErrorKind::AlreadyExists => {
+            Err(err).with_context(|| format!("Could not create {}", destination.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Sync the configured locations of the cache
+pub async fn sync_configured_locations(cache: &S3Cache) -> Result<()> {
+    if cache.synced.is_empty() {
+        return Ok(());
+    }
+    let client = new_client()?;
+    for location in &cache.synced {
+        sync_location(&client, cache, location).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::credential::StaticProvider;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    fn location(uri: &str) -> S3Location {
+        uri.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_uris() {
+        assert_eq!(
+            location("s3://tpch/nation/"),
+            S3Location {
+                bucket: "tpch".to_owned(),
+                prefix: "nation/".to_owned(),
+            }
+        );
+        assert_eq!(location("s3://tpch").prefix, "");
+        assert!("tpch/nation".parse::<S3Location>().is_err());
+        assert!("s3:///nation".parse::<S3Location>().is_err());
+        assert_eq!(
+            parse_locations(" s3://a/x/, ,s3://b").unwrap(),
+            vec![location("s3://a/x/"), location("s3://b")]
+        );
+        assert!(parse_locations("s3://a,/local").is_err());
+    }
+
+    #[test]
+    fn locations_contain_their_prefixes() {
+        assert!(location("s3://tpch/").contains(&location("s3://tpch/nation/")));
+        assert!(location("s3://tpch/nation/").contains(&location("s3://tpch/nation/")));
+        assert!(location("s3://tpch/nation").contains(&location("s3://tpch/nation/")));
+        assert!(location("s3://tpch/nation/").contains(&location("s3://tpch/nation/a.tbl")));
+        assert!(!location("s3://tpch/nation/").contains(&location("s3://tpch/region/")));
+        assert!(!location("s3://tpch/nation").contains(&location("s3://tpch/nation2/")));
+        assert!(!location("s3://tpch/nation/").contains(&location("s3://other/nation/")));
+    }
+
+    fn cache(dir: &Path, synced: &str) -> S3Cache {
+        S3Cache {
+            dir: dir.to_owned(),
+            synced: parse_locations(synced).unwrap(),
+        }
+    }
+
+    #[test]
+    fn cache_paths() {
+        let cache = cache(Path::new("/tmp/s3cache"), "s3://tpch/nation/,s3://other");
+        assert_eq!(
+            cache.path(&location("s3://tpch/nation/")),
+            Path::new("/tmp/s3cache/tpch/nation/")
+        );
+        assert!(cache.is_synced(&location("s3://tpch/nation/a.tbl")));
+        assert!(cache.is_synced(&location("s3://other/region/")));
+        assert!(!cache.is_synced(&location("s3://tpch/region/")));
+    }
+
+    type FakeObjects = Arc<Mutex<HashMap<String, (String, Vec<u8>)>>>;
+    type FakeResponse = warp::http::Result<warp::http::Response<Vec<u8>>>;
+
+    /// The objects are stored by `{bucket}/{key}`
+    fn list_response(
+        objects: &HashMap<String, (String, Vec<u8>)>,
+        bucket: &str,
+        prefix: &str,
+    ) -> FakeResponse {
+        let mut keys: Vec<_> = objects
+            .keys()
+            .filter_map(|path| path.strip_prefix(bucket)?.strip_prefix('/'))
+            .filter(|key| key.starts_with(prefix))
+            .collect();
+        keys.sort_unstable();
+        let mut body = format!(
+            "<ListBucketResult><Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount>",
+            bucket,
+            prefix,
+            keys.len()
+        );
+        for key in keys {
+            let (e_tag, data) = &objects[&format!("{}/{}", bucket, key)];
+            body.push_str(&format!(
+                "<Contents><Key>{}</Key><ETag>{}</ETag><Size>{}</Size></Contents>",
+                key,
+                e_tag,
+                data.len()
+            ));
+        }
+        body.push_str("<IsTruncated>false</IsTruncated></ListBucketResult>");
+        warp::http::Response::builder().body(body.into_bytes())
+    }
+
+    fn get_response(object: Option<&(String, Vec<u8>)>, range: Option<&str>) -> FakeResponse {
+        let (e_tag, data) = match object {
+            Some(object) => object,
+            None => return warp::http::Response::builder().status(404).body(vec![]),
+        };
+        let (start, end) = range
+            .and_then(|range| range.strip_prefix("bytes="))
+            .and_then(|range| range.split_once('-'))
+            .map(|(start, end)| (start.parse().unwrap(), end.parse::<usize>().unwrap() + 1))
+            .unwrap_or((0, data.len()));
+        warp::http::Response::builder()
+            .status(206)
+            .header("ETag", e_tag.as_str())
+            .body(data[start..end].to_vec())
+    }
+
+    /// Serve the objects, by key with their ETag, as the buckets of a local S3 API
+    /// and count the GetObject requests
+    fn fake_s3(objects: FakeObjects, gets: Arc<AtomicUsize>) -> S3Client {
+        use warp::Filter;
+
+        let routes = warp::get()
+            .and(warp::path::param::<String>())
+            .and(warp::path::tail())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(warp::header::optional::<String>("range"))
+            .map(
+                move |bucket: String, key: warp::path::Tail, query: HashMap<_, _>, range| {
+                    let objects = objects.lock().unwrap();
+                    if key.as_str().is_empty() {
+                        let prefix = query.get("prefix").map_or("", String::as_str);
+                        return list_response(&objects, &bucket, prefix);
+                    }
+                    gets.fetch_add(1, Ordering::SeqCst);
+                    let path = format!("{}/{}", bucket, key.as_str());
+                    get_response(objects.get(&path), range.as_deref())
+                },
+            );
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        S3Client::new_with(
+            HttpClient::new().unwrap(),
+            StaticProvider::new_minimal("key".to_owned(), "secret".to_owned()),
+            Region::Custom {
+                name: "us-east-1".to_owned(),
+                endpoint: format!("http://{}", addr),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn sync_from_fake_s3() {
+        let objects: FakeObjects = Arc::default();
+        let put = |key: &str, e_tag: &str, data: &str| {
+            let object = (e_tag.to_owned(), data.as_bytes().to_vec());
+            objects.lock().unwrap().insert(key.to_owned(), object);
+        };
+        put("tpch/nation/a.tbl", "a1", "0|ALGERIA|0|\n1|ARGENTINA|1|\n");
+        put("tpch/nation/b.tbl", "b1", "2|BRAZIL|1|\n");
+        put("tpch/region/a.tbl", "r1", "0|AFRICA|\n");
+        let gets = Arc::new(AtomicUsize::new(0));
+        let client = fake_s3(objects.clone(), gets.clone());
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), "s3://tpch/nation/");
+        let nation = location("s3://tpch/nation/");
+
+        let path = sync_location(&client, &cache, &nation).await.unwrap();
+        assert_eq!(path, dir.path().join("tpch/nation/"));
+        let read = |name: &str| fs::read_to_string(path.join(name)).unwrap();
+        assert_eq!(read("a.tbl"), "0|ALGERIA|0|\n1|ARGENTINA|1|\n");
+        assert_eq!(read("b.tbl"), "2|BRAZIL|1|\n");
+        // the ETags are not in the directory of the table
+        assert_eq!(fs::read_dir(&path).unwrap().count(), 2);
+        assert_eq!(gets.load(Ordering::SeqCst), 2);
+
+        // the objects with the same ETag are not downloaded again
+        sync_location(&client, &cache, &nation).await.unwrap();
+        assert_eq!(gets.load(Ordering::SeqCst), 2);
+
+        // an object rewritten with the same size is
+        put("tpch/nation/b.tbl", "b2", "2|BRAZIL|2|\n");
+        sync_location(&client, &cache, &nation).await.unwrap();
+        assert_eq!(gets.load(Ordering::SeqCst), 3);
+        assert_eq!(read("b.tbl"), "2|BRAZIL|2|\n");
+
+        let empty = location("s3://tpch/customer/");
+        assert!(sync_location(&client, &cache, &empty).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sync_metadata_from_fake_s3() {
+        let objects: FakeObjects = Arc::default();
+        // the footer does not fit in the first range
+        let metadata = vec![2u8; 70_000];
+        let mut parquet = b"PAR1".to_vec();
+        parquet.extend(vec![1u8; 1000]);
+        parquet.extend(&metadata);
+        parquet.extend(&(metadata.len() as u32).to_le_bytes());
+        parquet.extend(b"PAR1");
+        let csv = b"0|AFRICA|\n".to_vec();
+        let put = |path: &str, data: Vec<u8>| {
+            objects
+                .lock()
+                .unwrap()
+                .insert(path.to_owned(), ("1".to_owned(), data));
+        };
+        put("tpch/lineitem/a.parquet", parquet.clone());
+        put("tpch/lineitem/b.parquet", b"PAR1 not parquet".to_vec());
+        put("tpch/region/a.tbl", csv.clone());
+        let gets = Arc::new(AtomicUsize::new(0));
+        let client = fake_s3(objects, gets.clone());
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), "s3://tpch");
+
+        let file = location("s3://tpch/lineitem/a.parquet");
+        let path = sync_metadata(&client, &cache, &file, true).await.unwrap();
+        let cached = fs::read(&path).unwrap();
+        assert_eq!(cached.len(), parquet.len());
+        assert_eq!(&cached[..4], b"PAR1");
+        assert!(cached[4..1004].iter().all(|byte| *byte == 0));
+        assert_eq!(&cached[1004..], &parquet[1004..]);
+        assert_eq!(gets.load(Ordering::SeqCst), 2);
+        let invalid = location("s3://tpch/lineitem/b.parquet");
+        assert!(sync_metadata(&client, &cache, &invalid, true)
+            .await
+            .is_err());
+
+        // the CSV files are not read
+        let region = location("s3://tpch/region/");
+        let path = sync_metadata(&client, &cache, &region, false)
+            .await
+            .unwrap();
+        assert_eq!(fs::read(path.join("a.tbl")).unwrap(), vec![0; csv.len()]);
+        assert_eq!(gets.load(Ordering::SeqCst), 3);
+
+        // downloaded objects are kept, and the placeholders are downloaded
+        fs::remove_file(path.join("a.tbl")).unwrap();
+        sync_location(&client, &cache, &region).await.unwrap();
+        sync_metadata(&client, &cache, &region, false)
+            .await
+            .unwrap();
+        assert_eq!(fs::read(path.join("a.tbl")).unwrap(), csv);
+        sync_metadata(&client, &cache, &file, true).await.unwrap();
+        sync_location(&client, &cache, &file).await.unwrap();
+        assert_eq!(
+            fs::read(dir.path().join("tpch/lineitem/a.parquet")).unwrap(),
+            parquet
+        );
+    }
+
+    /// Sync the nation table from the MinIO server of `make run-integ-s3`
+    #[tokio::test]
+    #[ignore]
+    async fn sync_from_minio() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), "s3://tpch/nation/");
+        let client = new_client().unwrap();
+        let nation = location("s3://tpch/nation/");
+        let path = sync_location(&client, &cache, &nation).await.unwrap();
+        let rows: usize = fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+            .map(|content| content.lines().count())
+            .sum();
+        assert_eq!(rows, 25);
+        // files already in the cache are not downloaded again
+        let modified = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+        let first = fs::read_dir(&path).unwrap().next().unwrap().unwrap().path();
+        let before = modified(&first);
+        sync_location(&client, &cache, &nation).await.unwrap();
+        assert_eq!(modified(&first), before);
+    }
+}
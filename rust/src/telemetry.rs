@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Destination of the tracing spans, parsed from `none`, `otlp:<endpoint>` or `file:<path>`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceExporter {
+    None,
+    Otlp(String),
+    File(String),
+}
+
+impl FromStr for TraceExporter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.is_empty() || s == "none" {
+            return Ok(Self::None);
+        }
+        match s.split_once(':') {
+            Some(("otlp", endpoint)) => Ok(Self::Otlp(endpoint.to_owned())),
+            Some(("file", path)) => Ok(Self::File(path.to_owned())),
+            _ => Err(anyhow!("Unknown trace exporter: {}", s)),
+        }
+    }
+}
+
+/// Install the global tracing subscriber exporting spans to the given destination.
+/// The W3C trace context propagator is always installed so that incoming
+/// contexts are forwarded even if no spans are exported locally.
+pub fn init_tracing(service_name: &str, exporter: &str) -> Result<()> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let resource = Resource::new(vec![KeyValue::new("service.name", service_name.to_owned())]);
+    let tracer = match exporter.parse()? {
+        TraceExporter::None => return Ok(()),
+        TraceExporter::Otlp(endpoint) => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(trace::config().with_resource(resource))
+            .install_batch(opentelemetry::runtime::Tokio)?,
+        TraceExporter::File(path) => {
+            let file = File::create(&path)
+                .with_context(|| format!("Could not create trace file {}", path))?;
+            opentelemetry::sdk::export::trace::stdout::new_pipeline()
+                .with_writer(file)
+                .with_trace_config(trace::config().with_resource(resource))
+                .install_simple()
+        }
+    };
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)
+        .context("Could not install tracing subscriber")
+}
+
+/// Export the pending spans, to be called before a Lambda invocation returns
+pub fn flush_traces() {
+    global::force_flush_tracer_provider();
+}
+
+//// Context propagation ////
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(val)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, val);
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl<'a> Injector for MetadataInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(val)) = (
+            MetadataKey::from_bytes(key.as_bytes()),
+            MetadataValue::from_str(&value),
+        ) {
+            self.0.insert(name, val);
+        }
+    }
+}
+
+/// Add the context of the current span to the headers of an outgoing HTTP request
+pub fn inject_http_headers(headers: &mut HeaderMap) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+/// Add the context of the current span to the metadata of an outgoing gRPC request
+pub fn inject_grpc_metadata(metadata: &mut MetadataMap) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut MetadataInjector(metadata))
+    });
+}
+
+/// Wrap a gRPC message into a request that carries the current trace context
+pub fn traced_request<T>(message: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    inject_grpc_metadata(request.metadata_mut());
+    request
+}
+
+/// Read the remote trace context from incoming HTTP (or gRPC) headers
+pub fn extract_http_headers(headers: &HeaderMap) -> opentelemetry::Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_trace_exporter() {
+        assert_eq!("".parse::<TraceExporter>().unwrap(), TraceExporter::None);
+        assert_eq!("none".parse::<TraceExporter>().unwrap(), TraceExporter::None);
+        assert_eq!(
+            "otlp:http://collector:4317".parse::<TraceExporter>().unwrap(),
+            TraceExporter::Otlp("http://collector:4317".to_owned())
+        );
+        assert_eq!(
+            "file:/tmp/traces.json".parse::<TraceExporter>().unwrap(),
+            TraceExporter::File("/tmp/traces.json".to_owned())
+        );
+        assert!("jaeger:localhost".parse::<TraceExporter>().is_err());
+        assert!("otlp".parse::<TraceExporter>().is_err());
+    }
+}
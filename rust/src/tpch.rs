@@ -1,8 +1,16 @@
 use anyhow::{Result, bail};
 use ballista::context::BallistaContext;
 use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::execution::context::ExecutionConfig;
+use datafusion::parquet::basic::Compression;
+use datafusion::parquet::file::properties::WriterProperties;
+use datafusion::physical_plan::Partitioning;
 use datafusion::prelude::*;
+use log::info;
+use serde::{Deserialize, Serialize};
 use std::include_str;
+use std::path::Path;
+use std::str::FromStr;
 
 pub fn get_query(tpch_query: u8) -> Result<&'static str> {
     match tpch_query {
@@ -32,14 +40,60 @@ pub fn get_query(tpch_query: u8) -> Result<&'static str> {
     }
 }
 
-const TABLES: &[&str] = &[
+pub const TABLES: &[&str] = &[
     "part", "supplier", "partsupp", "customer", "orders", "lineitem", "nation", "region",
 ];
 
-fn get_schema(table: &str) -> Schema {
+/// Types of the key columns of the TPC-H tables
+#[derive(Debug, Clone, PartialEq)]
+pub struct TpchTypes {
+    /// `*key` columns
+    pub key: DataType,
+}
+
+impl TpchTypes {
+    /// Order keys are sparse and overflow Int32 beyond SF ~300
+    pub fn for_scale_factor(scale_factor: f64) -> Self {
+        Self {
+            key: if scale_factor > 300. {
+                DataType::Int64
+            } else {
+                DataType::Int32
+            },
+        }
+    }
+}
+
+/// Int64 keys, that fit any scale factor
+impl Default for TpchTypes {
+    fn default() -> Self {
+        Self {
+            key: DataType::Int64,
+        }
+    }
+}
+
+/// The column types of the TPC-H tables as they can be set in events and configs
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TpchSchemaOptions {
+    /// scale factor of the dataset, selects Int32 keys when small enough
+    pub scale_factor: Option<f64>,
+}
+
+impl TpchSchemaOptions {
+    pub fn types(&self) -> TpchTypes {
+        match self.scale_factor {
+            Some(scale_factor) => TpchTypes::for_scale_factor(scale_factor),
+            None => TpchTypes::default(),
+        }
+    }
+}
+
+fn get_schema(table: &str, types: &TpchTypes) -> Schema {
     match table {
         "part" => Schema::new(vec![
-            Field::new("p_partkey", DataType::Int32, false),
+            Field::new("p_partkey", types.key.clone(), false),
             Field::new("p_name", DataType::Utf8, false),
             Field::new("p_mfgr", DataType::Utf8, false),
             Field::new("p_brand", DataType::Utf8, false),
@@ -51,28 +105,28 @@ fn get_schema(table: &str) -> Schema {
         ]),
 
         "supplier" => Schema::new(vec![
-            Field::new("s_suppkey", DataType::Int32, false),
+            Field::new("s_suppkey", types.key.clone(), false),
             Field::new("s_name", DataType::Utf8, false),
             Field::new("s_address", DataType::Utf8, false),
-            Field::new("s_nationkey", DataType::Int32, false),
+            Field::new("s_nationkey", types.key.clone(), false),
             Field::new("s_phone", DataType::Utf8, false),
             Field::new("s_acctbal", DataType::Float64, false),
             Field::new("s_comment", DataType::Utf8, false),
         ]),
 
         "partsupp" => Schema::new(vec![
-            Field::new("ps_partkey", DataType::Int32, false),
-            Field::new("ps_suppkey", DataType::Int32, false),
+            Field::new("ps_partkey", types.key.clone(), false),
+            Field::new("ps_suppkey", types.key.clone(), false),
             Field::new("ps_availqty", DataType::Int32, false),
             Field::new("ps_supplycost", DataType::Float64, false),
             Field::new("ps_comment", DataType::Utf8, false),
         ]),
 
         "customer" => Schema::new(vec![
-            Field::new("c_custkey", DataType::Int32, false),
+            Field::new("c_custkey", types.key.clone(), false),
             Field::new("c_name", DataType::Utf8, false),
             Field::new("c_address", DataType::Utf8, false),
-            Field::new("c_nationkey", DataType::Int32, false),
+            Field::new("c_nationkey", types.key.clone(), false),
             Field::new("c_phone", DataType::Utf8, false),
             Field::new("c_acctbal", DataType::Float64, false),
             Field::new("c_mktsegment", DataType::Utf8, false),
@@ -80,8 +134,8 @@ fn get_schema(table: &str) -> Schema {
         ]),
 
         "orders" => Schema::new(vec![
-            Field::new("o_orderkey", DataType::Int32, false),
-            Field::new("o_custkey", DataType::Int32, false),
+            Field::new("o_orderkey", types.key.clone(), false),
+            Field::new("o_custkey", types.key.clone(), false),
             Field::new("o_orderstatus", DataType::Utf8, false),
             Field::new("o_totalprice", DataType::Float64, false),
             Field::new("o_orderdate", DataType::Date32, false),
@@ -92,9 +146,9 @@ fn get_schema(table: &str) -> Schema {
         ]),
 
         "lineitem" => Schema::new(vec![
-            Field::new("l_orderkey", DataType::Int32, false),
-            Field::new("l_partkey", DataType::Int32, false),
-            Field::new("l_suppkey", DataType::Int32, false),
+            Field::new("l_orderkey", types.key.clone(), false),
+            Field::new("l_partkey", types.key.clone(), false),
+            Field::new("l_suppkey", types.key.clone(), false),
             Field::new("l_linenumber", DataType::Int32, false),
             Field::new("l_quantity", DataType::Float64, false),
             Field::new("l_extendedprice", DataType::Float64, false),
@@ -111,14 +165,14 @@ fn get_schema(table: &str) -> Schema {
         ]),
 
         "nation" => Schema::new(vec![
-            Field::new("n_nationkey", DataType::Int32, false),
+            Field::new("n_nationkey", types.key.clone(), false),
             Field::new("n_name", DataType::Utf8, false),
-            Field::new("n_regionkey", DataType::Int32, false),
+            Field::new("n_regionkey", types.key.clone(), false),
             Field::new("n_comment", DataType::Utf8, false),
         ]),
 
         "region" => Schema::new(vec![
-            Field::new("r_regionkey", DataType::Int32, false),
+            Field::new("r_regionkey", types.key.clone(), false),
             Field::new("r_name", DataType::Utf8, false),
             Field::new("r_comment", DataType::Utf8, false),
         ]),
@@ -127,10 +181,10 @@ fn get_schema(table: &str) -> Schema {
     }
 }
 
-pub fn register_simple_tpch_tables(ctx: &mut BallistaContext) -> Result<()> {
+pub fn register_simple_tpch_tables(ctx: &mut BallistaContext, types: &TpchTypes) -> Result<()> {
     for table in TABLES {
         let path = format!("/mnt/data/{}.tbl", table);
-        let schema = get_schema(table);
+        let schema = get_schema(table, types);
         let options = CsvReadOptions::new()
             .schema(&schema)
             .delimiter(b'|')
@@ -141,10 +195,10 @@ pub fn register_simple_tpch_tables(ctx: &mut BallistaContext) -> Result<()> {
     Ok(())
 }
 
-pub fn register_memsql_tpch_tables(ctx: &mut BallistaContext) -> Result<()> {
+pub fn register_memsql_tpch_tables(ctx: &mut BallistaContext, types: &TpchTypes) -> Result<()> {
     for table in TABLES {
-        let path = format!("/mnt/data/{}/", table);
-        let schema = get_schema(table);
+        let path = tpch_table_path(table, TpchFormat::Tbl);
+        let schema = get_schema(table, types);
         let options = CsvReadOptions::new()
             .schema(&schema)
             .delimiter(b'|')
@@ -154,3 +208,178 @@ pub fn register_memsql_tpch_tables(ctx: &mut BallistaContext) -> Result<()> {
     }
     Ok(())
 }
+
+pub fn register_parquet_tpch_tables(ctx: &mut BallistaContext) -> Result<()> {
+    for table in TABLES {
+        let path = tpch_table_path(table, TpchFormat::Parquet);
+        ctx.register_parquet(table, &path)?;
+    }
+    Ok(())
+}
+
+/// Storage format of the TPC-H tables
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TpchFormat {
+    /// `.tbl` files generated by dbgen, split in partitions (memsql layout)
+    Tbl,
+    /// output of `convert_tbl_to_parquet`
+    Parquet,
+}
+
+impl Default for TpchFormat {
+    fn default() -> Self {
+        TpchFormat::Tbl
+    }
+}
+
+impl FromStr for TpchFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tbl" => Ok(TpchFormat::Tbl),
+            "parquet" => Ok(TpchFormat::Parquet),
+            _ => bail!("Unknown TPC-H format: {}", s),
+        }
+    }
+}
+
+/// Directory of the files of a TPC-H table in the given format
+pub fn tpch_table_path(table: &str, format: TpchFormat) -> String {
+    match format {
+        TpchFormat::Tbl => format!("/mnt/data/{}/", table),
+        TpchFormat::Parquet => format!("/mnt/data/parquet/{}/", table),
+    }
+}
+
+/// Register the TPC-H tables. The types only apply to `.tbl` files,
+/// Parquet files embed their schema.
+pub fn register_tpch_tables(
+    ctx: &mut BallistaContext,
+    format: TpchFormat,
+    types: &TpchTypes,
+) -> Result<()> {
+    match format {
+        TpchFormat::Tbl => register_memsql_tpch_tables(ctx, types),
+        TpchFormat::Parquet => register_parquet_tpch_tables(ctx),
+    }
+}
+
+/// Register the TPC-H tables on a local DataFusion context, e.g to compute reference results
+pub fn register_local_tpch_tables(
+    ctx: &mut ExecutionContext,
+    format: TpchFormat,
+    types: &TpchTypes,
+) -> Result<()> {
+    for table in TABLES {
+        match format {
+            TpchFormat::Tbl => {
+                let path = tpch_table_path(table, format);
+                let schema = get_schema(table, types);
+                let options = CsvReadOptions::new()
+                    .schema(&schema)
+                    .delimiter(b'|')
+                    .has_header(false)
+                    .file_extension(".tbl");
+                ctx.register_csv(table, &path, options)?;
+            }
+            TpchFormat::Parquet => {
+                let path = tpch_table_path(table, format);
+                ctx.register_parquet(table, &path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub struct ParquetConversionOptions {
+    pub row_group_size: usize,
+    pub compression: Compression,
+    /// number of Parquet files per table
+    pub partitions: usize,
+    /// types of the Parquet columns
+    pub types: TpchTypes,
+}
+
+pub fn parse_compression(compression: &str) -> Result<Compression> {
+    match compression.to_lowercase().as_str() {
+        "uncompressed" | "none" => Ok(Compression::UNCOMPRESSED),
+        "snappy" => Ok(Compression::SNAPPY),
+        "gzip" => Ok(Compression::GZIP),
+        "lzo" => Ok(Compression::LZO),
+        "brotli" => Ok(Compression::BROTLI),
+        "lz4" => Ok(Compression::LZ4),
+        "zstd" => Ok(Compression::ZSTD),
+        _ => bail!("unknown parquet compression {}", compression),
+    }
+}
+
+/// Convert the `.tbl` dataset in `input_dir` into partitioned Parquet tables in `output_dir`.
+/// Tables can either be single `{table}.tbl` files or `{table}/` directories of partitions.
+pub async fn convert_tbl_to_parquet(
+    input_dir: &str,
+    output_dir: &str,
+    options: &ParquetConversionOptions,
+) -> Result<()> {
+    for table in TABLES {
+        let dir_path = format!("{}/{}", input_dir, table);
+        let input_path = if Path::new(&dir_path).is_dir() {
+            dir_path
+        } else {
+            format!("{}/{}.tbl", input_dir, table)
+        };
+        let output_path = format!("{}/{}", output_dir, table);
+        info!("converting {} to {}", input_path, output_path);
+
+        let config = ExecutionConfig::new().with_concurrency(options.partitions);
+        let mut ctx = ExecutionContext::with_config(config);
+        let schema = get_schema(table, &options.types);
+        let csv_options = CsvReadOptions::new()
+            .schema(&schema)
+            .delimiter(b'|')
+            .has_header(false)
+            .file_extension(".tbl");
+        let df = ctx
+            .read_csv(&input_path, csv_options)?
+            .repartition(Partitioning::RoundRobinBatch(options.partitions))?;
+        let plan = ctx.optimize(&df.to_logical_plan())?;
+        let plan = ctx.create_physical_plan(&plan)?;
+
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(options.row_group_size)
+            .set_compression(options.compression)
+            .build();
+        ctx.write_parquet(plan, output_path, Some(props)).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_types() {
+        // without a scale factor, the keys fit any dataset
+        assert_eq!(TpchSchemaOptions::default().types().key, DataType::Int64);
+        let types = |scale_factor| {
+            TpchSchemaOptions {
+                scale_factor: Some(scale_factor),
+            }
+            .types()
+            .key
+        };
+        assert_eq!(types(100.), DataType::Int32);
+        assert_eq!(types(1000.), DataType::Int64);
+    }
+
+    #[test]
+    fn all_queries_are_defined() {
+        for query in 1..=22 {
+            assert!(get_query(query).unwrap().to_lowercase().contains("select"));
+        }
+        assert!(get_query(0).is_err());
+        assert!(get_query(23).is_err());
+    }
+}
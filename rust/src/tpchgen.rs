@@ -0,0 +1,688 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{Duration, NaiveDate};
+use futures::{StreamExt, TryStreamExt};
+use log::info;
+
+use crate::tpch::TABLES;
+
+// Generator of the TPC-H dataset following the value domains of the specification.
+// Every row is generated from its own random stream derived from the seed, so the
+// output does not depend on the number of files per table. The child tables
+// (lineitem, partsupp) replay the stream of their parent row so that both agree.
+
+const COLORS: &[&str] = &[
+    "almond", "antique", "aquamarine", "azure", "beige", "bisque", "black", "blanched", "blue",
+    "blush", "brown", "burlywood", "burnished", "chartreuse", "chiffon", "chocolate", "coral",
+    "cornflower", "cornsilk", "cream", "cyan", "dark", "deep", "dim", "dodger", "drab",
+    "firebrick", "floral", "forest", "frosted", "gainsboro", "ghost", "goldenrod", "green",
+    "grey", "honeydew", "hot", "indian", "ivory", "khaki", "lace", "lavender", "lawn", "lemon",
+    "light", "lime", "linen", "magenta", "maroon", "medium", "metallic", "midnight", "mint",
+    "misty", "moccasin", "navajo", "navy", "olive", "orange", "orchid", "pale", "papaya",
+    "peach", "peru", "pink", "plum", "powder", "puff", "purple", "red", "rose", "rosy", "royal",
+    "saddle", "salmon", "sandy", "seashell", "sienna", "sky", "slate", "smoke", "snow", "spring",
+    "steel", "tan", "thistle", "tomato", "turquoise", "violet", "wheat", "white", "yellow",
+];
+
+const TYPE_SIZES: &[&str] = &["STANDARD", "SMALL", "MEDIUM", "LARGE", "ECONOMY", "PROMO"];
+const TYPE_FINISHES: &[&str] = &["ANODIZED", "BURNISHED", "PLATED", "POLISHED", "BRUSHED"];
+const TYPE_MATERIALS: &[&str] = &["TIN", "NICKEL", "BRASS", "STEEL", "COPPER"];
+const CONTAINER_SIZES: &[&str] = &["SM", "LG", "MED", "JUMBO", "WRAP"];
+const CONTAINER_TYPES: &[&str] = &["CASE", "BOX", "BAG", "JAR", "PKG", "PACK", "CAN", "DRUM"];
+const SEGMENTS: &[&str] = &[
+    "AUTOMOBILE",
+    "BUILDING",
+    "FURNITURE",
+    "MACHINERY",
+    "HOUSEHOLD",
+];
+const PRIORITIES: &[&str] = &["1-URGENT", "2-HIGH", "3-MEDIUM", "4-NOT SPECIFIED", "5-LOW"];
+const INSTRUCTIONS: &[&str] = &["DELIVER IN PERSON", "COLLECT COD", "NONE", "TAKE BACK RETURN"];
+const MODES: &[&str] = &["REG AIR", "AIR", "RAIL", "SHIP", "TRUCK", "MAIL", "FOB"];
+
+const REGIONS: &[&str] = &["AFRICA", "AMERICA", "ASIA", "EUROPE", "MIDDLE EAST"];
+const NATIONS: &[(&str, i64)] = &[
+    ("ALGERIA", 0),
+    ("ARGENTINA", 1),
+    ("BRAZIL", 1),
+    ("CANADA", 1),
+    ("EGYPT", 4),
+    ("ETHIOPIA", 0),
+    ("FRANCE", 3),
+    ("GERMANY", 3),
+    ("INDIA", 2),
+    ("INDONESIA", 2),
+    ("IRAN", 4),
+    ("IRAQ", 4),
+    ("JAPAN", 2),
+    ("JORDAN", 4),
+    ("KENYA", 0),
+    ("MOROCCO", 0),
+    ("MOZAMBIQUE", 0),
+    ("PERU", 1),
+    ("CHINA", 2),
+    ("ROMANIA", 3),
+    ("SAUDI ARABIA", 4),
+    ("VIETNAM", 2),
+    ("RUSSIA", 3),
+    ("UNITED KINGDOM", 3),
+    ("UNITED STATES", 1),
+];
+
+// vocabulary of the comments
+const NOUNS: &[&str] = &[
+    "packages", "requests", "accounts", "deposits", "foxes", "ideas", "theodolites",
+    "pinto beans", "instructions", "dependencies", "excuses", "platelets", "asymptotes",
+    "courts", "dolphins", "multipliers", "sauternes", "warthogs", "frets", "dinos",
+    "attainments", "somas", "patterns", "forges", "braids", "frays", "warhorses", "dugouts",
+    "epitaphs", "pearls", "tithes", "waters", "orbits", "gifts", "sheaves", "depths",
+    "sentiments", "decoys", "realms", "pains", "grouches", "escapades",
+];
+const VERBS: &[&str] = &[
+    "sleep", "wake", "are", "cajole", "haggle", "nag", "use", "boost", "affix", "detect",
+    "integrate", "maintain", "nod", "was", "lose", "sublate", "solve", "thrash", "promise",
+    "engage", "hinder", "print", "x-ray", "breach", "eat", "grow", "impress", "mold", "poach",
+    "serve", "run", "dazzle", "snooze", "doze", "unwind", "kindle", "play", "hang", "believe",
+    "doubt",
+];
+const ADJECTIVES: &[&str] = &[
+    "special", "pending", "unusual", "express", "furious", "sly", "careful", "blithe", "quick",
+    "fluffy", "slow", "quiet", "ruthless", "thin", "close", "dogged", "daring", "brave",
+    "stealthy", "permanent", "enticing", "idle", "busy", "regular", "final", "ironic", "even",
+    "bold", "silent",
+];
+const ADVERBS: &[&str] = &[
+    "sometimes", "always", "never", "furiously", "slyly", "carefully", "blithely", "quickly",
+    "fluffily", "slowly", "quietly", "ruthlessly", "thinly", "closely", "doggedly", "daringly",
+    "bravely", "stealthily", "permanently", "enticingly", "idly", "busily", "regularly",
+    "finally", "ironically", "evenly", "boldly", "silently",
+];
+const PREPOSITIONS: &[&str] = &[
+    "about", "above", "according to", "across", "after", "against", "along", "among", "around",
+    "at", "atop", "before", "behind", "beneath", "beside", "besides", "between", "beyond", "by",
+    "despite", "during", "except", "for", "from", "inside", "into", "near", "of", "on",
+    "outside", "over", "past", "since", "through", "throughout", "to", "toward", "under",
+    "until", "up", "upon", "without", "with", "within",
+];
+const TERMINATORS: &[&str] = &[".", ";", ":", "?", "!", "--"];
+
+fn start_date() -> NaiveDate {
+    NaiveDate::from_ymd(1992, 1, 1)
+}
+
+fn current_date() -> NaiveDate {
+    NaiveDate::from_ymd(1995, 6, 17)
+}
+
+/// last order date, 151 days before the end date so that all lineitems are shipped
+fn last_order_date() -> NaiveDate {
+    NaiveDate::from_ymd(1998, 12, 31) - Duration::days(151)
+}
+
+/// A small deterministic random generator (splitmix64)
+struct Rng(u64);
+
+impl Rng {
+    /// The random stream of a given row of a generating entity
+    fn for_row(seed: u64, stream: u64, row: u64) -> Self {
+        let mut rng = Rng(seed
+            ^ (stream + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ row.wrapping_mul(0xD1B5_4A32_D192_ED03));
+        rng.next_u64();
+        rng
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// uniform value in [low, high]
+    fn range(&mut self, low: i64, high: i64) -> i64 {
+        low + (self.next_u64() % ((high - low + 1) as u64)) as i64
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.range(0, items.len() as i64 - 1) as usize]
+    }
+
+    /// random alphanumeric string with a length in [min, max]
+    fn vstring(&mut self, min: i64, max: i64) -> String {
+        const CHARS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ,";
+        (0..self.range(min, max))
+            .map(|_| CHARS[self.range(0, CHARS.len() as i64 - 1) as usize] as char)
+            .collect()
+    }
+
+    /// random sentences with a length in [min, max]
+    fn text(&mut self, min: i64, max: i64) -> String {
+        let len = self.range(min, max) as usize;
+        let mut text = String::with_capacity(len + 64);
+        while text.len() < len {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            let words = [
+                self.pick(ADJECTIVES),
+                self.pick(NOUNS),
+                self.pick(VERBS),
+                self.pick(ADVERBS),
+                self.pick(PREPOSITIONS),
+                "the",
+                self.pick(ADJECTIVES),
+                self.pick(NOUNS),
+            ];
+            text.push_str(&words.join(" "));
+            text.push_str(self.pick(TERMINATORS));
+        }
+        text.truncate(len);
+        text.trim_end().to_owned()
+    }
+
+    fn phone(&mut self, nation_key: i64) -> String {
+        format!(
+            "{}-{}-{}-{}",
+            nation_key + 10,
+            self.range(100, 999),
+            self.range(100, 999),
+            self.range(1000, 9999)
+        )
+    }
+}
+
+/// format an amount of cents as a decimal with 2 digits
+fn money(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{}{}.{:02}", sign, cents.abs() / 100, cents.abs() % 100)
+}
+
+/// Number of rows of each table, or of orders for the lineitem table
+/// and of parts for the partsupp table
+struct Cardinalities {
+    part: i64,
+    supplier: i64,
+    customer: i64,
+    orders: i64,
+    clerks: i64,
+}
+
+impl Cardinalities {
+    fn new(scale_factor: f64) -> Self {
+        let scaled = |base: f64| ((base * scale_factor) as i64).max(1);
+        Self {
+            part: scaled(200_000.),
+            supplier: scaled(10_000.),
+            customer: scaled(150_000.),
+            orders: scaled(1_500_000.),
+            clerks: scaled(1_000.),
+        }
+    }
+
+    /// number of generation units (rows, or parent rows) of the table
+    fn units(&self, table: &str) -> Result<i64> {
+        let units = match table {
+            "part" | "partsupp" => self.part,
+            "supplier" => self.supplier,
+            "customer" => self.customer,
+            "orders" | "lineitem" => self.orders,
+            "nation" => NATIONS.len() as i64,
+            "region" => REGIONS.len() as i64,
+            _ => bail!("Unknown TPC-H table {}", table),
+        };
+        Ok(units)
+    }
+
+    fn retail_price(part_key: i64) -> i64 {
+        90_000 + ((part_key / 10) % 20_001) + 100 * (part_key % 1000)
+    }
+
+    /// the i-th supplier (0 to 3) of a part
+    fn part_supplier(&self, part_key: i64, i: i64) -> i64 {
+        let s = self.supplier;
+        (part_key + (i * ((s / 4) + (part_key - 1) / s))) % s + 1
+    }
+}
+
+/// Id of the random stream of the rows of the table, shared by the tables
+/// generated from the same entity (part and partsupp, orders and lineitem)
+fn stream_id(table: &str) -> Result<u64> {
+    let stream = match table {
+        "part" | "partsupp" => 0,
+        "supplier" => 1,
+        "customer" => 2,
+        "orders" | "lineitem" => 3,
+        "nation" => 4,
+        "region" => 5,
+        _ => bail!("Unknown TPC-H table {}", table),
+    };
+    Ok(stream)
+}
+
+/// orders keys are sparse, only the first 8 of every 32 keys are used
+fn order_key(index: i64) -> i64 {
+    (index / 8) * 32 + (index % 8) + 1
+}
+
+struct Generator {
+    seed: u64,
+    card: Cardinalities,
+}
+
+impl Generator {
+    fn write_rows(&self, table: &str, units: Range<i64>, out: &mut impl Write) -> Result<()> {
+        let stream = stream_id(table)?;
+        for unit in units {
+            let mut rng = Rng::for_row(self.seed, stream, unit as u64);
+            match table {
+                "region" => self.region(&mut rng, unit, out)?,
+                "nation" => self.nation(&mut rng, unit, out)?,
+                "supplier" => self.supplier(&mut rng, unit + 1, out)?,
+                "part" => self.part(&mut rng, unit + 1, out)?,
+                "partsupp" => {
+                    self.part(&mut rng, unit + 1, &mut io::sink())?;
+                    self.partsupp(&mut rng, unit + 1, out)?
+                }
+                "customer" => self.customer(&mut rng, unit + 1, out)?,
+                "orders" => self.order(&mut rng, unit, out, None)?,
+                "lineitem" => {
+                    self.order(&mut rng, unit, &mut io::sink(), Some(out as &mut dyn Write))?
+                }
+                _ => bail!("Unknown TPC-H table {}", table),
+            }
+        }
+        Ok(())
+    }
+
+    fn region(&self, rng: &mut Rng, key: i64, out: &mut impl Write) -> Result<()> {
+        writeln!(
+            out,
+            "{}|{}|{}|",
+            key,
+            REGIONS[key as usize],
+            rng.text(31, 115)
+        )?;
+        Ok(())
+    }
+
+    fn nation(&self, rng: &mut Rng, key: i64, out: &mut impl Write) -> Result<()> {
+        let (name, region) = NATIONS[key as usize];
+        writeln!(out, "{}|{}|{}|{}|", key, name, region, rng.text(31, 114))?;
+        Ok(())
+    }
+
+    fn supplier(&self, rng: &mut Rng, key: i64, out: &mut impl Write) -> Result<()> {
+        let nation = rng.range(0, 24);
+        let mut comment = rng.text(25, 100);
+        // about 5 suppliers per 10000 have complaints and 5 have recommendations (Q16)
+        match rng.range(0, 1999) {
+            0 => comment = format!("{} Customer Complaints", &comment[..comment.len() / 2]),
+            1 => comment = format!("{} Customer Recommends", &comment[..comment.len() / 2]),
+            _ => {}
+        }
+        writeln!(
+            out,
+            "{}|Supplier#{:09}|{}|{}|{}|{}|{}|",
+            key,
+            key,
+            rng.vstring(10, 40),
+            nation,
+            rng.phone(nation),
+            money(rng.range(-99_999, 999_999)),
+            comment
+        )?;
+        Ok(())
+    }
+
+    fn part(&self, rng: &mut Rng, key: i64, out: &mut impl Write) -> Result<()> {
+        let mut name: Vec<&str> = vec![];
+        while name.len() < 5 {
+            let color = rng.pick(COLORS);
+            if !name.contains(&color) {
+                name.push(color);
+            }
+        }
+        let manufacturer = rng.range(1, 5);
+        writeln!(
+            out,
+            "{}|{}|Manufacturer#{}|Brand#{}{}|{} {} {}|{}|{} {}|{}|{}|",
+            key,
+            name.join(" "),
+            manufacturer,
+            manufacturer,
+            rng.range(1, 5),
+            rng.pick(TYPE_SIZES),
+            rng.pick(TYPE_FINISHES),
+            rng.pick(TYPE_MATERIALS),
+            rng.range(1, 50),
+            rng.pick(CONTAINER_SIZES),
+            rng.pick(CONTAINER_TYPES),
+            money(Cardinalities::retail_price(key)),
+            rng.text(5, 22)
+        )?;
+        Ok(())
+    }
+
+    fn partsupp(&self, rng: &mut Rng, part_key: i64, out: &mut impl Write) -> Result<()> {
+        for i in 0..4 {
+            writeln!(
+                out,
+                "{}|{}|{}|{}|{}|",
+                part_key,
+                self.card.part_supplier(part_key, i),
+                rng.range(1, 9999),
+                money(rng.range(100, 100_000)),
+                rng.text(49, 198)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn customer(&self, rng: &mut Rng, key: i64, out: &mut impl Write) -> Result<()> {
+        let nation = rng.range(0, 24);
+        writeln!(
+            out,
+            "{}|Customer#{:09}|{}|{}|{}|{}|{}|{}|",
+            key,
+            key,
+            rng.vstring(10, 40),
+            nation,
+            rng.phone(nation),
+            money(rng.range(-99_999, 999_999)),
+            rng.pick(SEGMENTS),
+            rng.text(29, 116)
+        )?;
+        Ok(())
+    }
+
+    /// Generate an order and its lineitems, which determine the order status and price
+    fn order(
+        &self,
+        rng: &mut Rng,
+        index: i64,
+        orders: &mut impl Write,
+        lineitems: Option<&mut dyn Write>,
+    ) -> Result<()> {
+        let key = order_key(index);
+        // a third of the customers have no orders
+        let mut customer = rng.range(1, self.card.customer);
+        while customer % 3 == 0 {
+            customer = rng.range(1, self.card.customer);
+        }
+        let order_days = (last_order_date() - start_date()).num_days();
+        let order_date = start_date() + Duration::days(rng.range(0, order_days));
+
+        let mut lines = String::new();
+        let mut total_price = 0.;
+        let mut shipped_count = 0;
+        let line_count = rng.range(1, 7);
+        for line_number in 1..=line_count {
+            let part = rng.range(1, self.card.part);
+            let supplier = self.card.part_supplier(part, rng.range(0, 3));
+            let quantity = rng.range(1, 50);
+            let extended_price = quantity * Cardinalities::retail_price(part);
+            let discount = rng.range(0, 10);
+            let tax = rng.range(0, 8);
+            let ship_date = order_date + Duration::days(rng.range(1, 121));
+            let commit_date = order_date + Duration::days(rng.range(30, 90));
+            let receipt_date = ship_date + Duration::days(rng.range(1, 30));
+            let return_flag = if receipt_date <= current_date() {
+                if rng.range(0, 1) == 0 {
+                    "R"
+                } else {
+                    "A"
+                }
+            } else {
+                "N"
+            };
+            let line_status = if ship_date > current_date() {
+                "O"
+            } else {
+                shipped_count += 1;
+                "F"
+            };
+            total_price += extended_price as f64 * (100 + tax) as f64 / 100.
+                * (100 - discount) as f64
+                / 100.;
+            lines.push_str(&format!(
+                "{}|{}|{}|{}|{}|{}|0.{:02}|0.{:02}|{}|{}|{}|{}|{}|{}|{}|{}|\n",
+                key,
+                part,
+                supplier,
+                line_number,
+                quantity,
+                money(extended_price),
+                discount,
+                tax,
+                return_flag,
+                line_status,
+                ship_date,
+                commit_date,
+                receipt_date,
+                rng.pick(INSTRUCTIONS),
+                rng.pick(MODES),
+                rng.text(10, 43)
+            ));
+        }
+        let status = match shipped_count {
+            0 => "O",
+            n if n == line_count => "F",
+            _ => "P",
+        };
+        writeln!(
+            orders,
+            "{}|{}|{}|{}|{}|{}|Clerk#{:09}|0|{}|",
+            key,
+            customer,
+            status,
+            money(total_price.round() as i64),
+            order_date,
+            rng.pick(PRIORITIES),
+            rng.range(1, self.card.clerks),
+            rng.text(19, 78)
+        )?;
+        if let Some(lineitems) = lineitems {
+            lineitems.write_all(lines.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the chunk `chunk` of `chunks` of the given table to `{output_dir}/{table}/{chunk}.tbl`
+pub fn generate_chunk(
+    table: &str,
+    scale_factor: f64,
+    seed: u64,
+    chunk: usize,
+    chunks: usize,
+    output_dir: &str,
+) -> Result<()> {
+    let generator = Generator {
+        seed,
+        card: Cardinalities::new(scale_factor),
+    };
+    let units = generator.card.units(table)?;
+    let start = units * chunk as i64 / chunks as i64;
+    let end = units * (chunk as i64 + 1) / chunks as i64;
+
+    let table_dir = format!("{}/{}", output_dir, table);
+    fs::create_dir_all(&table_dir)
+        .with_context(|| format!("Could not create directory {}", table_dir))?;
+    let path = format!("{}/{:03}.tbl", table_dir, chunk);
+    let file = File::create(&path).with_context(|| format!("Could not create {}", path))?;
+    let mut out = BufWriter::new(file);
+    generator.write_rows(table, start..end, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Generate all the TPC-H tables at the given scale factor, each split into `chunks` files.
+/// The small nation and region tables are always written as a single file.
+pub async fn generate_tables(
+    scale_factor: f64,
+    seed: u64,
+    chunks: usize,
+    output_dir: &str,
+    parallelism: usize,
+) -> Result<()> {
+    ensure!(chunks > 0, "The number of files per table should be at least 1");
+    ensure!(parallelism > 0, "The parallelism should be at least 1");
+    let tasks = TABLES.iter().flat_map(|table| {
+        let table_chunks = match *table {
+            "nation" | "region" => 1,
+            _ => chunks,
+        };
+        (0..table_chunks).map(move |chunk| (*table, chunk, table_chunks))
+    });
+    futures::stream::iter(tasks)
+        .map(|(table, chunk, table_chunks)| {
+            let output_dir = output_dir.to_owned();
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    generate_chunk(table, scale_factor, seed, chunk, table_chunks, &output_dir)
+                })
+                .await??;
+                info!("generated {} chunk {}/{}", table, chunk + 1, table_chunks);
+                Ok::<_, anyhow::Error>(())
+            }
+        })
+        .buffer_unordered(parallelism)
+        .try_collect::<Vec<_>>()
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    const SCALE_FACTOR: f64 = 0.001;
+
+    fn generate(dir: &Path, seed: u64, chunks: usize) {
+        for table in TABLES {
+            for chunk in 0..chunks {
+                generate_chunk(table, SCALE_FACTOR, seed, chunk, chunks, dir.to_str().unwrap())
+                    .unwrap();
+            }
+        }
+    }
+
+    /// All the rows of the table, in the order of its chunks
+    fn read_table(dir: &Path, table: &str) -> String {
+        let mut paths = fs::read_dir(dir.join(table))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect()
+    }
+
+    fn cents(money: &str) -> i64 {
+        let (units, cents) = money.split_once('.').unwrap();
+        let units: i64 = units.parse().unwrap();
+        let cents: i64 = cents.parse().unwrap();
+        if money.starts_with('-') {
+            units * 100 - cents
+        } else {
+            units * 100 + cents
+        }
+    }
+
+    #[test]
+    fn same_seed_same_bytes() {
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        generate(first.path(), 42, 2);
+        generate(second.path(), 42, 2);
+        for table in TABLES {
+            let path = Path::new(table).join("001.tbl");
+            assert_eq!(
+                fs::read(first.path().join(&path)).unwrap(),
+                fs::read(second.path().join(&path)).unwrap(),
+                "{} differs",
+                table
+            );
+        }
+
+        // the rows do not depend on the number of files
+        let single = tempfile::tempdir().unwrap();
+        generate(single.path(), 42, 1);
+        for table in TABLES {
+            assert_eq!(
+                read_table(first.path(), table),
+                read_table(single.path(), table)
+            );
+        }
+
+        let other = tempfile::tempdir().unwrap();
+        generate(other.path(), 43, 1);
+        assert_ne!(
+            read_table(single.path(), "orders"),
+            read_table(other.path(), "orders")
+        );
+    }
+
+    #[test]
+    fn lineitems_match_their_orders() {
+        let dir = tempfile::tempdir().unwrap();
+        generate(dir.path(), 7, 3);
+
+        let mut order_prices = HashMap::new();
+        for row in read_table(dir.path(), "orders").lines() {
+            let fields: Vec<&str> = row.split('|').collect();
+            order_prices.insert(fields[0].to_owned(), cents(fields[3]));
+        }
+        assert_eq!(order_prices.len(), 1500);
+
+        let mut line_totals: HashMap<String, f64> = HashMap::new();
+        for row in read_table(dir.path(), "lineitem").lines() {
+            let fields: Vec<&str> = row.split('|').collect();
+            let extended_price = cents(fields[5]);
+            let discount = cents(fields[6]);
+            let tax = cents(fields[7]);
+            // same operations as the generator for an exact match
+            *line_totals.entry(fields[0].to_owned()).or_default() += extended_price as f64
+                * (100 + tax) as f64
+                / 100.
+                * (100 - discount) as f64
+                / 100.;
+        }
+        assert_eq!(line_totals.len(), order_prices.len());
+        for (order_key, total) in line_totals {
+            let order_price = order_prices
+                .get(&order_key)
+                .unwrap_or_else(|| panic!("order {} of lineitem is missing", order_key));
+            assert_eq!(total.round() as i64, *order_price, "order {}", order_key);
+        }
+    }
+
+    #[test]
+    fn partsupp_rows_reference_their_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        generate(dir.path(), 7, 1);
+        let part_count = read_table(dir.path(), "part").lines().count() as i64;
+        let supplier_count = read_table(dir.path(), "supplier").lines().count() as i64;
+        let partsupp = read_table(dir.path(), "partsupp");
+        assert_eq!(partsupp.lines().count() as i64, part_count * 4);
+        for row in partsupp.lines() {
+            let fields: Vec<i64> = row.split('|').take(2).map(|f| f.parse().unwrap()).collect();
+            assert!((1..=part_count).contains(&fields[0]));
+            assert!((1..=supplier_count).contains(&fields[1]));
+        }
+    }
+
+    #[test]
+    fn unknown_tables_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(generate_chunk("nations", 1., 0, 0, 1, dir.path().to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use std::cmp::Ordering;
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::array_value_to_string;
+use datafusion::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::tpch::{get_query, register_local_tpch_tables, TpchFormat, TpchTypes};
+
+/// maximum number of mismatches listed in a report
+const MAX_REPORTED_MISMATCHES: usize = 10;
+
+/// Where the reference answers of the TPC-H queries come from
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReferenceSource {
+    /// run the query on a local single-node DataFusion context over the same tables
+    Local,
+    /// answers bundled with this crate for the dataset generated by
+    /// `tpch_gen --scale-factor 0.01 --seed 0`
+    Bundled,
+    /// directory of answer files `q{n}.out` as shipped with the TPC-H kit
+    /// (pipe separated values, with a header line)
+    Answers(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ValidationOptions {
+    pub reference: ReferenceSource,
+    /// relative tolerance for floating point values
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    /// absolute tolerance for floating point values, e.g. 0.005 for answers rounded to 2 digits
+    #[serde(default)]
+    pub abs_tolerance: f64,
+}
+
+fn default_tolerance() -> f64 {
+    1e-6
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Mismatch {
+    pub row: usize,
+    pub column: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub expected_rows: usize,
+    pub actual_rows: usize,
+    /// true if the rows were sorted before the comparison (query without ORDER BY)
+    pub order_insensitive: bool,
+    /// false if the reference answer only contains NULL values, as the bundled answers
+    /// of q17 and q19 where no row passes the filters: `valid` then only checks the shape
+    pub verifiable: bool,
+    /// the first mismatching values
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Check the results of a TPC-H query against the reference answer
+pub async fn validate_tpch_query(
+    tpch_query: u8,
+    format: TpchFormat,
+    types: &TpchTypes,
+    batches: &[RecordBatch],
+    options: &ValidationOptions,
+) -> Result<ValidationReport> {
+    let sql = get_query(tpch_query)?;
+    let expected = match &options.reference {
+        ReferenceSource::Local => {
+            let mut ctx = ExecutionContext::new();
+            register_local_tpch_tables(&mut ctx, format, types)?;
+            let expected_batches = ctx.sql(sql)?.collect().await?;
+            batches_to_rows(&expected_batches)
+        }
+        ReferenceSource::Bundled => parse_answer(bundled_answer(tpch_query)?),
+        ReferenceSource::Answers(dir) => {
+            let path = format!("{}/q{}.out", dir, tpch_query);
+            let answer = fs::read_to_string(&path)
+                .with_context(|| format!("Could not read reference answer {}", path))?;
+            parse_answer(&answer)
+        }
+    };
+    let order_insensitive = !has_order_by(sql);
+    Ok(compare(
+        expected,
+        batches_to_rows(batches),
+        order_insensitive,
+        options,
+    ))
+}
+
+/// Answer of the query for the dataset generated by `tpch_gen --scale-factor 0.01 --seed 0`
+fn bundled_answer(tpch_query: u8) -> Result<&'static str> {
+    match tpch_query {
+        1 => Ok(include_str!("tpch_answers/sf0.01/q1.out")),
+        2 => Ok(include_str!("tpch_answers/sf0.01/q2.out")),
+        3 => Ok(include_str!("tpch_answers/sf0.01/q3.out")),
+        4 => Ok(include_str!("tpch_answers/sf0.01/q4.out")),
+        5 => Ok(include_str!("tpch_answers/sf0.01/q5.out")),
+        6 => Ok(include_str!("tpch_answers/sf0.01/q6.out")),
+        7 => Ok(include_str!("tpch_answers/sf0.01/q7.out")),
+        8 => Ok(include_str!("tpch_answers/sf0.01/q8.out")),
+        9 => Ok(include_str!("tpch_answers/sf0.01/q9.out")),
+        10 => Ok(include_str!("tpch_answers/sf0.01/q10.out")),
+        11 => Ok(include_str!("tpch_answers/sf0.01/q11.out")),
+        12 => Ok(include_str!("tpch_answers/sf0.01/q12.out")),
+        13 => Ok(include_str!("tpch_answers/sf0.01/q13.out")),
+        14 => Ok(include_str!("tpch_answers/sf0.01/q14.out")),
+        15 => Ok(include_str!("tpch_answers/sf0.01/q15.out")),
+        16 => Ok(include_str!("tpch_answers/sf0.01/q16.out")),
+        17 => Ok(include_str!("tpch_answers/sf0.01/q17.out")),
+        18 => Ok(include_str!("tpch_answers/sf0.01/q18.out")),
+        19 => Ok(include_str!("tpch_answers/sf0.01/q19.out")),
+        20 => Ok(include_str!("tpch_answers/sf0.01/q20.out")),
+        21 => Ok(include_str!("tpch_answers/sf0.01/q21.out")),
+        22 => Ok(include_str!("tpch_answers/sf0.01/q22.out")),
+        _ => bail!("unknown tpch query {}", tpch_query),
+    }
+}
+
+fn has_order_by(sql: &str) -> bool {
+    sql.to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .windows(2)
+        .any(|words| words == ["order", "by"])
+}
+
+fn batches_to_rows(batches: &[RecordBatch]) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    for batch in batches {
+        for row in 0..batch.num_rows() {
+            rows.push(
+                batch
+                    .columns()
+                    .iter()
+                    .map(|column| array_value_to_string(column, row).unwrap_or_default())
+                    .collect(),
+            );
+        }
+    }
+    rows
+}
+
+/// Parse a TPC-H kit answer file, skipping the header line.
+/// A single empty line is the row of an aggregate over no rows (NULL).
+fn parse_answer(answer: &str) -> Vec<Vec<String>> {
+    let lines: Vec<&str> = answer.lines().skip(1).collect();
+    if let [line] = lines.as_slice() {
+        if line.trim().is_empty() {
+            return vec![vec![String::new()]];
+        }
+    }
+    lines
+        .into_iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            line.trim_end_matches('|')
+                .split('|')
+                .map(|value| value.trim().to_owned())
+                .collect()
+        })
+        .collect()
+}
+
+/// An answer without any non NULL value can't tell a right result from a wrong one
+fn is_verifiable(answer: &[Vec<String>]) -> bool {
+    answer.iter().flatten().any(|value| !value.is_empty())
+}
+
+fn values_match(expected: &str, actual: &str, options: &ValidationOptions) -> bool {
+    match (expected.parse::<f64>(), actual.parse::<f64>()) {
+        (Ok(expected), Ok(actual)) => {
+            let allowed = (expected.abs() * options.tolerance).max(options.abs_tolerance);
+            (expected - actual).abs() <= allowed
+        }
+        _ => expected.trim() == actual.trim(),
+    }
+}
+
+/// Order of the rows for the order insensitive comparison, numbers are compared by value
+fn compare_rows(a: &[String], b: &[String]) -> Ordering {
+    for (a, b) in a.iter().zip(b) {
+        let ordering = match (a.parse::<f64>(), b.parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _ => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+fn compare(
+    mut expected: Vec<Vec<String>>,
+    mut actual: Vec<Vec<String>>,
+    order_insensitive: bool,
+    options: &ValidationOptions,
+) -> ValidationReport {
+    if order_insensitive {
+        expected.sort_by(|a, b| compare_rows(a, b));
+        actual.sort_by(|a, b| compare_rows(a, b));
+    }
+    let mut mismatches = vec![];
+    for (row, (expected_row, actual_row)) in expected.iter().zip(actual.iter()).enumerate() {
+        let column_count = expected_row.len().max(actual_row.len());
+        for column in 0..column_count {
+            let expected_value = expected_row.get(column).map(String::as_str).unwrap_or("");
+            let actual_value = actual_row.get(column).map(String::as_str).unwrap_or("");
+            if !values_match(expected_value, actual_value, options) {
+                mismatches.push(Mismatch {
+                    row,
+                    column,
+                    expected: expected_value.to_owned(),
+                    actual: actual_value.to_owned(),
+                });
+            }
+        }
+    }
+    let valid = mismatches.is_empty() && expected.len() == actual.len();
+    mismatches.truncate(MAX_REPORTED_MISMATCHES);
+    ValidationReport {
+        valid,
+        expected_rows: expected.len(),
+        actual_rows: actual.len(),
+        order_insensitive,
+        mismatches,
+        verifiable: is_verifiable(&expected),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(tolerance: f64, abs_tolerance: f64) -> ValidationOptions {
+        ValidationOptions {
+            reference: ReferenceSource::Local,
+            tolerance,
+            abs_tolerance,
+        }
+    }
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(|value| value.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn numbers_match_with_tolerance() {
+        let exact = options(1e-6, 0.);
+        assert!(values_match("1000000", "1000000.5", &exact));
+        assert!(!values_match("1000000", "1000002", &exact));
+        // no absolute floor by default, small values keep the relative tolerance
+        assert!(!values_match("0.05", "0.0501", &exact));
+        assert!(values_match("0.05", "0.05000000001", &exact));
+        assert!(values_match("0.05", "0.0501", &options(1e-6, 0.005)));
+        assert!(values_match("ASIA", " ASIA", &exact));
+        assert!(!values_match("ASIA", "EUROPE", &exact));
+    }
+
+    #[test]
+    fn unordered_rows_are_sorted_by_value() {
+        let expected = rows(&[&["9", "a"], &["10", "b"], &["100", "c"]]);
+        let actual = rows(&[&["100.0", "c"], &["9.0", "a"], &["10.0", "b"]]);
+        let report = compare(expected.clone(), actual.clone(), true, &options(1e-6, 0.));
+        assert!(report.valid, "{:?}", report.mismatches);
+        let report = compare(expected, actual, false, &options(1e-6, 0.));
+        assert!(!report.valid);
+        assert_eq!(report.mismatches[0].row, 0);
+    }
+
+    #[test]
+    fn row_counts_must_match() {
+        let report = compare(
+            rows(&[&["1"], &["2"]]),
+            rows(&[&["1"]]),
+            false,
+            &options(1e-6, 0.),
+        );
+        assert!(!report.valid);
+        assert_eq!((report.expected_rows, report.actual_rows), (2, 1));
+    }
+
+    #[test]
+    fn parse_answers() {
+        assert_eq!(
+            parse_answer("a|b\nx | 1.5|\ny|2\n"),
+            rows(&[&["x", "1.5"], &["y", "2"]])
+        );
+        assert_eq!(parse_answer("revenue\n\n"), rows(&[&[""]]));
+        assert_eq!(parse_answer("a|b\n"), Vec::<Vec<String>>::new());
+    }
+
+    #[test]
+    fn all_queries_have_bundled_answers() {
+        // no row passes the filters of q17 and q19 in the generated dataset
+        let unverifiable = [17, 19];
+        for query in 1..=22 {
+            let answer = parse_answer(bundled_answer(query).unwrap());
+            assert!(!answer.is_empty(), "q{} has no rows", query);
+            assert_eq!(
+                is_verifiable(&answer),
+                !unverifiable.contains(&query),
+                "q{}",
+                query
+            );
+        }
+        assert!(bundled_answer(23).is_err());
+    }
+
+    #[test]
+    fn null_answers_are_not_verifiable() {
+        let expected = parse_answer("avg_yearly\n\n");
+        let report = compare(expected.clone(), expected, true, &options(1e-6, 0.));
+        assert!(report.valid);
+        assert!(!report.verifiable);
+        let expected = parse_answer("revenue\n1.5\n");
+        let report = compare(expected, vec![], true, &options(1e-6, 0.));
+        assert!(report.verifiable);
+    }
+
+    #[test]
+    fn has_order_by_ignores_layout() {
+        assert!(has_order_by("select a from t\n  ORDER\n BY a"));
+        assert!(!has_order_by("select a from t group by a"));
+    }
+}